@@ -0,0 +1,45 @@
+//! Request bodies rendered from a Handlebars template (`--body-template`).
+//!
+//! Unlike the `{placeholder}` templates of `--body`, a body template can use
+//! conditionals, loops and the built-in helpers of Handlebars (`if`, `each`,
+//! `eq`, `len`, ...), given the values of the request: `counter`, `uuid`,
+//! `timestamp`, `random`, `random_int` and the data file `row`.
+
+use handlebars::Handlebars;
+use serde_json::Value;
+
+/// The name the template is registered under
+const TEMPLATE_NAME: &str = "body";
+
+/// Struct to hold a compiled body template
+pub(crate) struct BodyTemplate {
+    registry: Handlebars<'static>,
+}
+
+impl BodyTemplate {
+    /// Load and compile the template at `path`, then render it once with
+    /// `sample` so mistakes surface before the run rather than in every
+    /// request
+    pub(crate) fn load(path: &str, sample: &Value) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read body template {}: {}", path, e))?;
+        let mut registry = Handlebars::new();
+        // Bodies are JSON, XML or text rather than HTML
+        registry.register_escape_fn(handlebars::no_escape);
+        registry
+            .register_template_string(TEMPLATE_NAME, source)
+            .map_err(|e| format!("invalid body template {}: {}", path, e))?;
+        let template = BodyTemplate { registry };
+        template
+            .render(sample)
+            .map_err(|e| format!("invalid body template {}: {}", path, e))?;
+        Ok(template)
+    }
+
+    /// Render the body of a request
+    pub(crate) fn render(&self, data: &Value) -> Result<String, String> {
+        self.registry
+            .render(TEMPLATE_NAME, data)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,105 @@
+//! Quiet reports for runs that repeat the last one (`--summary-only-on-change`).
+//!
+//! The summary file holds the key metrics of the last run that was reported,
+//! per URL. A new run whose metrics all stay within `--change-tolerance` of
+//! them prints one line instead of its report and leaves the file as it is, so
+//! a slow drift adds up until it is reported rather than moving the reference
+//! along with it. A run that changed, or that has no summary yet, prints the
+//! full report and becomes the new reference.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{is_successful, percentile, throughput, LoadTestRun, SuccessCriteria};
+
+/// Struct to hold the metrics of a run that are compared
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunSummary {
+    pub(crate) requests: usize,
+    pub(crate) error_percent: f64, // Failed responses and errors out of every request
+    pub(crate) throughput: f64,    // Requests per second
+    pub(crate) p50: u64,           // Latency percentiles in milliseconds
+    pub(crate) p95: u64,
+    pub(crate) p99: u64,
+}
+
+/// The summaries of the summary file, by URL
+type Summaries = BTreeMap<String, RunSummary>;
+
+impl RunSummary {
+    pub(crate) fn new(run: &LoadTestRun, criteria: &SuccessCriteria) -> Self {
+        let mut times: Vec<u64> = run.responses.iter().map(|d| d.time).collect();
+        times.sort_unstable();
+        let successful = run
+            .responses
+            .iter()
+            .filter(|d| is_successful(d, criteria))
+            .count();
+        let sent = run.responses.len() as u64 + run.request_errors;
+        RunSummary {
+            requests: run.responses.len(),
+            error_percent: (sent - successful as u64) as f64 * 100.0 / sent.max(1) as f64,
+            throughput: throughput(run.responses.len(), run.elapsed),
+            p50: percentile(&times, 0.50),
+            p95: percentile(&times, 0.95),
+            p99: percentile(&times, 0.99),
+        }
+    }
+
+    /// The metrics that moved beyond `tolerance` percent of `previous`. The
+    /// error rate is compared in percentage points, and latencies must also
+    /// move by more than a millisecond, their resolution.
+    pub(crate) fn changes(&self, previous: &RunSummary, tolerance: f64) -> Vec<String> {
+        let beyond =
+            |now: f64, before: f64| (now - before).abs() > before.abs() * tolerance / 100.0;
+        let mut changes = vec![];
+        if beyond(self.requests as f64, previous.requests as f64) {
+            changes.push(format!(
+                "requests {} -> {}",
+                previous.requests, self.requests
+            ));
+        }
+        if (self.error_percent - previous.error_percent).abs() > tolerance {
+            changes.push(format!(
+                "error rate {:.2}% -> {:.2}%",
+                previous.error_percent, self.error_percent
+            ));
+        }
+        if beyond(self.throughput, previous.throughput) {
+            changes.push(format!(
+                "throughput {:.2} -> {:.2} req/s",
+                previous.throughput, self.throughput
+            ));
+        }
+        for (name, now, before) in [
+            ("P50", self.p50, previous.p50),
+            ("P95", self.p95, previous.p95),
+            ("P99", self.p99, previous.p99),
+        ] {
+            if now.abs_diff(before) > 1 && beyond(now as f64, before as f64) {
+                changes.push(format!("{} {} -> {} ms", name, before, now));
+            }
+        }
+        changes
+    }
+}
+
+/// Function to read the summaries of the summary file; a missing file has none
+pub(crate) fn load(path: &str) -> Result<Summaries, String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid summary file {}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Summaries::new()),
+        Err(e) => Err(format!("cannot read summary file {}: {}", path, e)),
+    }
+}
+
+/// Function to store the summary of a URL, keeping those of the other URLs
+pub(crate) fn save(path: &str, url: &str, summary: RunSummary) -> Result<(), String> {
+    let mut summaries = load(path)?;
+    summaries.insert(url.to_string(), summary);
+    let contents = serde_json::to_string_pretty(&summaries)
+        .map_err(|e| format!("cannot write to {}: {}", path, e))?;
+    std::fs::write(path, contents + "\n").map_err(|e| format!("cannot write to {}: {}", path, e))
+}
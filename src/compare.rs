@@ -0,0 +1,295 @@
+//! Side-by-side comparison of two deployments (`--url-a` and `--url-b`).
+//!
+//! Both targets get the same load at the same time, so a difference between
+//! them isn't a difference between two moments. Besides the deltas of every
+//! metric, two tests tell a real difference from noise: the Mann-Whitney U
+//! test of the latencies, which makes no assumption on their distribution,
+//! and a two-proportion z-test of the error rates. Like the latency trend, a
+//! difference is significant when it is more than twice its standard error.
+
+use std::fs::File;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::change::RunSummary;
+use crate::{percentile, write_error, LoadTestRun, OutputFormat, SuccessCriteria};
+
+/// Struct to hold one metric of both targets
+#[derive(Debug, Serialize)]
+pub(crate) struct MetricDelta {
+    metric: &'static str,
+    unit: &'static str,
+    a: f64,
+    b: f64,
+    delta: f64,                 // B minus A
+    delta_percent: Option<f64>, // The delta in percent of A, None when A is 0
+    significant: Option<bool>,  // None for metrics without a test
+}
+
+/// Struct to hold the comparison of the two runs
+#[derive(Debug, Serialize)]
+pub(crate) struct Comparison {
+    url_a: String,
+    url_b: String,
+    latency_z: f64,    // Mann-Whitney U as a z-score, positive when B is slower
+    error_rate_z: f64, // Two-proportion z-score, positive when B fails more
+    metrics: Vec<MetricDelta>,
+}
+
+impl Comparison {
+    pub(crate) fn new(
+        url_a: &str,
+        a: &LoadTestRun,
+        url_b: &str,
+        b: &LoadTestRun,
+        criteria: &SuccessCriteria,
+    ) -> Self {
+        let times = |run: &LoadTestRun| {
+            let mut times: Vec<u64> = run.responses.iter().map(|d| d.time).collect();
+            times.sort_unstable();
+            times
+        };
+        let (times_a, times_b) = (times(a), times(b));
+        let (summary_a, summary_b) = (RunSummary::new(a, criteria), RunSummary::new(b, criteria));
+        let requests = |run: &LoadTestRun| run.responses.len() as u64 + run.request_errors;
+        let latency_z = mann_whitney_z(&times_a, &times_b);
+        let error_rate_z = proportion_z(
+            summary_a.error_percent / 100.0,
+            requests(a),
+            summary_b.error_percent / 100.0,
+            requests(b),
+        );
+        let latency_significant = Some(latency_z.abs() > 2.0);
+        let mean = |values: &[u64]| values.iter().sum::<u64>() as f64 / values.len().max(1) as f64;
+        let ttfbs = |run: &LoadTestRun| run.responses.iter().map(|d| d.ttfb).collect::<Vec<_>>();
+        let metric = |metric, unit, a: f64, b: f64, significant| MetricDelta {
+            metric,
+            unit,
+            a,
+            b,
+            delta: b - a,
+            delta_percent: (a != 0.0).then(|| (b - a) / a * 100.0),
+            significant,
+        };
+        let metrics = vec![
+            metric(
+                "Requests",
+                "",
+                summary_a.requests as f64,
+                summary_b.requests as f64,
+                None,
+            ),
+            metric(
+                "Error Rate",
+                "%",
+                summary_a.error_percent,
+                summary_b.error_percent,
+                Some(error_rate_z.abs() > 2.0),
+            ),
+            metric(
+                "Throughput",
+                "req/s",
+                summary_a.throughput,
+                summary_b.throughput,
+                None,
+            ),
+            metric(
+                "Avg Latency",
+                "ms",
+                mean(&times_a),
+                mean(&times_b),
+                latency_significant,
+            ),
+            metric(
+                "P50 Latency",
+                "ms",
+                summary_a.p50 as f64,
+                summary_b.p50 as f64,
+                latency_significant,
+            ),
+            metric(
+                "P95 Latency",
+                "ms",
+                summary_a.p95 as f64,
+                summary_b.p95 as f64,
+                latency_significant,
+            ),
+            metric(
+                "P99 Latency",
+                "ms",
+                summary_a.p99 as f64,
+                summary_b.p99 as f64,
+                latency_significant,
+            ),
+            metric(
+                "Max Latency",
+                "ms",
+                percentile(&times_a, 1.0) as f64,
+                percentile(&times_b, 1.0) as f64,
+                None,
+            ),
+            metric("Avg TTFB", "ms", mean(&ttfbs(a)), mean(&ttfbs(b)), None),
+        ];
+        Comparison {
+            url_a: url_a.to_string(),
+            url_b: url_b.to_string(),
+            latency_z,
+            error_rate_z,
+            metrics,
+        }
+    }
+
+    /// Print the comparison as a table, with a verdict on the latencies
+    pub(crate) fn display(&self, precision: usize) {
+        println!("\nA/B Comparison");
+        println!("A\t{}", self.url_a);
+        println!("B\t{}", self.url_b);
+        println!("Metric\tA\tB\tDelta (B - A)\tSignificant");
+        for metric in &self.metrics {
+            let unit = if metric.unit.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", metric.unit)
+            };
+            let percent = metric.delta_percent.map_or(String::new(), |percent| {
+                format!(" ({:+.*}%)", precision, percent)
+            });
+            let significant = match metric.significant {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "-",
+            };
+            println!(
+                "{}\t{:.*}{}\t{:.*}{}\t{:+.*}{}{}\t{}",
+                metric.metric,
+                precision,
+                metric.a,
+                unit,
+                precision,
+                metric.b,
+                unit,
+                precision,
+                metric.delta,
+                unit,
+                percent,
+                significant
+            );
+        }
+        let verdict = match (self.latency_z.abs() > 2.0, self.latency_z > 0.0) {
+            (false, _) => "no significant difference",
+            (true, true) => "B is significantly slower",
+            (true, false) => "B is significantly faster",
+        };
+        println!(
+            "Latency\t{} (Mann-Whitney z = {:.2})",
+            verdict, self.latency_z
+        );
+    }
+
+    /// Save the comparison to the results file: the whole of it as JSON, or
+    /// one row per metric as CSV or tab-separated text
+    pub(crate) fn save(&self, format: OutputFormat, output: &str) -> Result<(), String> {
+        let mut file = File::create(output).map_err(write_error(output))?;
+        if format == OutputFormat::Json {
+            serde_json::to_writer_pretty(&mut file, self).map_err(write_error(output))?;
+            return writeln!(file).map_err(write_error(output));
+        }
+        let separator = if format == OutputFormat::Csv {
+            ","
+        } else {
+            "\t"
+        };
+        writeln!(file, "# url_a: {}", self.url_a).map_err(write_error(output))?;
+        writeln!(file, "# url_b: {}", self.url_b).map_err(write_error(output))?;
+        writeln!(file, "# latency_z: {}", self.latency_z).map_err(write_error(output))?;
+        writeln!(file, "# error_rate_z: {}", self.error_rate_z).map_err(write_error(output))?;
+        writeln!(
+            file,
+            "{}",
+            [
+                "metric",
+                "unit",
+                "a",
+                "b",
+                "delta",
+                "delta_percent",
+                "significant"
+            ]
+            .join(separator)
+        )
+        .map_err(write_error(output))?;
+        for metric in &self.metrics {
+            let values = [
+                metric.metric.to_string(),
+                metric.unit.to_string(),
+                metric.a.to_string(),
+                metric.b.to_string(),
+                metric.delta.to_string(),
+                metric
+                    .delta_percent
+                    .map_or(String::new(), |p| p.to_string()),
+                metric.significant.map_or(String::new(), |s| s.to_string()),
+            ];
+            writeln!(file, "{}", values.join(separator)).map_err(write_error(output))?;
+        }
+        Ok(())
+    }
+}
+
+/// Function to compute the Mann-Whitney U statistic of two sorted samples as
+/// a z-score, corrected for ties, positive when `b` tends to be larger
+fn mann_whitney_z(a: &[u64], b: &[u64]) -> f64 {
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    // Merge the sorted samples, giving every run of equal values the
+    // average of its ranks
+    let (mut i, mut j) = (0, 0);
+    let mut rank = 1.0;
+    let mut rank_sum_b = 0.0;
+    let mut ties = 0.0;
+    while i < a.len() || j < b.len() {
+        let value = match (a.get(i), b.get(j)) {
+            (Some(&x), Some(&y)) => x.min(y),
+            (Some(&x), None) => x,
+            (None, Some(&y)) => y,
+            (None, None) => unreachable!(),
+        };
+        let start = (i, j);
+        while a.get(i) == Some(&value) {
+            i += 1;
+        }
+        while b.get(j) == Some(&value) {
+            j += 1;
+        }
+        let (in_a, in_b) = ((i - start.0) as f64, (j - start.1) as f64);
+        let count = in_a + in_b;
+        rank_sum_b += in_b * (rank + (count - 1.0) / 2.0);
+        ties += count * count * count - count;
+        rank += count;
+    }
+    let n = n_a + n_b;
+    let u = rank_sum_b - n_b * (n_b + 1.0) / 2.0;
+    let variance = n_a * n_b / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)).max(1.0));
+    if variance <= 0.0 {
+        return 0.0;
+    }
+    (u - n_a * n_b / 2.0) / variance.sqrt()
+}
+
+/// Function to compute the two-proportion z-score of two failure rates,
+/// positive when `rate_b` is higher
+fn proportion_z(rate_a: f64, n_a: u64, rate_b: f64, n_b: u64) -> f64 {
+    if n_a == 0 || n_b == 0 {
+        return 0.0;
+    }
+    let (n_a, n_b) = (n_a as f64, n_b as f64);
+    let pooled = (rate_a * n_a + rate_b * n_b) / (n_a + n_b);
+    let variance = pooled * (1.0 - pooled) * (1.0 / n_a + 1.0 / n_b);
+    if variance <= 0.0 {
+        return 0.0;
+    }
+    (rate_b - rate_a) / variance.sqrt()
+}
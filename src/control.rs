@@ -0,0 +1,134 @@
+//! The control socket of a running test (`--control-port`).
+//!
+//! It listens on a loopback port only, so just the machine running loadster
+//! can steer it. Clients send one command per line and get one line back,
+//! starting with `ok` or `error:`:
+//!
+//! - `set-concurrency <N>`: run N workers from now on, starting new ones or
+//!   parking the ones above N once their request in flight finishes
+//! - `status`: the current concurrency and the number of responses so far
+//! - `stop`: stop sending requests and report the results
+//!
+//! e.g. `echo "set-concurrency 200" | nc 127.0.0.1 7000`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How often the socket checks whether the run has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A command sent to the control socket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ControlCommand {
+    SetConcurrency(u32),
+    Status,
+    Stop,
+}
+
+impl ControlCommand {
+    /// Parse a line sent to the control socket
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let argument = words.next();
+        if words.next().is_some() {
+            return Err(format!("too many arguments for {}", command));
+        }
+        match (command, argument) {
+            ("set-concurrency", Some(users)) => users
+                .parse()
+                .map(ControlCommand::SetConcurrency)
+                .map_err(|_| format!("invalid concurrency {}", users)),
+            ("set-concurrency", None) => Err("set-concurrency needs a number of users".to_string()),
+            ("status", None) => Ok(ControlCommand::Status),
+            ("stop", None) => Ok(ControlCommand::Stop),
+            ("status" | "stop", Some(_)) => Err(format!("{} takes no arguments", command)),
+            _ => Err(format!(
+                "unknown command {:?}, expected set-concurrency <N>, status or stop",
+                command
+            )),
+        }
+    }
+}
+
+/// Function to bind the control socket to a loopback port; port 0 picks a
+/// free one
+pub(crate) fn bind(port: u16) -> Result<TcpListener, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|e| format!("cannot listen on control port {}: {}", port, e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("cannot listen on control port {}: {}", port, e))?;
+    Ok(listener)
+}
+
+/// Function to answer the clients of the control socket one after the other
+/// until `finished` is set. `handle` carries out a command and returns the
+/// reply to send.
+pub(crate) fn serve(
+    listener: &TcpListener,
+    finished: &AtomicBool,
+    mut handle: impl FnMut(ControlCommand) -> String,
+) {
+    while !finished.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => serve_client(stream, finished, &mut handle),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+/// Function to answer the commands of one client until it disconnects or the
+/// run finishes
+fn serve_client(
+    stream: TcpStream,
+    finished: &AtomicBool,
+    handle: &mut impl FnMut(ControlCommand) -> String,
+) {
+    // Reads time out so a client that stays connected doesn't keep the run
+    // from finishing
+    if stream.set_nonblocking(false).is_err()
+        || stream.set_read_timeout(Some(POLL_INTERVAL)).is_err()
+    {
+        return;
+    }
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while !finished.load(Ordering::SeqCst) {
+        let closed = match reader.read_line(&mut line) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(_) => return,
+        };
+        // A line that timed out halfway is completed by the next read, but
+        // the last line may have no newline at all
+        if !closed && !line.ends_with('\n') {
+            continue;
+        }
+        let reply = match line.trim() {
+            "" => None,
+            command => Some(match ControlCommand::parse(command) {
+                Ok(command) => handle(command),
+                Err(e) => format!("error: {}", e),
+            }),
+        };
+        line.clear();
+        if let Some(reply) = reply {
+            if writeln!(writer, "{}", reply).is_err() {
+                return;
+            }
+        }
+        if closed {
+            return;
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! Distributed load generation.
+//!
+//! An agent (`--agent` or the `agent` subcommand) listens on a TCP socket and runs tests on behalf of a
+//! coordinator (`--coordinator --agents ...`), which sends the same test to
+//! every agent and merges their results into a single run.
+//!
+//! The wire protocol is one JSON document per line. For every test the
+//! coordinator opens a connection and sends an `AgentRequest`; the agent runs
+//! the test and answers with a `Result<AgentResult, String>`, serialized by
+//! serde as `{"Ok": {...}}` or `{"Err": "message"}`, then closes the
+//! connection. There is no authentication, so agents should only listen on
+//! trusted networks.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::scenario::ScenarioStats;
+use crate::{
+    call_api, error_body_list, AbortReason, Cli, CompressionStats, ErrorBody, GoldenMismatch,
+    LoadTestRun, MalformedJson, RequestSamples, ResponseDetails, RunInputs,
+};
+
+/// The port agents listen on when an address does not name one
+const DEFAULT_AGENT_PORT: u16 = 7878;
+
+/// Serde helpers to send a `StatusCode` as its numeric code
+pub(crate) mod status_code {
+    use reqwest::StatusCode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        let code = u16::deserialize(deserializer)?;
+        StatusCode::from_u16(code).map_err(D::Error::custom)
+    }
+}
+
+/// A test sent by the coordinator to an agent
+#[derive(Serialize, Deserialize)]
+struct AgentRequest {
+    url: String,
+    args: Cli,
+}
+
+/// The results of a test run by an agent
+#[derive(Serialize, Deserialize)]
+struct AgentResult {
+    started_at_ms: u64, // Wall-clock start of the run on the agent, in milliseconds since UNIX_EPOCH
+    elapsed_ms: u64,
+    responses: Vec<ResponseDetails>,
+    aborted: Option<AbortReason>,
+    compression: Option<CompressionStats>,
+    connections_opened: u64,
+    prewarmed: Option<u64>,
+    retries: u64,
+    retry_classes: BTreeMap<String, u64>,
+    breaker_trips: u64,
+    request_errors: u64,
+    #[serde(default)]
+    resource_exhausted: u64,
+    abandoned: u64,
+    timeouts: Vec<u64>,
+    requests: u64,
+    max_duration_reached: bool,
+    scenario: Option<ScenarioStats>,
+    lock_wait_ns: Option<u64>,
+    error_bodies: Vec<ErrorBody>,
+    golden_mismatch: Option<GoldenMismatch>,
+    malformed_json: Vec<MalformedJson>,
+    requests_per_connection: Vec<u64>,
+}
+
+/// Command line arguments of the `agent` subcommand
+#[derive(Args, Clone)]
+pub(crate) struct AgentArgs {
+    /// The address to listen on, e.g. :7878 or 10.0.0.5:7878 (default: :7878)
+    #[clap(
+        long,
+        default_value = ":7878",
+        help = "The address to listen on, e.g. :7878 or 10.0.0.5:7878 (default: :7878)"
+    )]
+    pub(crate) listen: String,
+}
+
+/// Function to run an agent: accept tests from coordinators, one at a time,
+/// until the process is stopped
+pub(crate) fn run_agent(listen: &str) -> Result<(), String> {
+    let address = with_default_host(listen);
+    let listener =
+        TcpListener::bind(&address).map_err(|e| format!("cannot listen on {}: {}", address, e))?;
+    println!("Agent listening on {}", address);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Agent connection failed: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = serve_test(stream) {
+            eprintln!("Agent test failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Function to run the test sent over one coordinator connection and send back
+/// the results
+fn serve_test(stream: TcpStream) -> Result<(), String> {
+    let peer = stream
+        .peer_addr()
+        .map(|peer| peer.to_string())
+        .unwrap_or_default();
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|e| format!("cannot read test from {}: {}", peer, e))?;
+
+    let result = match serde_json::from_str::<AgentRequest>(&line) {
+        Ok(request) => {
+            println!("Running test against {} for {}", request.url, peer);
+            run_request(request)
+        }
+        Err(e) => Err(format!("invalid test: {}", e)),
+    };
+
+    let mut stream = stream;
+    serde_json::to_writer(&mut stream, &result)
+        .map_err(|e| format!("cannot send results to {}: {}", peer, e))?;
+    stream
+        .write_all(b"\n")
+        .map_err(|e| format!("cannot send results to {}: {}", peer, e))
+}
+
+/// Function to run a test received from a coordinator
+fn run_request(request: AgentRequest) -> Result<AgentResult, String> {
+    let inputs = RunInputs::load(&request.args)?;
+    let run = call_api(request.url, &request.args, &inputs).map_err(|e| e.to_string())?;
+    Ok(AgentResult {
+        started_at_ms: epoch_millis(run.started_at),
+        elapsed_ms: run.elapsed.as_millis() as u64,
+        responses: run.responses,
+        aborted: run.aborted,
+        compression: run.compression,
+        connections_opened: run.connections_opened,
+        prewarmed: run.prewarmed,
+        retries: run.retries,
+        retry_classes: run.retry_classes,
+        breaker_trips: run.breaker_trips,
+        request_errors: run.request_errors,
+        resource_exhausted: run.resource_exhausted,
+        abandoned: run.abandoned,
+        timeouts: run.timeouts,
+        requests: run.requests,
+        max_duration_reached: run.max_duration_reached,
+        scenario: run.scenario,
+        lock_wait_ns: run.lock_wait_ns,
+        error_bodies: run.error_bodies,
+        golden_mismatch: run.golden_mismatch,
+        malformed_json: run.malformed_json,
+        requests_per_connection: run.requests_per_connection,
+    })
+}
+
+/// Function to run a test on every agent of --agents at the same time and
+/// merge their results into one run. Each agent runs the full test, so the
+/// total load is the configured load times the number of agents.
+pub(crate) fn run_on_agents(url: &str, args: &Cli) -> Result<LoadTestRun, String> {
+    let handles: Vec<_> = args
+        .agents
+        .iter()
+        .map(|agent| {
+            let address = with_default_port(agent);
+            let mut spec = args.clone();
+            spec.coordinator = false;
+            spec.agents.clear();
+            let request = serde_json::to_string(&AgentRequest {
+                url: url.to_string(),
+                args: spec,
+            })
+            .expect("tests always serialize");
+            thread::Builder::new()
+                .name(format!("loadster-agent-{}", address))
+                .spawn(move || {
+                    send_test(&address, &request).map_err(|e| format!("agent {}: {}", address, e))
+                })
+                .expect("the system can start another thread")
+        })
+        .collect();
+
+    let mut results = vec![];
+    for handle in handles {
+        results.push(handle.join().unwrap()?);
+    }
+
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    Ok(merge_results(
+        results,
+        RequestSamples::new(args.sample_size, StdRng::from_rng(&mut rng)),
+    ))
+}
+
+/// Function to send a test to an agent and wait for its results
+fn send_test(address: &str, request: &str) -> Result<AgentResult, String> {
+    let mut stream = TcpStream::connect(address).map_err(|e| format!("cannot connect: {}", e))?;
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.write_all(b"\n"))
+        .map_err(|e| format!("cannot send test: {}", e))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(|e| format!("cannot read results: {}", e))?;
+    let result: Result<AgentResult, String> =
+        serde_json::from_str(&line).map_err(|e| format!("invalid results: {}", e))?;
+    result
+}
+
+/// Function to merge the results of several agents into one run. Request
+/// offsets are shifted so they are relative to the earliest agent start.
+fn merge_results(results: Vec<AgentResult>, mut samples: RequestSamples) -> LoadTestRun {
+    let base_ms = results
+        .iter()
+        .map(|r| r.started_at_ms)
+        .min()
+        .unwrap_or_default();
+    let end_ms = results
+        .iter()
+        .map(|r| r.started_at_ms + r.elapsed_ms)
+        .max()
+        .unwrap_or(base_ms);
+
+    let mut responses = vec![];
+    let mut aborted = None;
+    let mut compression: Option<CompressionStats> = None;
+    let mut connections_opened = 0;
+    let mut prewarmed: Option<u64> = None;
+    let mut retries = 0;
+    let mut retry_classes = BTreeMap::new();
+    let mut breaker_trips = 0;
+    let mut request_errors = 0;
+    let mut resource_exhausted = 0;
+    let mut abandoned = 0;
+    let mut timeouts = vec![];
+    let mut requests = 0;
+    let mut max_duration_reached = false;
+    let mut scenario: Option<ScenarioStats> = None;
+    let mut lock_wait_ns: Option<u64> = None;
+    let mut error_bodies = HashMap::new();
+    let mut golden_mismatch = None;
+    let mut malformed_json = vec![];
+    let mut requests_per_connection = vec![];
+    for result in results {
+        let shift = result.started_at_ms - base_ms;
+        for mut detail in result.responses {
+            detail.started_at_offset_ms += shift;
+            detail.started_at_offset_ns += shift * 1_000_000;
+            detail.ended_at_offset_ns += shift * 1_000_000;
+            samples.record(&detail);
+            responses.push(detail);
+        }
+        aborted = aborted.or(result.aborted);
+        connections_opened += result.connections_opened;
+        prewarmed = match (prewarmed, result.prewarmed) {
+            (Some(total), Some(count)) => Some(total + count),
+            (total, count) => total.or(count),
+        };
+        retries += result.retries;
+        for (class, count) in result.retry_classes {
+            *retry_classes.entry(class).or_default() += count;
+        }
+        breaker_trips += result.breaker_trips;
+        request_errors += result.request_errors;
+        resource_exhausted += result.resource_exhausted;
+        abandoned += result.abandoned;
+        golden_mismatch = golden_mismatch.or(result.golden_mismatch);
+        malformed_json.extend(result.malformed_json);
+        requests_per_connection.extend(result.requests_per_connection);
+        timeouts.extend(result.timeouts);
+        requests += result.requests;
+        max_duration_reached |= result.max_duration_reached;
+        for body in result.error_bodies {
+            *error_bodies.entry((body.status, body.body)).or_default() += body.count;
+        }
+        lock_wait_ns = match (lock_wait_ns, result.lock_wait_ns) {
+            (Some(total), Some(wait)) => Some(total + wait),
+            (total, wait) => total.or(wait),
+        };
+        scenario = match (scenario, result.scenario) {
+            (Some(mut total), Some(stats)) => {
+                for (total, abandoned) in total.abandoned.iter_mut().zip(stats.abandoned) {
+                    *total += abandoned;
+                }
+                Some(total)
+            }
+            (total, stats) => total.or(stats),
+        };
+        compression = match (compression, result.compression) {
+            (Some(mut total), Some(stats)) => {
+                total.original_bytes += stats.original_bytes;
+                total.compressed_bytes += stats.compressed_bytes;
+                Some(total)
+            }
+            (total, stats) => total.or(stats),
+        };
+    }
+    responses.sort_by_key(|detail| detail.started_at_offset_ms);
+    malformed_json.truncate(MalformedJson::MAX_SAMPLES);
+
+    LoadTestRun {
+        started_at: UNIX_EPOCH + Duration::from_millis(base_ms),
+        elapsed: Duration::from_millis(end_ms - base_ms),
+        responses,
+        aborted,
+        samples,
+        compression,
+        connections_opened,
+        prewarmed,
+        retries,
+        retry_classes,
+        breaker_trips,
+        request_errors,
+        resource_exhausted,
+        abandoned,
+        timeouts,
+        requests,
+        max_duration_reached,
+        schedule_lag: vec![],
+        think_times: vec![],
+        scenario,
+        lock_wait_ns,
+        error_bodies: error_body_list(error_bodies),
+        golden_mismatch,
+        malformed_json,
+        requests_per_connection,
+    }
+}
+
+/// Function to turn a wall-clock time into milliseconds since UNIX_EPOCH
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Function to complete a listen address like `:7878` to listen on all
+/// interfaces
+pub(crate) fn with_default_host(address: &str) -> String {
+    match address.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => address.to_string(),
+    }
+}
+
+/// Function to complete an agent address without a port with the default port
+fn with_default_port(address: &str) -> String {
+    if address
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_AGENT_PORT)
+    }
+}
@@ -0,0 +1,255 @@
+//! Benchmarks of the `Expect: 100-continue` handshake (`--expect-continue`).
+//!
+//! A client that sends `Expect: 100-continue` writes the head of its request
+//! and holds back the body until the server answers `100 Continue`, so a
+//! server that is going to reject the upload, e.g. with 401 or 413, can say so
+//! before the body crosses the network. reqwest sends the body right away and
+//! never reports interim responses, so this mode speaks HTTP/1.1 over plain
+//! TCP sockets itself, like `--pipeline`. Every request waits up to
+//! `CONTINUE_WAIT` for the interim response; without one, the body is sent
+//! anyway, as RFC 9110 asks of clients.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::pipeline::{open, read_body, read_head, read_response, request_head, resolve};
+use crate::{percentile, throughput};
+
+/// How long a request waits for `100 Continue` before it sends its body
+/// regardless
+const CONTINUE_WAIT: Duration = Duration::from_secs(1);
+
+/// Struct to hold what to send and how
+pub(crate) struct ExpectContinue<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) method: &'a str,
+    pub(crate) headers: &'a [String],
+    pub(crate) body: &'a str,
+    pub(crate) connections: u32,
+    pub(crate) requests: u64,
+    pub(crate) timeout: Duration,
+    pub(crate) precision: usize,
+}
+
+/// Struct to hold what a connection measured
+#[derive(Default)]
+struct ConnectionResult {
+    continue_times: Vec<u64>, // From writing the head to reading 100 Continue, in microseconds
+    latencies: Vec<u64>, // From writing the head to reading the final response, in microseconds
+    codes: BTreeMap<u16, u64>, // Final status codes
+    rejected: u64,       // Requests answered with a final status before their body was sent
+    unacknowledged: u64, // Requests that got no 100 Continue within CONTINUE_WAIT
+    errors: u64,         // Requests whose connection failed before the final response
+}
+
+/// How a request ended
+enum Outcome {
+    Continued(Duration, u16, bool), // Time to 100 Continue, final status and keep-alive
+    Rejected(u16),                  // Final status before the body; the connection is closed
+    Unacknowledged(u16, bool),      // Final status and keep-alive, the body sent without a 100
+}
+
+/// Function to send the requests over every connection at once and print the
+/// report
+pub(crate) fn run(expect: &ExpectContinue) -> Result<(), String> {
+    let (url, address) = resolve(expect.url, "--expect-continue")?;
+    let mut head = request_head(expect.method, &url, expect.headers);
+    head.push_str(&format!(
+        "Content-Length: {}\r\nExpect: 100-continue\r\n\r\n",
+        expect.body.len()
+    ));
+
+    println!(
+        "Sending {} requests with Expect: 100-continue over {} connections",
+        expect.requests, expect.connections
+    );
+    let next = AtomicU64::new(0);
+    let start = Instant::now();
+    let connect = || TcpStream::connect_timeout(&address, expect.timeout);
+    let connections: Vec<ConnectionResult> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..expect.connections)
+            .map(|i| {
+                thread::Builder::new()
+                    .name(format!("loadster-expect-{}", i))
+                    .spawn_scoped(scope, || {
+                        run_connection(expect, head.as_bytes(), &next, &connect)
+                    })
+                    .expect("the system can start another thread")
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    display_report(expect, &connections, start.elapsed());
+    Ok(())
+}
+
+/// Function to send requests over one connection, one at a time, until all
+/// requests are sent, reconnecting whenever the connection is closed
+fn run_connection(
+    expect: &ExpectContinue,
+    head: &[u8],
+    next: &AtomicU64,
+    connect: &impl Fn() -> io::Result<TcpStream>,
+) -> ConnectionResult {
+    let mut result = ConnectionResult::default();
+    let mut connection: Option<(TcpStream, BufReader<TcpStream>)> = None;
+    while next.fetch_add(1, Ordering::SeqCst) < expect.requests {
+        if connection.is_none() {
+            connection = open(connect, expect.timeout).ok();
+        }
+        let Some((writer, reader)) = &mut connection else {
+            result.errors += 1;
+            continue;
+        };
+        let start = Instant::now();
+        let (status, keep_alive) = match send(expect, head, writer, reader) {
+            Ok(Outcome::Continued(waited, status, keep_alive)) => {
+                result.continue_times.push(waited.as_micros() as u64);
+                (status, keep_alive)
+            }
+            Ok(Outcome::Rejected(status)) => {
+                result.rejected += 1;
+                (status, false)
+            }
+            Ok(Outcome::Unacknowledged(status, keep_alive)) => {
+                result.unacknowledged += 1;
+                (status, keep_alive)
+            }
+            Err(_) => {
+                result.errors += 1;
+                connection = None;
+                continue;
+            }
+        };
+        result.latencies.push(start.elapsed().as_micros() as u64);
+        *result.codes.entry(status).or_default() += 1;
+        if !keep_alive {
+            connection = None;
+        }
+    }
+    result
+}
+
+/// Function to send one request: its head, then its body once the server
+/// answers 100 Continue or doesn't answer within CONTINUE_WAIT
+fn send(
+    expect: &ExpectContinue,
+    head: &[u8],
+    writer: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+) -> io::Result<Outcome> {
+    let start = Instant::now();
+    writer.write_all(head)?;
+    writer.set_read_timeout(Some(CONTINUE_WAIT.min(expect.timeout)))?;
+    let interim = read_head(reader);
+    writer.set_read_timeout(Some(expect.timeout))?;
+    let waited = start.elapsed();
+    let continued = match interim {
+        Ok(response) if response.status == 100 => true,
+        Ok(response) if response.status >= 200 => {
+            // The body is never sent; the server can't tell whether it will
+            // be, so the connection isn't reused
+            read_body(reader, &response, false)?;
+            return Ok(Outcome::Rejected(response.status));
+        }
+        // Other interim responses don't answer the expectation
+        Ok(_) => false,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            false
+        }
+        Err(e) => return Err(e),
+    };
+    writer.write_all(expect.body.as_bytes())?;
+    let (status, keep_alive) = read_response(reader, false)?;
+    Ok(if continued {
+        Outcome::Continued(waited, status, keep_alive)
+    } else {
+        Outcome::Unacknowledged(status, keep_alive)
+    })
+}
+
+/// Function to display how the server answered the expectation, the time to
+/// 100 Continue and the total latency
+fn display_report(expect: &ExpectContinue, connections: &[ConnectionResult], elapsed: Duration) {
+    let precision = expect.precision;
+    let sum = |count: fn(&ConnectionResult) -> u64| connections.iter().map(count).sum::<u64>();
+    let sorted = |times: fn(&ConnectionResult) -> &Vec<u64>| {
+        let mut sorted: Vec<u64> = connections
+            .iter()
+            .flat_map(|c| times(c).iter().copied())
+            .collect();
+        sorted.sort_unstable();
+        sorted
+    };
+    let continue_times = sorted(|c| &c.continue_times);
+    let latencies = sorted(|c| &c.latencies);
+    let rejected = sum(|c| c.rejected);
+
+    println!(
+        "\nExpect: 100-continue Report ({} connections)",
+        expect.connections
+    );
+    println!("Target URL\t{}", expect.url);
+    println!("Requests\t{}", expect.requests);
+    println!("Continued\t{}", continue_times.len());
+    println!("Rejected Before the Body\t{}", rejected);
+    println!(
+        "No 100 Continue Within {} ms\t{}",
+        CONTINUE_WAIT.as_millis(),
+        sum(|c| c.unacknowledged)
+    );
+    println!("Errors\t{}", sum(|c| c.errors));
+    println!(
+        "Body Bytes Not Sent\t{}",
+        rejected * expect.body.len() as u64
+    );
+    println!(
+        "Throughput\t{:.*} req/s",
+        precision,
+        throughput(latencies.len(), elapsed)
+    );
+
+    let ms = |micros: u64| micros as f64 / 1000.0;
+    println!("\nMetric\tAvg\tP50\tP95\tP99");
+    for (name, times) in [
+        ("100 Continue Time", &continue_times),
+        ("Total Latency", &latencies),
+    ] {
+        println!(
+            "{}\t{:.*} ms\t{:.*} ms\t{:.*} ms\t{:.*} ms",
+            name,
+            precision,
+            ms(times.iter().sum::<u64>()) / times.len().max(1) as f64,
+            precision,
+            ms(percentile(times, 0.50)),
+            precision,
+            ms(percentile(times, 0.95)),
+            precision,
+            ms(percentile(times, 0.99))
+        );
+    }
+
+    let mut codes: BTreeMap<u16, u64> = BTreeMap::new();
+    for connection in connections {
+        for (code, count) in &connection.codes {
+            *codes.entry(*code).or_default() += count;
+        }
+    }
+    println!("\nResponse Codes");
+    println!("Code\tCount");
+    for (code, count) in codes {
+        println!("{}\t{}", code, count);
+    }
+}
@@ -0,0 +1,116 @@
+//! Running out of file descriptors (`--raise-fd-limit`).
+//!
+//! Every connection holds a file descriptor, so at high concurrency a run can
+//! hit the limit of the process (EMFILE) or of the whole system (ENFILE).
+//! reqwest reports that as an ordinary connection error; the io::Error below
+//! its sources tells it apart, so those requests count as `exhausted` rather
+//! than `err` and the user gets a hint instead of a wall of failures.
+
+use std::error::Error;
+
+/// Function to tell whether an error, or any error it was caused by, is the
+/// process or the system running out of file descriptors
+pub(crate) fn is_exhausted(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(io) = error.downcast_ref::<std::io::Error>() {
+            if io.raw_os_error().is_some_and(is_exhaustion_code) {
+                return true;
+            }
+            // The source of a wrapping io::Error skips the error it wraps
+            if io.get_ref().is_some_and(|inner| is_exhausted(inner)) {
+                return true;
+            }
+        }
+        source = error.source();
+    }
+    false
+}
+
+#[cfg(unix)]
+fn is_exhaustion_code(code: i32) -> bool {
+    code == libc::EMFILE || code == libc::ENFILE
+}
+
+#[cfg(windows)]
+fn is_exhaustion_code(code: i32) -> bool {
+    const WSAEMFILE: i32 = 10024;
+    code == WSAEMFILE
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_exhaustion_code(_code: i32) -> bool {
+    false
+}
+
+/// Function to read the soft and hard limit on open file descriptors
+#[cfg(unix)]
+pub(crate) fn limits() -> Option<(u64, u64)> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to the struct it is given
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    Some((limit.rlim_cur, limit.rlim_max))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn limits() -> Option<(u64, u64)> {
+    None
+}
+
+/// Function to raise the soft limit on open file descriptors to the hard
+/// limit, returning the soft limit before and after
+#[cfg(unix)]
+pub(crate) fn raise() -> Result<(u64, u64), String> {
+    let (soft, hard) = limits().ok_or_else(|| {
+        format!(
+            "cannot read the file descriptor limit: {}",
+            std::io::Error::last_os_error()
+        )
+    })?;
+    // macOS takes no more than OPEN_MAX, however high the hard limit
+    let target = if cfg!(target_os = "macos") {
+        hard.min(10240)
+    } else {
+        hard
+    };
+    if target <= soft {
+        return Ok((soft, soft));
+    }
+    let limit = libc::rlimit {
+        rlim_cur: target,
+        rlim_max: hard,
+    };
+    // SAFETY: setrlimit only reads the struct it is given
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        return Err(format!(
+            "cannot raise the file descriptor limit from {} to {}: {}",
+            soft,
+            target,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok((soft, target))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn raise() -> Result<(u64, u64), String> {
+    Err("--raise-fd-limit is only supported on Unix".to_string())
+}
+
+/// Function to word the hint printed the first time a request runs out of
+/// file descriptors
+pub(crate) fn hint(users: i32) -> String {
+    let limit = match limits() {
+        Some((soft, _)) => format!(" (the limit is {})", soft),
+        None => String::new(),
+    };
+    format!(
+        "Hint: the system ran out of file descriptors{}, which every connection needs; raise the limit with `ulimit -n` or --raise-fd-limit, or lower --users ({})",
+        limit, users
+    )
+}
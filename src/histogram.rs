@@ -0,0 +1,171 @@
+//! The latency histogram (`--histogram` and `--histogram-scale`).
+//!
+//! Linear buckets are all the same width, which suits latencies of one order
+//! of magnitude. Once they span several, from sub-millisecond cache hits to
+//! timeouts of seconds, nearly every response lands in the first bucket, so
+//! the log scale doubles the width of every bucket instead: under 1 ms, 1-2,
+//! 2-4, 4-8 ms and so on. The terminal chart and the JSON output file both
+//! use the buckets of `buckets`, so they always agree.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The number of buckets of the linear scale, at most
+const LINEAR_BUCKETS: u64 = 20;
+
+/// Width of the bar of the largest bucket
+const BAR_WIDTH: u64 = 40;
+
+/// Supported scales of the latency histogram
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HistogramScale {
+    Linear, // Buckets of the same width
+    Log,    // Buckets of twice the width of the one before
+}
+
+/// Struct to hold one bucket of the histogram
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Bucket {
+    pub(crate) lower_ms: u64, // Inclusive
+    pub(crate) upper_ms: u64, // Exclusive
+    pub(crate) count: u64,
+}
+
+/// Function to spread sorted latencies in milliseconds over the buckets of a
+/// scale, from the bucket of the fastest to that of the slowest. Buckets in
+/// between are kept when empty, so gaps in the distribution show.
+pub(crate) fn buckets(sorted_times: &[u64], scale: HistogramScale) -> Vec<Bucket> {
+    let (Some(&min), Some(&max)) = (sorted_times.first(), sorted_times.last()) else {
+        return vec![];
+    };
+    let mut bounds = vec![];
+    match scale {
+        HistogramScale::Linear => {
+            let width = (max - min + 1).div_ceil(LINEAR_BUCKETS);
+            let mut lower = min - min % width;
+            while lower <= max {
+                bounds.push((lower, lower + width));
+                lower += width;
+            }
+        }
+        HistogramScale::Log => {
+            if min == 0 {
+                bounds.push((0, 1));
+            }
+            // The power of two at or below the fastest latency above 0
+            let mut lower = 1u64 << min.max(1).ilog2();
+            while lower <= max {
+                bounds.push((lower, lower.saturating_mul(2)));
+                lower = lower.saturating_mul(2);
+            }
+        }
+    }
+    let mut buckets: Vec<Bucket> = bounds
+        .into_iter()
+        .map(|(lower_ms, upper_ms)| Bucket {
+            lower_ms,
+            upper_ms,
+            count: 0,
+        })
+        .collect();
+    let mut index = 0;
+    for &time in sorted_times {
+        while time >= buckets[index].upper_ms {
+            index += 1;
+        }
+        buckets[index].count += 1;
+    }
+    buckets
+}
+
+/// Function to print the buckets as a chart with a bar per bucket
+pub(crate) fn display(buckets: &[Bucket], scale: HistogramScale, precision: usize) {
+    let total: u64 = buckets.iter().map(|bucket| bucket.count).sum();
+    let most = buckets
+        .iter()
+        .map(|bucket| bucket.count)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let scale = match scale {
+        HistogramScale::Linear => "linear",
+        HistogramScale::Log => "log",
+    };
+    println!("\nLatency Histogram ({} scale)", scale);
+    println!("Latency (ms)\tRequests\tShare");
+    for bucket in buckets {
+        println!(
+            "{}-{}\t{}\t{:.*}%\t{}",
+            bucket.lower_ms,
+            bucket.upper_ms,
+            bucket.count,
+            precision,
+            bucket.count as f64 * 100.0 / total.max(1) as f64,
+            "#".repeat((bucket.count * BAR_WIDTH).div_ceil(most) as usize)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(buckets: &[Bucket]) -> Vec<(u64, u64, u64)> {
+        buckets
+            .iter()
+            .map(|bucket| (bucket.lower_ms, bucket.upper_ms, bucket.count))
+            .collect()
+    }
+
+    #[test]
+    fn no_latencies_give_no_buckets() {
+        assert!(buckets(&[], HistogramScale::Linear).is_empty());
+        assert!(buckets(&[], HistogramScale::Log).is_empty());
+    }
+
+    #[test]
+    fn a_single_latency_gives_one_bucket() {
+        assert_eq!(bounds(&buckets(&[7], HistogramScale::Linear)), [(7, 8, 1)]);
+        assert_eq!(bounds(&buckets(&[7], HistogramScale::Log)), [(4, 8, 1)]);
+        assert_eq!(bounds(&buckets(&[0], HistogramScale::Log)), [(0, 1, 1)]);
+    }
+
+    #[test]
+    fn linear_buckets_cover_the_range_in_at_most_twenty() {
+        let times: Vec<u64> = (0..=100).collect();
+        let buckets = buckets(&times, HistogramScale::Linear);
+        assert!(buckets.len() <= LINEAR_BUCKETS as usize);
+        assert_eq!(buckets.first().unwrap().lower_ms, 0);
+        assert!(buckets.last().unwrap().upper_ms > 100);
+        assert!(buckets
+            .iter()
+            .all(|bucket| bucket.upper_ms - bucket.lower_ms == 6));
+        assert_eq!(buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 101);
+    }
+
+    #[test]
+    fn linear_buckets_keep_empty_gaps() {
+        let buckets = buckets(&[0, 39], HistogramScale::Linear);
+        assert_eq!(buckets.len(), 20);
+        assert_eq!(buckets[0].count, 1);
+        assert_eq!(buckets[19].count, 1);
+        assert!(buckets[1..19].iter().all(|bucket| bucket.count == 0));
+    }
+
+    #[test]
+    fn log_buckets_start_below_one_millisecond_and_double() {
+        assert_eq!(
+            bounds(&buckets(&[0, 1, 3, 4, 9], HistogramScale::Log)),
+            [(0, 1, 1), (1, 2, 1), (2, 4, 1), (4, 8, 1), (8, 16, 1)]
+        );
+    }
+
+    #[test]
+    fn log_buckets_put_powers_of_two_in_the_bucket_they_open() {
+        assert_eq!(
+            bounds(&buckets(&[8, 15, 16], HistogramScale::Log)),
+            [(8, 16, 2), (16, 32, 1)]
+        );
+    }
+}
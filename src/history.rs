@@ -0,0 +1,184 @@
+//! A SQLite database of past runs (`--db` and the `history` subcommand).
+//!
+//! Every run appends a row with its key metrics to the `runs` table, and with
+//! `--db-responses` its responses to the `responses` table, so trends over
+//! time are a query away. The tables are created the first time a database is
+//! used; rows are never updated or deleted by loadster.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::Args;
+use reqwest::Method;
+use rusqlite::{params, Connection};
+
+use crate::change::RunSummary;
+use crate::{Cli, LoadTestRun, SuccessCriteria};
+
+/// The tables of a results database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL, -- Seconds since UNIX_EPOCH
+    url TEXT NOT NULL,
+    method TEXT NOT NULL,
+    users INTEGER NOT NULL,
+    requests INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    throughput REAL NOT NULL, -- Requests per second
+    p50_ms INTEGER NOT NULL,
+    p95_ms INTEGER NOT NULL,
+    p99_ms INTEGER NOT NULL,
+    error_percent REAL NOT NULL, -- Failed responses and errors out of every request
+    loadster_version TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_by_url ON runs (url, started_at);
+CREATE TABLE IF NOT EXISTS responses (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    started_at_offset_ms INTEGER NOT NULL, -- Since the start of the run
+    status INTEGER NOT NULL,
+    time_ms INTEGER NOT NULL,
+    ttfb_ms INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    url TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS responses_by_run ON responses (run_id);
+";
+
+/// Command line arguments of the `history` subcommand
+#[derive(Args, Clone)]
+pub(crate) struct HistoryArgs {
+    /// The results database to read, as written with --db
+    #[clap(long, help = "The results database to read, as written with --db")]
+    db: String,
+
+    /// Only list the runs of this URL
+    #[clap(long, help = "Only list the runs of this URL")]
+    url: Option<String>,
+
+    /// The number of most recent runs to list (default: 20)
+    #[clap(
+        long,
+        default_value_t = 20,
+        help = "The number of most recent runs to list (default: 20)"
+    )]
+    limit: u32,
+}
+
+/// Function to open a results database, creating its tables if needed
+fn open(path: &str) -> Result<Connection, String> {
+    let connection = Connection::open(path)
+        .map_err(|e| format!("cannot open results database {}: {}", path, e))?;
+    connection.execute_batch(SCHEMA).map_err(|e| {
+        format!(
+            "cannot create the tables of results database {}: {}",
+            path, e
+        )
+    })?;
+    Ok(connection)
+}
+
+/// Function to append a run to the results database, and its responses with
+/// --db-responses, in a single transaction
+pub(crate) fn record(run: &LoadTestRun, url: &str, args: &Cli, path: &str) -> Result<(), String> {
+    let write_error =
+        |e: rusqlite::Error| format!("cannot write to results database {}: {}", path, e);
+    let summary = RunSummary::new(run, &SuccessCriteria::new(args));
+    let method: Method = args.method.clone().into();
+    let started_at = run
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut connection = open(path)?;
+    let transaction = connection.transaction().map_err(write_error)?;
+    transaction
+        .execute(
+            "INSERT INTO runs (started_at, url, method, users, requests, duration_ms, throughput,
+                p50_ms, p95_ms, p99_ms, error_percent, loadster_version)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                started_at.as_secs() as i64,
+                url,
+                method.as_str(),
+                args.users,
+                summary.requests as i64,
+                run.elapsed.as_millis() as i64,
+                summary.throughput,
+                summary.p50 as i64,
+                summary.p95 as i64,
+                summary.p99 as i64,
+                summary.error_percent,
+                env!("CARGO_PKG_VERSION"),
+            ],
+        )
+        .map_err(write_error)?;
+    if args.db_responses {
+        let run_id = transaction.last_insert_rowid();
+        let mut insert = transaction
+            .prepare(
+                "INSERT INTO responses (run_id, started_at_offset_ms, status, time_ms, ttfb_ms, bytes, url)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .map_err(write_error)?;
+        for detail in &run.responses {
+            insert
+                .execute(params![
+                    run_id,
+                    detail.started_at_offset_ms as i64,
+                    detail.status.as_u16(),
+                    detail.time as i64,
+                    detail.ttfb as i64,
+                    detail.bytes as i64,
+                    detail.url,
+                ])
+                .map_err(write_error)?;
+        }
+    }
+    transaction.commit().map_err(write_error)
+}
+
+/// Function to carry out the `history` subcommand: list the most recent runs,
+/// newest first
+pub(crate) fn run(args: &HistoryArgs) -> Result<(), String> {
+    let read_error =
+        |e: rusqlite::Error| format!("cannot read results database {}: {}", args.db, e);
+    if !std::path::Path::new(&args.db).exists() {
+        return Err(format!("no results database {}", args.db));
+    }
+    let connection = open(&args.db)?;
+    let mut query = connection
+        .prepare(
+            "SELECT id, started_at, url, users, requests, throughput, p50_ms, p95_ms, p99_ms, error_percent
+             FROM runs WHERE ?1 IS NULL OR url = ?1
+             ORDER BY started_at DESC, id DESC LIMIT ?2",
+        )
+        .map_err(read_error)?;
+    let rows = query
+        .query_map(params![args.url, args.limit], |row| {
+            let started_at: i64 = row.get(1)?;
+            Ok(format!(
+                "{}\t{}\t{}\t{}\t{}\t{:.2} req/s\t{} ms\t{} ms\t{} ms\t{:.2}%",
+                row.get::<_, i64>(0)?,
+                httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(started_at.max(0) as u64)),
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, f64>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, i64>(7)?,
+                row.get::<_, i64>(8)?,
+                row.get::<_, f64>(9)?,
+            ))
+        })
+        .map_err(read_error)?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(read_error)?;
+    if rows.is_empty() {
+        println!("No runs in {}", args.db);
+        return Ok(());
+    }
+    println!("Run\tStarted At\tURL\tUsers\tRequests\tThroughput\tP50\tP95\tP99\tErrors");
+    for row in rows {
+        println!("{}", row);
+    }
+    Ok(())
+}
@@ -1,29 +1,99 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
 use std::{
     str::FromStr,
     thread,
-    time::{Instant, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::{Parser, ValueEnum};
-use reqwest::header::USER_AGENT;
-use reqwest::{blocking::Client, Method, StatusCode};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use flate2::write::GzEncoder;
+use hdrhistogram::serialization::interval_log::IntervalLogWriterBuilder;
+use hdrhistogram::serialization::V2DeflateSerializer;
+use hdrhistogram::Histogram;
+use hyper_util::client::legacy::connect::HttpInfo;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reqwest::header::{
+    AUTHORIZATION, CONNECTION, CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, RANGE, RETRY_AFTER,
+    USER_AGENT,
+};
+use reqwest::{
+    blocking::{Client, Request, RequestBuilder, Response},
+    Method, StatusCode, Version,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tower::util::MapRequestLayer;
+
+mod body_template;
+mod change;
+mod compare;
+mod control;
+mod distributed;
+mod expect_continue;
+mod fdlimit;
+mod histogram;
+mod history;
+mod mock;
+#[cfg(feature = "otel")]
+mod otel;
+mod pipeline;
+mod process;
+mod profiles;
+mod replay;
+mod request_file;
+mod scenario;
+mod schema;
+mod sigv4;
+mod targets;
+mod thinktime;
+mod tui;
+mod websocket;
 
 /// Command line arguments parser
-#[derive(Parser)]
+#[derive(Parser, Clone, Serialize, Deserialize)]
 #[clap(
+    subcommand_negates_reqs = true,
     about = "Loadster is a simple load testing tool that allows you to test the performance of your web applications by sending concurrent HTTP requests."
 )]
 struct Cli {
     /// The target URL for the load test
-    #[clap(short = 'u', long, help = "The target URL for the load test")]
-    url: String,
+    #[clap(
+        short = 'u',
+        long,
+        required_unless_present_any = ["config", "profile", "url_file", "agent", "openapi", "sitemap", "repeatable_load", "scenario", "replay_log", "url_a", "request"],
+        help = "The target URL for the load test"
+    )]
+    url: Option<String>,
+
+    /// Compare two deployments: send the same load to this URL and to --url-b at the same time, and report the difference
+    #[clap(
+        long,
+        help = "Compare two deployments: send the same load to this URL and to --url-b at the same time, and report the difference"
+    )]
+    url_a: Option<String>,
+
+    /// The second deployment of --url-a, reported as B
+    #[clap(long, help = "The second deployment of --url-a, reported as B")]
+    url_b: Option<String>,
+
+    /// A JSON file describing the whole request: its url, method, headers and body, each replacing the matching flags
+    #[clap(
+        long,
+        help = "A JSON file describing the whole request: its url, method, headers and body, each replacing the matching flags"
+    )]
+    request: Option<String>,
 
-    /// The HTTP method to use (default: GET). Supported methods: GET, POST, PUT, DELETE, PATCH
-    #[clap(short = 'm', long, value_enum, default_value_t = HttpMethod::Get, help = "The HTTP method to use (default: GET). Supported methods: GET, POST, PUT, DELETE, PATCH")]
+    /// The HTTP method to use (default: GET). Supported methods: GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS
+    #[clap(short = 'm', long, value_enum, default_value_t = HttpMethod::Get, help = "The HTTP method to use (default: GET). Supported methods: GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS")]
     method: HttpMethod,
 
     /// The number of concurrent users (default: 10)
@@ -35,6 +105,35 @@ struct Cli {
     )]
     users: i32,
 
+    /// Raise the soft limit on open file descriptors to the hard limit before the run, as every connection needs one
+    #[clap(
+        long,
+        help = "Raise the soft limit on open file descriptors to the hard limit before the run, as every connection needs one"
+    )]
+    raise_fd_limit: bool,
+
+    /// Allow more concurrent users than the thread-per-user model handles safely (10000)
+    #[clap(
+        long,
+        help = "Allow more concurrent users than the thread-per-user model handles safely (10000)"
+    )]
+    allow_unsafe_concurrency: bool,
+
+    /// The total number of requests, sent by the users one after the other (default: one per user)
+    #[clap(
+        short = 'n',
+        long,
+        help = "The total number of requests, sent by the users one after the other (default: one per user)"
+    )]
+    requests: Option<u64>,
+
+    /// Stop sending requests after this many seconds, even if not all requests were sent
+    #[clap(
+        long,
+        help = "Stop sending requests after this many seconds, even if not all requests were sent"
+    )]
+    max_duration: Option<u64>,
+
     /// The timeout for each request in seconds (default: 30)
     #[clap(
         short = 't',
@@ -44,6 +143,20 @@ struct Cli {
     )]
     timeout: u64,
 
+    /// Draw each request's timeout uniformly from the timeout plus or minus this percentage, e.g. 20 for 24-36 s with -t 30
+    #[clap(
+        long,
+        help = "Draw each request's timeout uniformly from the timeout plus or minus this percentage, e.g. 20 for 24-36 s with -t 30"
+    )]
+    timeout_jitter: Option<f64>,
+
+    /// Simulate clients this far from the server: wait for a round trip time in milliseconds, half before each request is sent and half after its response, e.g. 80, 50-150 for a uniform spread or exp:80 for an exponential one
+    #[clap(
+        long,
+        help = "Simulate clients this far from the server: wait for a round trip time in milliseconds, half before each request is sent and half after its response, e.g. 80, 50-150 for a uniform spread or exp:80 for an exponential one"
+    )]
+    network_latency: Option<String>,
+
     /// Additional headers to include in the requests
     #[clap(
         short = 'H',
@@ -60,272 +173,8123 @@ struct Cli {
     )]
     body: Option<String>,
 
+    /// Send a generated body of this size, e.g. 500kb or 1mb (k, m and g are powers of 1024)
+    #[clap(
+        long,
+        help = "Send a generated body of this size, e.g. 500kb or 1mb (k, m and g are powers of 1024)"
+    )]
+    generate_body: Option<String>,
+
+    /// How --generate-body fills the body (default: random). Supported fills: random, zero, json
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = BodyFill::Random,
+        help = "How --generate-body fills the body (default: random). Supported fills: random, zero, json"
+    )]
+    generate_body_type: BodyFill,
+
     /// Enable verbose output
     #[clap(short = 'v', long, help = "Enable verbose output")]
     verbose: bool,
 
+    /// Only print the report, without per-request messages
+    #[clap(
+        short = 'q',
+        long,
+        help = "Only print the report, without per-request messages"
+    )]
+    quiet: bool,
+
+    /// Print a warning as soon as a request takes longer than this many milliseconds
+    #[clap(
+        long,
+        help = "Print a warning as soon as a request takes longer than this many milliseconds"
+    )]
+    warn_slow: Option<u64>,
+
+    /// Print one line for this fraction of the requests, drawn at random, e.g. 0.01 for one in a hundred
+    #[clap(
+        long,
+        help = "Print one line for this fraction of the requests, drawn at random, e.g. 0.01 for one in a hundred"
+    )]
+    log_sample: Option<f64>,
+
+    /// Weight the requests of --log-sample by their latency, so a request twice as slow as the worker's average is twice as likely to be printed
+    #[clap(
+        long,
+        help = "Weight the requests of --log-sample by their latency, so a request twice as slow as the worker's average is twice as likely to be printed"
+    )]
+    log_bias_slow: bool,
+
+    /// On a failed request, print the request and response in full and pause the run until Enter is pressed
+    #[clap(
+        long,
+        help = "On a failed request, print the request and response in full and pause the run until Enter is pressed"
+    )]
+    pause_on_error: bool,
+
+    /// Listen on this loopback port for commands that change the concurrency or stop the test while it runs
+    #[clap(
+        long,
+        help = "Listen on this loopback port for commands that change the concurrency or stop the test while it runs"
+    )]
+    control_port: Option<u16>,
+
+    /// Print a rolling summary every this many seconds during the run
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Print a rolling summary every this many seconds during the run"
+    )]
+    report_interval: Option<u64>,
+
+    /// Show a live dashboard of the run in the terminal, then the report; q stops the run
+    #[clap(
+        long,
+        help = "Show a live dashboard of the run in the terminal, then the report; q stops the run"
+    )]
+    tui: bool,
+
+    /// Report loadster's own memory use, threads and lock wait time, in the rolling summary and at the end of the run
+    #[clap(
+        long,
+        help = "Report loadster's own memory use, threads and lock wait time, in the rolling summary and at the end of the run"
+    )]
+    self_metrics: bool,
+
     /// Save the results to a file
     #[clap(short = 'o', long, help = "Save the results to a file")]
     output: Option<String>,
-}
 
-/// Supported HTTP methods
-#[derive(Debug, Clone, ValueEnum)]
-enum HttpMethod {
-    Get,
-    Post,
-    Put,
-    Delete,
-    Patch,
-}
+    /// The format of the results file (default: text). Supported formats: text, csv, json
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text, help = "The format of the results file (default: text). Supported formats: text, csv, json")]
+    format: OutputFormat,
 
-impl FromStr for HttpMethod {
-    type Err = String;
+    /// Add the start and end of every request, in nanoseconds since the start of the run, to the JSON results file, for aligning with server-side traces
+    #[clap(
+        long,
+        help = "Add the start and end of every request, in nanoseconds since the start of the run, to the JSON results file, for aligning with server-side traces"
+    )]
+    ns_timestamps: bool,
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "get" => Ok(HttpMethod::Get),
-            "post" => Ok(HttpMethod::Post),
-            "put" => Ok(HttpMethod::Put),
-            "delete" => Ok(HttpMethod::Delete),
-            "patch" => Ok(HttpMethod::Patch),
-            _ => Err(format!("'{}' is not a valid HTTP method", s)),
-        }
-    }
-}
+    /// Print the report only when the key metrics changed beyond --change-tolerance from the summary saved in this file, and a single line otherwise; the file is updated whenever the report is printed
+    #[clap(
+        long,
+        help = "Print the report only when the key metrics changed beyond --change-tolerance from the summary saved in this file, and a single line otherwise; the file is updated whenever the report is printed"
+    )]
+    summary_only_on_change: Option<String>,
 
-impl From<HttpMethod> for Method {
-    fn from(method: HttpMethod) -> Self {
-        match method {
-            HttpMethod::Get => Method::GET,
-            HttpMethod::Post => Method::POST,
-            HttpMethod::Put => Method::PUT,
-            HttpMethod::Delete => Method::DELETE,
-            HttpMethod::Patch => Method::PATCH,
-        }
-    }
-}
+    /// Append a summary of every run to this SQLite database, created on first use; list past runs with the history subcommand
+    #[clap(
+        long,
+        help = "Append a summary of every run to this SQLite database, created on first use; list past runs with the history subcommand"
+    )]
+    db: Option<String>,
 
-/// Struct to hold response details
-#[derive(Debug, Clone)]
-struct ResponseDetails {
-    status: StatusCode,
-    time: u64,      // Time in milliseconds
-    timestamp: u64, // Timestamp in seconds since UNIX_EPOCH
-}
+    /// Also store every response of the run in the responses table of --db
+    #[clap(
+        long,
+        help = "Also store every response of the run in the responses table of --db"
+    )]
+    db_responses: bool,
 
-fn main() {
-    let args = Cli::parse();
-    let data = call_api(
-        args.url.to_owned(),
-        args.method.into(),
-        args.users,
-        args.timeout,
-        args.headers,
-        args.body,
-        args.verbose,
-    )
-    .unwrap();
-    display_results(&data);
-    generate_report(&data, &args.url);
+    /// How far, in percent, a metric may move from the saved summary before it counts as changed (the error rate in percentage points)
+    #[clap(
+        long,
+        default_value_t = 10.0,
+        help = "How far, in percent, a metric may move from the saved summary before it counts as changed (the error rate in percentage points)"
+    )]
+    change_tolerance: f64,
 
-    if let Some(output) = args.output {
-        save_results(&data, &output);
-    }
-}
+    /// Run the test once per comma-separated concurrency level, e.g. 10,50,100, and report throughput and latency per level
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Run the test once per comma-separated concurrency level, e.g. 10,50,100, and report throughput and latency per level"
+    )]
+    sweep: Vec<i32>,
 
-/// Function to call the API concurrently
-fn call_api(
-    url: String,
-    method: Method,
-    concurrency: i32,
-    timeout: u64,
-    headers: Vec<String>,
-    body: Option<String>,
-    verbose: bool,
-) -> Result<Vec<ResponseDetails>, reqwest::Error> {
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(timeout))
-        .build()?;
-    let data = Arc::new(Mutex::new(vec![]));
-    let mut handles = vec![];
-
-    for i in 0..concurrency {
-        let url = url.clone();
-        let data = Arc::clone(&data);
-        let client = client.clone();
-        let method = method.clone();
-        let headers = headers.clone();
-        let body = body.clone();
-        let handle = thread::spawn(move || {
-            let start = Instant::now();
-            let mut request = client
-                .request(method.clone(), url.as_str())
-                .header(USER_AGENT, "loadster 1.0.0");
-
-            for header in headers {
-                let parts: Vec<&str> = header.splitn(2, ':').collect();
-                if parts.len() == 2 {
-                    request = request.header(parts[0], parts[1]);
-                }
-            }
+    /// Measure cache effectiveness: run the test once with a unique cache-busting query parameter on every request (cold), then repeat the same requests (warm), and compare their latencies
+    #[clap(
+        long,
+        help = "Measure cache effectiveness: run the test once with a unique cache-busting query parameter on every request (cold), then repeat the same requests (warm), and compare their latencies"
+    )]
+    cache_test: bool,
 
-            if let Some(body) = body {
-                request = request.body(body.clone());
-            }
+    /// Print a histogram of the latencies, and add it to the JSON output file
+    #[clap(
+        long,
+        help = "Print a histogram of the latencies, and add it to the JSON output file"
+    )]
+    histogram: bool,
 
-            let res = request.send();
-            match res {
-                Ok(res) => {
-                    let duration = start.elapsed();
-                    let timestamp = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-                    if verbose {
-                        println!("i: {} ,Status: {}", i, res.status());
-                    }
-                    let mut data = data.lock().unwrap();
-                    let response_details = ResponseDetails {
-                        status: res.status(),
-                        time: duration.as_millis() as u64,
-                        timestamp,
-                    };
-                    data.push(response_details);
-                }
-                Err(e) => {
-                    eprintln!("Request failed: {}", e);
-                }
-            }
-        });
-        handles.push(handle);
-    }
+    /// The buckets of --histogram: linear for buckets of the same width, log for buckets that double in width (default: linear)
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = histogram::HistogramScale::Linear,
+        help = "The buckets of --histogram: linear for buckets of the same width, log for buckets that double in width (default: linear)"
+    )]
+    histogram_scale: histogram::HistogramScale,
 
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    /// Save the latency histogram to a file in the HdrHistogram interval log format
+    #[clap(
+        long,
+        help = "Save the latency histogram to a file in the HdrHistogram interval log format"
+    )]
+    hdr_file: Option<String>,
 
-    let result = {
-        let data = data.lock().unwrap();
-        data.clone()
-    };
-    Ok(result)
-}
+    /// Save every response as a point of the InfluxDB line protocol, tagged with its URL, method and status
+    #[clap(
+        long,
+        help = "Save every response as a point of the InfluxDB line protocol, tagged with its URL, method and status"
+    )]
+    influx_file: Option<String>,
 
-/// Function to display the results of the load test
-fn display_results(data: &[ResponseDetails]) {
-    let total_requests = data.len();
-    let successful_requests = data.iter().filter(|d| d.status.is_success()).count();
-    let failed_requests = total_requests - successful_requests;
-    let total_time: u64 = data.iter().map(|d| d.time).sum();
-    let avg_time = total_time as f64 / total_requests as f64;
+    /// Save the latency of every response to a file, one number of milliseconds per line, in ascending order
+    #[clap(
+        long,
+        help = "Save the latency of every response to a file, one number of milliseconds per line, in ascending order"
+    )]
+    raw_latencies: Option<String>,
 
-    // Calculate additional metrics
-    let mut times: Vec<u64> = data.iter().map(|d| d.time).collect();
-    times.sort_unstable();
-    let median_time = times[times.len() / 2];
-    let min_time = times.first().unwrap_or(&0); // Prefix with underscore
-    let max_time = times.last().unwrap_or(&0);
+    /// Write --raw-latencies in the order the requests were sent, every latency after the time its request was sent
+    #[clap(
+        long,
+        help = "Write --raw-latencies in the order the requests were sent, every latency after the time its request was sent"
+    )]
+    raw_latencies_timestamps: bool,
 
-    println!("\nLoad Test Results:");
-    println!("Total Requests: {}", total_requests);
-    println!("Successful Requests: {}", successful_requests);
-    println!("Failed Requests: {}", failed_requests);
-    println!("Total Time: {} ms", total_time);
-    println!("Average Time per Request: {:.2} ms", avg_time);
-    println!("Median Time: {} ms", median_time);
-    println!("Minimum Time: {} ms", min_time);
-    println!("Maximum Time: {} ms", max_time);
-}
+    /// Load options from a TOML config file; command line flags take precedence
+    #[clap(
+        long,
+        help = "Load options from a TOML config file; command line flags take precedence"
+    )]
+    #[serde(skip)]
+    config: Option<String>,
 
-/// Function to generate a detailed load test report
-fn generate_report(data: &[ResponseDetails], url: &str) {
-    let total_requests = data.len();
-    let successful_requests = data.iter().filter(|d| d.status.is_success()).count();
-    let failed_requests = total_requests - successful_requests;
-    let total_time: u64 = data.iter().map(|d| d.time).sum();
-    let avg_time = total_time as f64 / total_requests as f64;
+    /// Load options from a profile saved with --save-profile; command line flags take precedence
+    #[clap(
+        long,
+        help = "Load options from a profile saved with --save-profile; command line flags take precedence"
+    )]
+    #[serde(skip)]
+    profile: Option<String>,
 
-    // Calculate additional metrics
-    let mut times: Vec<u64> = data.iter().map(|d| d.time).collect();
-    times.sort_unstable();
-    let median_time = times[times.len() / 2];
-    let max_time = times.last().unwrap_or(&0);
-    let p95_time = times[(times.len() as f64 * 0.95) as usize];
-    let p99_time = times[(times.len() as f64 * 0.99) as usize];
+    /// Save the options of this run that differ from their defaults as a named profile, then run it
+    #[clap(
+        long,
+        help = "Save the options of this run that differ from their defaults as a named profile, then run it"
+    )]
+    #[serde(skip)]
+    save_profile: Option<String>,
 
-    // Calculate response code distribution
-    let mut response_codes: HashMap<StatusCode, usize> = HashMap::new();
-    for detail in data {
-        *response_codes.entry(detail.status).or_insert(0) += 1;
-    }
+    /// When to read response bodies (default: on-assert, only when an option inspects them or they have no Content-Length). Supported values: always, never, on-assert
+    #[clap(long, value_enum, default_value_t = ReadBody::OnAssert, help = "When to read response bodies (default: on-assert, only when an option inspects them or they have no Content-Length). Supported values: always, never, on-assert")]
+    read_body: ReadBody,
 
-    // Calculate throughput
-    let duration_seconds = total_time as f64 / 1000.0;
-    let throughput = total_requests as f64 / duration_seconds;
+    /// Ask for the first byte of every response only (Range: bytes=0-0) and never read bodies, so times exclude the body transfer
+    #[clap(
+        long,
+        help = "Ask for the first byte of every response only (Range: bytes=0-0) and never read bodies, so times exclude the body transfer"
+    )]
+    ignore_body: bool,
 
-    println!("\nLoad Test Report");
-    println!("Summary");
-    println!("Metric\tValue");
-    println!("Target URL\t{}", url);
-    println!("Total Requests\t{}", total_requests);
-    println!("Successful Requests\t{}", successful_requests);
-    println!("Failed Requests\t{}", failed_requests);
-    println!("Duration\t{:.2} seconds", duration_seconds);
-    println!("Throughput\t{:.2} req/s", throughput);
-    println!("Avg Latency\t{:.2} ms", avg_time);
-    println!("P95 Latency\t{} ms", p95_time);
-    println!("P99 Latency\t{} ms", p99_time);
+    /// Read the bodies of responses with a non-2xx status, up to --max-body-size, and report the most common ones
+    #[clap(
+        long,
+        help = "Read the bodies of responses with a non-2xx status, up to --max-body-size, and report the most common ones"
+    )]
+    capture_error_bodies: bool,
 
-    println!("\nResponse Codes");
-    println!("Code\tCount\tPercentage");
-    for (code, count) in &response_codes {
-        println!(
-            "{}\t{}\t{:.2}%",
-            code,
-            count,
-            (*count as f64 / total_requests as f64) * 100.0
-        );
-    }
+    /// Read every response body at most this many bytes per second, like a slow client
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Read every response body at most this many bytes per second, like a slow client"
+    )]
+    bandwidth: Option<u64>,
 
-    println!("\nLatency Distribution");
-    println!("Percentile\tLatency (ms)");
-    println!("P50\t{}", median_time);
-    println!("P75\t{}", times[(times.len() as f64 * 0.75) as usize]);
-    println!("P95\t{}", p95_time);
-    println!("P99\t{}", p99_time);
-    println!("Max\t{}", max_time);
+    /// Validate every successful JSON response body against this JSON Schema file; violations count as failures
+    #[clap(
+        long,
+        help = "Validate every successful JSON response body against this JSON Schema file; violations count as failures"
+    )]
+    assert_schema: Option<String>,
 
-    // Additional metrics
-    let min_success_time = data
-        .iter()
-        .filter(|d| d.status.is_success())
-        .map(|d| d.time)
-        .min()
-        .unwrap_or(0);
-    let max_success_time = data
-        .iter()
-        .filter(|d| d.status.is_success())
-        .map(|d| d.time)
+    /// Hash every successful response body and count those whose SHA-256 isn't this hex digest as failures, for URLs that always return the same body
+    #[clap(
+        long,
+        help = "Hash every successful response body and count those whose SHA-256 isn't this hex digest as failures, for URLs that always return the same body"
+    )]
+    expected_checksum: Option<String>,
+
+    /// Hash every successful response body and report the bodies that more than one response returned, for URLs whose bodies should all differ
+    #[clap(
+        long,
+        help = "Hash every successful response body and report the bodies that more than one response returned, for URLs whose bodies should all differ"
+    )]
+    detect_duplicate_checksum: bool,
+
+    /// Compare every successful response body with this file, counting those that differ as failures; the report shows a unified diff of the first mismatch
+    #[clap(
+        long,
+        help = "Compare every successful response body with this file, counting those that differ as failures; the report shows a unified diff of the first mismatch"
+    )]
+    golden: Option<String>,
+
+    /// Parse every successful response body as JSON, counting those that don't parse as failures; the report shows a sample of the malformed bodies
+    #[clap(
+        long,
+        help = "Parse every successful response body as JSON, counting those that don't parse as failures; the report shows a sample of the malformed bodies"
+    )]
+    validate_json: bool,
+
+    /// Count only responses with this status code as successful, instead of any 2xx (repeatable)
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u16).range(100..1000),
+        help = "Count only responses with this status code as successful, instead of any 2xx (repeatable)"
+    )]
+    assert_status: Vec<u16>,
+
+    /// Count responses slower than this many milliseconds as failed
+    #[clap(
+        long,
+        help = "Count responses slower than this many milliseconds as failed"
+    )]
+    assert_max_latency: Option<u64>,
+
+    /// Maximum number of response body bytes kept in memory per request, 0 for unlimited (default: 10485760)
+    #[clap(
+        long,
+        default_value = "10485760",
+        help = "Maximum number of response body bytes kept in memory per request, 0 for unlimited (default: 10485760)"
+    )]
+    max_body_size: u64,
+
+    /// A directory of body files or a file with one body per line, sampled per request
+    #[clap(
+        long,
+        help = "A directory of body files or a file with one body per line, sampled per request"
+    )]
+    body_pool: Option<String>,
+
+    /// A Handlebars template file rendered into the body of every request, with conditionals and loops over the request's values
+    #[clap(
+        long,
+        help = "A Handlebars template file rendered into the body of every request, with conditionals and loops over the request's values"
+    )]
+    body_template: Option<String>,
+
+    /// How requests pick their body from the body pool (default: random)
+    #[clap(long, value_enum, default_value_t = PoolOrder::Random, help = "How requests pick their body from the body pool (default: random)")]
+    body_pool_order: PoolOrder,
+
+    /// How requests pick from the URLs of --openapi or --sitemap: random (by weight), round-robin, or sequential (each URL once, in order) (default: random)
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = UrlOrder::Random,
+        help = "How requests pick from the URLs of --openapi or --sitemap: random (by weight), round-robin, or sequential (each URL once, in order) (default: random)"
+    )]
+    url_order: UrlOrder,
+
+    /// Seed for the random number generator, for reproducible runs
+    #[clap(
+        long,
+        help = "Seed for the random number generator, for reproducible runs"
+    )]
+    seed: Option<u64>,
+
+    /// Benchmark loadster itself: send every request on a fixed schedule to a built-in mock server, so runs are repeatable
+    #[clap(
+        long,
+        help = "Benchmark loadster itself: send every request on a fixed schedule to a built-in mock server, so runs are repeatable"
+    )]
+    repeatable_load: bool,
+
+    /// The time the mock server of --repeatable-load takes to answer, in milliseconds (default: 10)
+    #[clap(
+        long,
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "The time the mock server of --repeatable-load takes to answer, in milliseconds (default: 10)"
+    )]
+    mock_latency: u64,
+
+    /// Stop the test as soon as a response with this status code arrives (repeatable)
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u16).range(100..1000),
+        help = "Stop the test as soon as a response with this status code arrives (repeatable)"
+    )]
+    abort_on_status: Vec<u16>,
+
+    /// The number of example and slowest requests to keep and report with --show-samples, 0 to disable (default: 5)
+    #[clap(
+        long,
+        default_value = "5",
+        help = "The number of example and slowest requests to keep and report with --show-samples, 0 to disable (default: 5)"
+    )]
+    sample_size: usize,
+
+    /// List the slowest requests and a random sample of the requests after the report, --sample-size of each
+    #[clap(
+        long,
+        help = "List the slowest requests and a random sample of the requests after the report, --sample-size of each"
+    )]
+    show_samples: bool,
+
+    /// Print the percentiles of the response sizes and a histogram of the sizes with the latency of each bucket
+    #[clap(
+        long,
+        help = "Print the percentiles of the response sizes and a histogram of the sizes with the latency of each bucket"
+    )]
+    size_stats: bool,
+
+    /// Print the trend of latency over the run and the P95 of its first and last quarters; a run whose last quarter is much slower is flagged either way
+    #[clap(
+        long,
+        help = "Print the trend of latency over the run and the P95 of its first and last quarters; a run whose last quarter is much slower is flagged either way"
+    )]
+    latency_trend: bool,
+
+    /// Print how many requests each connection served, to tell whether keep-alive works
+    #[clap(
+        long,
+        help = "Print how many requests each connection served, to tell whether keep-alive works"
+    )]
+    connection_stats: bool,
+
+    /// The number of responses a percentile must have at or above it to be reliable, e.g. 1 needs 100 responses for P99 (default: 1)
+    #[clap(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "The number of responses a percentile must have at or above it to be reliable, e.g. 1 needs 100 responses for P99 (default: 1)"
+    )]
+    min_samples: u64,
+
+    /// Also report the mean latency without the fastest and slowest this percentage of responses each, e.g. 5 for the middle 90%
+    #[clap(
+        long,
+        help = "Also report the mean latency without the fastest and slowest this percentage of responses each, e.g. 5 for the middle 90%"
+    )]
+    trim_mean: Option<f64>,
+
+    /// Estimate 95% confidence intervals of the P50, P95 and P99 latencies from this many bootstrap resamples of the response times, e.g. 1000 (repeatable with --seed)
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Estimate 95% confidence intervals of the P50, P95 and P99 latencies from this many bootstrap resamples of the response times, e.g. 1000 (repeatable with --seed)"
+    )]
+    bootstrap: Option<u32>,
+
+    /// Fail with a non-zero exit code if the throughput is below this many requests per second
+    #[clap(
+        long,
+        help = "Fail with a non-zero exit code if the throughput is below this many requests per second"
+    )]
+    min_throughput: Option<f64>,
+
+    /// Fail with a non-zero exit code if fewer than this percentage of requests reused an open connection
+    #[clap(
+        long,
+        help = "Fail with a non-zero exit code if fewer than this percentage of requests reused an open connection"
+    )]
+    assert_min_reuse: Option<f64>,
+
+    /// A CSV file whose columns can be used as {column} placeholders, one row per request
+    #[clap(
+        long,
+        help = "A CSV file whose columns can be used as {column} placeholders, one row per request"
+    )]
+    data_file: Option<String>,
+
+    /// Print the headers and body of one response before the test starts
+    #[clap(
+        long,
+        help = "Print the headers and body of one response before the test starts"
+    )]
+    sample_response: bool,
+
+    /// A file with one URL per line, each benchmarked in turn with the same settings
+    #[clap(
+        long,
+        help = "A file with one URL per line, each benchmarked in turn with the same settings"
+    )]
+    url_file: Option<String>,
+
+    /// An OpenAPI document (JSON or YAML) whose operations make up a weighted request mix; --url replaces its servers
+    #[clap(
+        long,
+        help = "An OpenAPI document (JSON or YAML) whose operations make up a weighted request mix; --url replaces its servers"
+    )]
+    openapi: Option<String>,
+
+    /// A sitemap whose pages are requested with GET, in equal shares
+    #[clap(
+        long,
+        help = "A sitemap whose pages are requested with GET, in equal shares"
+    )]
+    sitemap: Option<String>,
+
+    /// A TOML file of steps each iteration of the run sends in order, e.g. log in and then call the API; --url is the base of relative step URLs
+    #[clap(
+        long,
+        help = "A TOML file of steps each iteration of the run sends in order, e.g. log in and then call the API; --url is the base of relative step URLs"
+    )]
+    scenario: Option<String>,
+
+    /// Abandon the rest of a scenario iteration as soon as one of its steps fails, and start the next iteration from the top
+    #[clap(
+        long,
+        help = "Abandon the rest of a scenario iteration as soon as one of its steps fails, and start the next iteration from the top"
+    )]
+    fail_fast: bool,
+
+    /// An access log whose requests are replayed, in order, against --url
+    #[clap(
+        long,
+        help = "An access log whose requests are replayed, in order, against --url"
+    )]
+    replay_log: Option<String>,
+
+    /// The format of --replay-log (default: combined). Supported formats: combined, json, har
+    #[clap(long, value_enum, default_value_t = replay::LogFormat::Combined, help = "The format of --replay-log (default: combined). Supported formats: combined, json, har")]
+    log_format: replay::LogFormat,
+
+    /// Send the requests of --replay-log with the time between them in the log
+    #[clap(
+        long,
+        help = "Send the requests of --replay-log with the time between them in the log"
+    )]
+    preserve_timing: bool,
+
+    /// Replay --preserve-timing at this multiple of the log's rate, e.g. 2 halves the time between requests
+    #[clap(
+        long,
+        help = "Replay --preserve-timing at this multiple of the log's rate, e.g. 2 halves the time between requests"
+    )]
+    rate_multiplier: Option<f64>,
+
+    /// Experimental: pause every worker after each request for a think time that adapts once a second to keep the P95 latency near --target-p95
+    #[clap(
+        long,
+        help = "Experimental: pause every worker after each request for a think time that adapts once a second to keep the P95 latency near --target-p95"
+    )]
+    adaptive_thinktime: bool,
+
+    /// Desynchronize the workers: each waits a random time of up to this many milliseconds before every iteration, its first included
+    #[clap(
+        long,
+        help = "Desynchronize the workers: each waits a random time of up to this many milliseconds before every iteration, its first included"
+    )]
+    jitter: Option<u64>,
+
+    /// Send this percentage of the requests, picked at random, to a path that doesn't exist, to check error handling and alerting under a known error rate
+    #[clap(
+        long,
+        help = "Send this percentage of the requests, picked at random, to a path that doesn't exist, to check error handling and alerting under a known error rate"
+    )]
+    inject_errors: Option<f64>,
+
+    /// Cap the rate of every worker at this many requests per second, each on its own, like clients with a rate limit of their own
+    #[clap(
+        long,
+        help = "Cap the rate of every worker at this many requests per second, each on its own, like clients with a rate limit of their own"
+    )]
+    max_rps_per_worker: Option<f64>,
+
+    /// The P95 latency in milliseconds that --adaptive-thinktime aims for
+    #[clap(
+        long,
+        help = "The P95 latency in milliseconds that --adaptive-thinktime aims for"
+    )]
+    target_p95: Option<u64>,
+
+    /// Compress the request body and set Content-Encoding. Supported encodings: gzip, br
+    #[clap(
+        long,
+        value_enum,
+        help = "Compress the request body and set Content-Encoding. Supported encodings: gzip, br"
+    )]
+    compress_body: Option<BodyCompression>,
+
+    /// The maximum number of simultaneous connections, independent of the number of users
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "The maximum number of simultaneous connections, independent of the number of users"
+    )]
+    connections: Option<u64>,
+
+    /// Close connections that sat idle in the pool for this many seconds, to match the keep-alive timeout of the server (default: 90); 0 closes every connection after its response
+    #[clap(
+        long,
+        help = "Close connections that sat idle in the pool for this many seconds, to match the keep-alive timeout of the server (default: 90); 0 closes every connection after its response"
+    )]
+    keepalive_timeout: Option<u64>,
+
+    /// Warn as soon as the server closes a connection with Connection: close, which breaks keep-alive
+    #[clap(
+        long,
+        help = "Warn as soon as the server closes a connection with Connection: close, which breaks keep-alive"
+    )]
+    warn_on_connection_close: bool,
+
+    /// Open this many connections to the target before the measured run starts, so the first requests don't pay for connection setup
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Open this many connections to the target before the measured run starts, so the first requests don't pay for connection setup"
+    )]
+    prewarm_connections: Option<u32>,
+
+    /// The local IP address to send requests from, e.g. one of the addresses of a multi-homed machine
+    #[clap(
+        long,
+        help = "The local IP address to send requests from, e.g. one of the addresses of a multi-homed machine"
+    )]
+    local_address: Option<IpAddr>,
+
+    /// Pin the TLS version of HTTPS connections. Supported versions: 1.2, 1.3 (1.3 requires the rustls feature)
+    #[clap(
+        long,
+        value_enum,
+        help = "Pin the TLS version of HTTPS connections. Supported versions: 1.2, 1.3 (1.3 requires the rustls feature)"
+    )]
+    tls_version: Option<TlsVersion>,
+
+    /// Set TCP_NODELAY on connections, sending small requests without waiting for Nagle's algorithm (default: on). Supported values: on, off
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Switch::On,
+        help = "Set TCP_NODELAY on connections, sending small requests without waiting for Nagle's algorithm (default: on). Supported values: on, off"
+    )]
+    tcp_nodelay: Switch,
+
+    /// Send requests over HTTP/3 (QUIC) instead of TCP; experimental, requires the http3 feature
+    #[clap(
+        long,
+        help = "Send requests over HTTP/3 (QUIC) instead of TCP; experimental, requires the http3 feature"
+    )]
+    http3: bool,
+
+    /// Fall back to HTTP/1.1 and HTTP/2 when the server doesn't answer over HTTP/3, instead of failing
+    #[clap(
+        long,
+        help = "Fall back to HTTP/1.1 and HTTP/2 when the server doesn't answer over HTTP/3, instead of failing"
+    )]
+    http3_fallback: bool,
+
+    /// Export an OpenTelemetry span for every request to this OTLP/HTTP collector, e.g. http://localhost:4318, and send a traceparent header; requires the otel feature
+    #[clap(
+        long,
+        help = "Export an OpenTelemetry span for every request to this OTLP/HTTP collector, e.g. http://localhost:4318, and send a traceparent header; requires the otel feature"
+    )]
+    otel_endpoint: Option<String>,
+
+    /// Benchmark HTTP/1.1 pipelining: send this many requests at a time on each connection without waiting for responses, and compare with sending them one at a time
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(2..),
+        help = "Benchmark HTTP/1.1 pipelining: send this many requests at a time on each connection without waiting for responses, and compare with sending them one at a time"
+    )]
+    pipeline: Option<u32>,
+
+    /// Benchmark the Expect: 100-continue handshake: send every request head with the header, its body only after the server answers, and report the time to 100 Continue
+    #[clap(
+        long,
+        help = "Benchmark the Expect: 100-continue handshake: send every request head with the header, its body only after the server answers, and report the time to 100 Continue"
+    )]
+    expect_continue: bool,
+
+    /// Resend a request up to this many times when it fails without a response, e.g. on a connection error or timeout
+    #[clap(
+        long,
+        default_value_t = 0,
+        help = "Resend a request up to this many times when it fails without a response, e.g. on a connection error or timeout"
+    )]
+    retries: u32,
+
+    /// Also resend responses with these status codes, each with an optional backoff in milliseconds, e.g. 429:1000,503:100,502; a Retry-After header on a 429 or 503 response takes precedence over the backoff
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Also resend responses with these status codes, each with an optional backoff in milliseconds, e.g. 429:1000,503:100,502; a Retry-After header on a 429 or 503 response takes precedence over the backoff"
+    )]
+    retry_on: Vec<String>,
+
+    /// Drop this percentage of requests mid-flight, closing their connection, to test how the server copes with clients that disconnect
+    #[clap(
+        long,
+        help = "Drop this percentage of requests mid-flight, closing their connection, to test how the server copes with clients that disconnect"
+    )]
+    abandon_percent: Option<f64>,
+
+    /// Pause a worker after this many consecutive failed requests before probing the server again
+    #[clap(
+        long,
+        help = "Pause a worker after this many consecutive failed requests before probing the server again"
+    )]
+    circuit_breaker_threshold: Option<u32>,
+
+    /// How long a worker pauses once its circuit breaker trips, in milliseconds (default: 1000)
+    #[clap(
+        long,
+        default_value_t = 1000,
+        help = "How long a worker pauses once its circuit breaker trips, in milliseconds (default: 1000)"
+    )]
+    circuit_breaker_cooldown: u64,
+
+    /// Fetch an OAuth2 access token from this URL (client credentials grant) and send it as a Bearer token
+    #[clap(
+        long,
+        help = "Fetch an OAuth2 access token from this URL (client credentials grant) and send it as a Bearer token"
+    )]
+    oauth_token_url: Option<String>,
+
+    /// The OAuth2 client ID
+    #[clap(long, help = "The OAuth2 client ID")]
+    oauth_client_id: Option<String>,
+
+    /// The OAuth2 client secret
+    #[clap(long, help = "The OAuth2 client secret")]
+    oauth_client_secret: Option<String>,
+
+    /// The OAuth2 scope to request
+    #[clap(long, help = "The OAuth2 scope to request")]
+    oauth_scope: Option<String>,
+
+    /// Sign requests with AWS Signature Version 4
+    #[clap(long, help = "Sign requests with AWS Signature Version 4")]
+    aws_sigv4: bool,
+
+    /// The AWS region to sign for (default: AWS_REGION or AWS_DEFAULT_REGION)
+    #[clap(
+        long,
+        help = "The AWS region to sign for (default: AWS_REGION or AWS_DEFAULT_REGION)"
+    )]
+    aws_region: Option<String>,
+
+    /// The AWS service to sign for, e.g. execute-api, lambda or s3
+    #[clap(
+        long,
+        help = "The AWS service to sign for, e.g. execute-api, lambda or s3"
+    )]
+    aws_service: Option<String>,
+
+    /// The profile in the shared credentials file to use when the AWS credentials are not in the environment (default: AWS_PROFILE or default)
+    #[clap(
+        long,
+        help = "The profile in the shared credentials file to use when the AWS credentials are not in the environment (default: AWS_PROFILE or default)"
+    )]
+    aws_profile: Option<String>,
+
+    /// The number of decimal places of averages, rates and percentages in the report (default: 2)
+    #[clap(
+        long,
+        default_value_t = 2,
+        help = "The number of decimal places of averages, rates and percentages in the report (default: 2)"
+    )]
+    precision: usize,
+
+    /// Don't color the report, even on a terminal (colors are also off when NO_COLOR is set)
+    #[clap(
+        long,
+        help = "Don't color the report, even on a terminal (colors are also off when NO_COLOR is set)"
+    )]
+    no_color: bool,
+
+    /// Print the requests, failures and latency of every worker, to diagnose uneven load
+    #[clap(
+        long,
+        help = "Print the requests, failures and latency of every worker, to diagnose uneven load"
+    )]
+    per_worker_stats: bool,
+
+    /// Print the requests, failures and latency of every server IP the hostname resolved to, to find a slow backend
+    #[clap(
+        long,
+        help = "Print the requests, failures and latency of every server IP the hostname resolved to, to find a slow backend"
+    )]
+    split_by_ip: bool,
+
+    /// Report the results per URL by URL template (e.g. /items/{id}) instead of per concrete URL
+    #[clap(
+        long,
+        help = "Report the results per URL by URL template (e.g. /items/{id}) instead of per concrete URL"
+    )]
+    url_template_report: bool,
+
+    /// Run as an agent that waits for tests from a coordinator
+    #[clap(long, help = "Run as an agent that waits for tests from a coordinator")]
+    agent: bool,
+
+    /// The address an agent listens on, e.g. :7878 or 10.0.0.5:7878 (default: :7878)
+    #[clap(
+        long,
+        default_value = ":7878",
+        help = "The address an agent listens on, e.g. :7878 or 10.0.0.5:7878 (default: :7878)"
+    )]
+    listen: String,
+
+    /// Run the test on the agents given with --agents and merge their results
+    #[clap(
+        long,
+        help = "Run the test on the agents given with --agents and merge their results"
+    )]
+    coordinator: bool,
+
+    /// Comma-separated agent addresses (host or host:port) for --coordinator
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "Comma-separated agent addresses (host or host:port) for --coordinator"
+    )]
+    agents: Vec<String>,
+
+    #[clap(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+}
+
+/// Subcommands of loadster; without one, it runs an HTTP load test
+#[derive(Subcommand, Clone)]
+enum Command {
+    /// Run an HTTP load test, the default: `overloader run -u URL` is the same as `overloader -u URL`
+    Run,
+    /// Load test a WebSocket endpoint: open concurrent connections and measure the round trip of messages
+    Ws(websocket::WsArgs),
+    /// Run as an agent that waits for tests from a coordinator, like --agent
+    Agent(distributed::AgentArgs),
+    /// Run the mock server of --repeatable-load on its own, answering every request with 200 OK after a fixed latency
+    Serve(mock::ServeArgs),
+    /// List or delete the profiles saved with --save-profile
+    Profile(profiles::ProfileArgs),
+    /// List the most recent runs recorded in a results database with --db
+    History(history::HistoryArgs),
+}
+
+/// Supported HTTP methods
+#[derive(Debug, Clone, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HttpMethod {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Head,
+    Options,
+}
+
+/// Supported formats of the results file
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+/// When response bodies are read
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ReadBody {
+    Always,
+    Never,
+    OnAssert,
+}
+
+/// Ways of filling a body generated with --generate-body
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BodyFill {
+    Random, // Random letters and digits
+    Zero,   // NUL bytes
+    Json,   // A JSON object with a string of random letters and digits
+}
+
+/// Ways of picking an entry from a pool
+#[derive(Debug, Clone, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum PoolOrder {
+    Random,
+    RoundRobin,
+}
+
+/// Ways of picking a request from a request mix
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum UrlOrder {
+    Random,     // In proportion to the weights
+    RoundRobin, // Request `i` sends URL `i % n`, over and over
+    Sequential, // Every URL once, in order, then the run ends
+}
+
+/// A setting that is either on or off
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Switch {
+    On,
+    Off,
+}
+
+impl Switch {
+    fn is_on(self) -> bool {
+        self == Switch::On
+    }
+}
+
+/// TLS versions that connections can be pinned to
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize)]
+enum TlsVersion {
+    #[value(name = "1.2")]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn version(self) -> reqwest::tls::Version {
+        match self {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "TLS 1.2",
+            TlsVersion::Tls13 => "TLS 1.3",
+        }
+    }
+}
+
+/// Supported request body compressions
+#[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BodyCompression {
+    Gzip,
+    Br,
+}
+
+impl BodyCompression {
+    /// The Content-Encoding header value of the compression
+    fn content_encoding(self) -> &'static str {
+        match self {
+            BodyCompression::Gzip => "gzip",
+            BodyCompression::Br => "br",
+        }
+    }
+
+    /// Compress a request body in memory
+    fn compress(self, body: &[u8]) -> Vec<u8> {
+        match self {
+            BodyCompression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder
+                    .write_all(body)
+                    .expect("compressing into memory cannot fail");
+                encoder
+                    .finish()
+                    .expect("compressing into memory cannot fail")
+            }
+            BodyCompression::Br => {
+                let mut compressed = vec![];
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                    encoder
+                        .write_all(body)
+                        .expect("compressing into memory cannot fail");
+                }
+                compressed
+            }
+        }
+    }
+}
+
+/// Struct to hold one distinct body of the failed responses of a run, with
+/// --capture-error-bodies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ErrorBody {
+    status: u16,
+    body: String, // The body as text, at most --max-body-size bytes of it
+    count: u64,   // Number of responses with this status and body
+}
+
+/// Struct to hold the first response body of a run that differed from the
+/// --golden file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GoldenMismatch {
+    request: u64,   // Index of the request
+    removed: usize, // Lines of the golden file missing from the body
+    added: usize,   // Lines of the body missing from the golden file
+    diff: String,   // Unified diff from the golden file to the body
+}
+
+/// Struct to hold a response body of --validate-json that didn't parse
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MalformedJson {
+    request: u64,  // Index of the request
+    error: String, // Why parsing failed, with the line and column
+    body: String,  // The start of the body
+}
+
+impl MalformedJson {
+    /// The most malformed bodies kept for the report
+    const MAX_SAMPLES: usize = 5;
+
+    /// The most characters of a body kept
+    const MAX_CHARS: usize = 200;
+
+    fn new(body: &[u8], error: serde_json::Error, request: u64) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let mut start: String = body.chars().take(Self::MAX_CHARS).collect();
+        if start.len() < body.len() {
+            start.push_str("...");
+        }
+        MalformedJson {
+            request,
+            error: error.to_string(),
+            body: start,
+        }
+    }
+}
+
+impl GoldenMismatch {
+    fn new(golden: &[u8], body: &[u8], request: u64) -> Self {
+        let golden = String::from_utf8_lossy(golden);
+        let body = String::from_utf8_lossy(body);
+        let diff = similar::TextDiff::from_lines(golden.as_ref(), body.as_ref());
+        let (mut removed, mut added) = (0, 0);
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                similar::ChangeTag::Delete => removed += 1,
+                similar::ChangeTag::Insert => added += 1,
+                similar::ChangeTag::Equal => {}
+            }
+        }
+        GoldenMismatch {
+            request,
+            removed,
+            added,
+            diff: diff
+                .unified_diff()
+                .header("golden", &format!("response {}", request))
+                .to_string(),
+        }
+    }
+}
+
+/// Function to turn the distinct error bodies of a run into a list, most
+/// common first
+fn error_body_list(bodies: HashMap<(u16, String), u64>) -> Vec<ErrorBody> {
+    let mut bodies: Vec<ErrorBody> = bodies
+        .into_iter()
+        .map(|((status, body), count)| ErrorBody {
+            status,
+            body,
+            count,
+        })
+        .collect();
+    bodies.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(a.status.cmp(&b.status))
+            .then_with(|| a.body.cmp(&b.body))
+    });
+    bodies
+}
+
+/// Struct to hold the request body sizes of a run sent with --compress-body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompressionStats {
+    compression: BodyCompression,
+    original_bytes: u64,   // Total size of the bodies before compression
+    compressed_bytes: u64, // Total size of the bodies as sent
+}
+
+/// Struct to hold the values substituted into the `{placeholder}`s of the
+/// URL, headers and body of one request
+#[derive(Debug, Clone)]
+struct TemplateContext {
+    counter: u64,                 // Index of the request within the run
+    uuid: String,                 // Random UUID (v4), drawn from the seeded random number generator
+    row: HashMap<String, String>, // Row of the data file, keyed by column name
+}
+
+impl TemplateContext {
+    /// The values a --body-template is rendered with; `random` and
+    /// `random_int` are drawn from `rng`
+    fn template_data(&self, rng: &mut StdRng) -> Value {
+        serde_json::json!({
+            "counter": self.counter,
+            "uuid": self.uuid,
+            "timestamp": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "random": rng.random::<f64>(),
+            "random_int": rng.random::<u32>(),
+            "row": self.row,
+        })
+    }
+}
+
+/// Struct to hold a request body loaded from the body pool
+#[derive(Debug, Clone)]
+struct BodyPoolEntry {
+    name: String, // File name or line number the body was read from
+    body: String,
+}
+
+impl FromStr for HttpMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "get" => Ok(HttpMethod::Get),
+            "post" => Ok(HttpMethod::Post),
+            "put" => Ok(HttpMethod::Put),
+            "delete" => Ok(HttpMethod::Delete),
+            "patch" => Ok(HttpMethod::Patch),
+            "head" => Ok(HttpMethod::Head),
+            "options" => Ok(HttpMethod::Options),
+            _ => Err(format!("'{}' is not a valid HTTP method", s)),
+        }
+    }
+}
+
+impl From<HttpMethod> for Method {
+    fn from(method: HttpMethod) -> Self {
+        match method {
+            HttpMethod::Get => Method::GET,
+            HttpMethod::Post => Method::POST,
+            HttpMethod::Put => Method::PUT,
+            HttpMethod::Delete => Method::DELETE,
+            HttpMethod::Patch => Method::PATCH,
+            HttpMethod::Head => Method::HEAD,
+            HttpMethod::Options => Method::OPTIONS,
+        }
+    }
+}
+
+/// Struct to hold response details
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResponseDetails {
+    #[serde(with = "distributed::status_code")]
+    status: StatusCode,
+    time: u64,                 // Time in milliseconds
+    ttfb: u64,                 // Time until the status and headers arrived, in milliseconds
+    started_at_offset_ms: u64, // Start of the request in milliseconds since the start of the run
+    started_at_offset_ns: u64, // The same in nanoseconds, for --influx-file and --ns-timestamps
+    #[serde(default)]
+    ended_at_offset_ns: u64, // End of the request in nanoseconds since the start of the run
+    bytes: u64, // Size of the response body in bytes, from Content-Length if it was not read
+    bytes_read: u64, // Bytes of the body kept in memory, at most --max-body-size
+    body_read: bool, // Whether the body was read, see --read-body
+    url: String, // The URL sent, after its placeholders were filled in
+    #[serde(default)]
+    method: String, // The method sent
+    url_template: String, // The URL before its placeholders were filled in
+    worker: u32, // Index of the worker (user) that sent the request
+    step: Option<usize>, // Index of the --scenario step the request was sent for
+    #[serde(default)]
+    schema: Option<schema::SchemaCheck>, // The body checked against --assert-schema
+    #[serde(default)]
+    remote_ip: Option<IpAddr>, // The IP address of the server that answered, with --split-by-ip
+    #[serde(default)]
+    version: String, // The HTTP version of the response, e.g. HTTP/1.1
+    #[serde(default)]
+    checksum: Option<String>, // Hex SHA-256 of the body, with --expected-checksum or --detect-duplicate-checksum
+    #[serde(default)]
+    golden: Option<bool>, // Whether the body matched the --golden file, when compared
+    #[serde(default)]
+    json_valid: Option<bool>, // Whether the body parsed as JSON, with --validate-json
+    #[serde(default)]
+    connection_close: bool, // Whether the server closed the connection with Connection: close
+    #[serde(default)]
+    injected: bool, // Whether --inject-errors sent the request to a path that doesn't exist
+}
+
+/// Struct to hold the conditions a response must meet to count as a
+/// success, from the --assert-* options. Every condition given must hold.
+#[derive(Debug, Clone, Default)]
+struct SuccessCriteria {
+    statuses: Vec<u16>,       // --assert-status, any 2xx when empty
+    max_latency: Option<u64>, // --assert-max-latency, in milliseconds
+    checksum: Option<String>, // --expected-checksum, in lowercase hex
+}
+
+impl SuccessCriteria {
+    fn new(args: &Cli) -> Self {
+        SuccessCriteria {
+            statuses: args.assert_status.clone(),
+            max_latency: args.assert_max_latency,
+            checksum: args
+                .expected_checksum
+                .as_ref()
+                .map(|checksum| checksum.to_ascii_lowercase()),
+        }
+    }
+
+    /// Whether a status is one of the successful ones
+    fn accepts_status(&self, status: StatusCode) -> bool {
+        if self.statuses.is_empty() {
+            status.is_success()
+        } else {
+            self.statuses.contains(&status.as_u16())
+        }
+    }
+}
+
+/// Function to decide whether a response was a success: its status is
+/// accepted, it arrived within --assert-max-latency, and its body matches
+/// --assert-schema, --expected-checksum and --golden and parses with
+/// --validate-json when it was checked.
+/// The report, the gates and the workers all go by this.
+fn is_successful(details: &ResponseDetails, criteria: &SuccessCriteria) -> bool {
+    criteria.accepts_status(details.status)
+        && criteria.max_latency.is_none_or(|max| details.time <= max)
+        && !matches!(details.schema, Some(schema::SchemaCheck::Violations(_)))
+        && !checksum_mismatch(details, criteria)
+        && details.golden != Some(false)
+        && details.json_valid != Some(false)
+}
+
+/// Function to check whether a hashed body differs from --expected-checksum
+fn checksum_mismatch(details: &ResponseDetails, criteria: &SuccessCriteria) -> bool {
+    match (&criteria.checksum, &details.checksum) {
+        (Some(expected), Some(checksum)) => expected != checksum,
+        _ => false,
+    }
+}
+
+impl ResponseDetails {
+    /// Whether the response body was read but larger than --max-body-size
+    fn truncated(&self) -> bool {
+        self.body_read && self.bytes > self.bytes_read
+    }
+}
+
+/// Struct to hold the results of a load test run
+///
+/// Request start times are kept as offsets from a single monotonic `Instant`
+/// taken when the run starts, so adjustments of the wall clock during the run
+/// (e.g. by NTP) cannot reorder or skew them. `started_at` is only used to turn
+/// those offsets into wall-clock timestamps for display.
+struct LoadTestRun {
+    started_at: SystemTime,
+    elapsed: Duration, // Wall-clock time from the start of the run until the last response
+    responses: Vec<ResponseDetails>,
+    aborted: Option<AbortReason>,
+    samples: RequestSamples,
+    compression: Option<CompressionStats>,
+    connections_opened: u64, // Number of new connections the client had to open
+    prewarmed: Option<u64>,  // Connections opened by --prewarm-connections before the run
+    retries: u64,            // Number of attempts resent after a failure, see --retries
+    retry_classes: BTreeMap<String, u64>, // Resent attempts by the class of their status, `err` for those without a response
+    breaker_trips: u64,                   // Number of times a worker's circuit breaker tripped
+    request_errors: u64, // Number of requests that got no response, e.g. connection errors
+    resource_exhausted: u64, // Those of them that failed for lack of file descriptors
+    abandoned: u64,      // Number of requests dropped mid-flight by --abandon-percent
+    timeouts: Vec<u64>,  // Effective timeout in milliseconds of every request that timed out
+    requests: u64,       // Number of requests the run was to send
+    max_duration_reached: bool, // Whether --max-duration stopped the run before all requests were sent
+    schedule_lag: Vec<u64>, // How late every request of --repeatable-load or --preserve-timing was sent, in microseconds
+    think_times: Vec<thinktime::ThinkTimeStep>, // Every adjustment of --adaptive-thinktime
+    scenario: Option<scenario::ScenarioStats>,
+    lock_wait_ns: Option<u64>, // Time the workers waited for shared state, with --self-metrics
+    error_bodies: Vec<ErrorBody>, // Distinct bodies of failed responses, with --capture-error-bodies
+    golden_mismatch: Option<GoldenMismatch>, // The first body that differed from --golden
+    malformed_json: Vec<MalformedJson>, // The first bodies that didn't parse with --validate-json
+    requests_per_connection: Vec<u64>, // Responses read over every connection, empty over HTTP/3
+}
+
+/// Struct to keep a bounded set of example requests
+///
+/// `sample` is a uniform random sample of every recorded response (reservoir
+/// sampling) and `slowest` holds the slowest responses, slowest first. Both
+/// hold at most `capacity` entries, so memory stays constant however many
+/// responses are recorded.
+struct RequestSamples {
+    capacity: usize,
+    seen: u64,
+    sample: Vec<ResponseDetails>,
+    slowest: Vec<ResponseDetails>,
+    rng: StdRng,
+}
+
+impl RequestSamples {
+    fn new(capacity: usize, rng: StdRng) -> Self {
+        RequestSamples {
+            capacity,
+            seen: 0,
+            sample: Vec::with_capacity(capacity),
+            slowest: Vec::with_capacity(capacity + 1),
+            rng,
+        }
+    }
+
+    /// Offer a response to the sample and the slowest requests
+    fn record(&mut self, detail: &ResponseDetails) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.seen += 1;
+        if self.sample.len() < self.capacity {
+            self.sample.push(detail.clone());
+        } else {
+            let slot = self.rng.random_range(0..self.seen) as usize;
+            if slot < self.capacity {
+                self.sample[slot] = detail.clone();
+            }
+        }
+
+        let position = self.slowest.partition_point(|d| d.time >= detail.time);
+        if position < self.capacity {
+            self.slowest.insert(position, detail.clone());
+            self.slowest.truncate(self.capacity);
+        }
+    }
+}
+
+/// Struct to fetch and cache an OAuth2 access token using the client
+/// credentials grant. The token is refreshed once most of its lifetime has
+/// passed, so long runs keep sending valid tokens.
+struct OAuthTokenSource {
+    client: Client,
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    token: Mutex<OAuthToken>,
+    refreshing: AtomicBool, // Whether a worker is fetching a new token
+}
+
+/// Struct to hold a fetched access token
+struct OAuthToken {
+    access_token: String,
+    refresh_at: Option<Instant>, // None when the server did not say when it expires
+}
+
+/// The fields of a token endpoint response that loadster uses
+#[derive(Deserialize)]
+struct OAuthTokenResponse {
+    access_token: String,
+    expires_in: Option<u64>, // Lifetime of the token in seconds
+}
+
+impl OAuthTokenSource {
+    /// Create a token source from the --oauth-* flags and fetch the first token
+    fn new(args: &Cli, token_url: &str) -> Result<Self, String> {
+        let (Some(client_id), Some(client_secret)) =
+            (&args.oauth_client_id, &args.oauth_client_secret)
+        else {
+            return Err(
+                "--oauth-token-url requires --oauth-client-id and --oauth-client-secret"
+                    .to_string(),
+            );
+        };
+        let client = Client::builder()
+            .timeout(Duration::from_secs(args.timeout))
+            .build()
+            .map_err(|e| format!("cannot create OAuth2 client: {}", e))?;
+        let mut source = OAuthTokenSource {
+            client,
+            token_url: token_url.to_string(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            scope: args.oauth_scope.clone(),
+            token: Mutex::new(OAuthToken {
+                access_token: String::new(),
+                refresh_at: None,
+            }),
+            refreshing: AtomicBool::new(false),
+        };
+        let token = source.fetch()?;
+        source.token = Mutex::new(token);
+        Ok(source)
+    }
+
+    /// Request a new token from the token endpoint
+    fn fetch(&self) -> Result<OAuthToken, String> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope.as_str()));
+        }
+        let res = self
+            .client
+            .post(&self.token_url)
+            .form(&form)
+            .send()
+            .map_err(|e| format!("cannot fetch OAuth2 token from {}: {}", self.token_url, e))?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(format!(
+                "cannot fetch OAuth2 token from {}: server returned {}",
+                self.token_url, status
+            ));
+        }
+        let fetched_at = Instant::now();
+        let response: OAuthTokenResponse = res.json().map_err(|e| {
+            format!(
+                "cannot fetch OAuth2 token from {}: invalid token response: {}",
+                self.token_url, e
+            )
+        })?;
+        Ok(OAuthToken {
+            access_token: response.access_token,
+            refresh_at: response
+                .expires_in
+                .map(|seconds| fetched_at + Duration::from_secs(seconds) * 9 / 10),
+        })
+    }
+
+    /// The current access token, refreshed first if it is about to expire.
+    /// One worker fetches the new token while the others go on with the
+    /// current one, so a slow token endpoint holds up a single request. A
+    /// failed refresh is reported, the previous token is used again and the
+    /// refresh is retried a second later.
+    fn access_token(&self) -> String {
+        {
+            let token = self.token.lock().unwrap();
+            let due = token.refresh_at.is_some_and(|at| Instant::now() >= at);
+            if !due || self.refreshing.swap(true, Ordering::SeqCst) {
+                return token.access_token.clone();
+            }
+        }
+        let fetched = self.fetch();
+        let mut token = self.token.lock().unwrap();
+        match fetched {
+            Ok(fresh) => *token = fresh,
+            Err(e) => {
+                eprintln!("Token refresh failed: {}", e);
+                token.refresh_at = Some(Instant::now() + Duration::from_secs(1));
+            }
+        }
+        self.refreshing.store(false, Ordering::SeqCst);
+        token.access_token.clone()
+    }
+}
+
+/// A counting semaphore that bounds how many workers can be inside a section
+/// at the same time
+struct Semaphore {
+    permits: Mutex<u64>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: u64) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a permit is available; it is returned when the guard drops
+    fn acquire(&self) -> SemaphoreGuard<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        SemaphoreGuard { semaphore: self }
+    }
+}
+
+/// A permit of a `Semaphore`
+struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Struct to hold the workers of a run while a failed request is inspected
+/// (--pause-on-error)
+struct PauseGate {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    enabled: AtomicBool, // Cleared once the user chooses to stop pausing
+}
+
+impl PauseGate {
+    fn new() -> Self {
+        PauseGate {
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            enabled: AtomicBool::new(true),
+        }
+    }
+
+    /// Block while the run is paused
+    fn wait(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused {
+            paused = self.resumed.wait(paused).unwrap();
+        }
+    }
+
+    /// Pause the run, waiting for a pause of another worker to end first.
+    /// Returns false when pausing was turned off in the meantime.
+    fn pause(&self) -> bool {
+        let mut paused = self.paused.lock().unwrap();
+        while *paused {
+            paused = self.resumed.wait(paused).unwrap();
+        }
+        if !self.enabled.load(Ordering::SeqCst) {
+            return false;
+        }
+        *paused = true;
+        true
+    }
+
+    fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+}
+
+/// Struct to hold how many workers of a run may send requests, changed through
+/// --control-port while the run goes on
+struct Concurrency {
+    active: Mutex<u32>,
+    changed: Condvar,
+}
+
+impl Concurrency {
+    fn new(users: u32) -> Self {
+        Concurrency {
+            active: Mutex::new(users),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn get(&self) -> u32 {
+        *self.active.lock().unwrap()
+    }
+
+    fn set(&self, users: u32) {
+        *self.active.lock().unwrap() = users;
+        self.changed.notify_all();
+    }
+
+    /// Block worker `index` while it is above the concurrency, until `done`
+    /// says the run is over
+    fn wait(&self, index: u32, done: impl Fn() -> bool) {
+        let mut active = self.active.lock().unwrap();
+        // Checked now and then too, as the run can also end by time
+        while index >= *active && !done() {
+            active = self
+                .changed
+                .wait_timeout(active, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// A worker of a run that is still sending requests, counted in the shared
+/// number of running workers until it is dropped, even by a panic
+struct RunningWorker<'a>(&'a (Mutex<u32>, Condvar));
+
+impl Drop for RunningWorker<'_> {
+    fn drop(&mut self) {
+        let (running, finished) = self.0;
+        *running.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        finished.notify_all();
+    }
+}
+
+/// Struct to track a worker's consecutive failures, so it can pause for a
+/// cooldown before probing a failing server again, like resilient clients do
+struct CircuitBreaker {
+    threshold: Option<u32>,
+    cooldown: Duration,
+    consecutive_failures: u32,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: Option<u32>, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Record a failed request and pause for the cooldown when this trips the
+    /// breaker. Returns whether it tripped.
+    fn record_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        match self.threshold {
+            Some(threshold) if self.consecutive_failures >= threshold => {
+                self.consecutive_failures = 0;
+                thread::sleep(self.cooldown);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+}
+
+/// Struct to hold the token bucket of a worker with --max-rps-per-worker. A
+/// token is added every `interval` and the bucket holds at most one, so the
+/// worker never sends faster than the rate, however long it was idle.
+struct TokenBucket {
+    interval: Duration,
+    next: Instant, // When the next token is added, or was if in the past
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        TokenBucket {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: Instant::now(),
+        }
+    }
+
+    /// Take a token, waiting for one when the bucket is empty
+    fn take(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + self.interval;
+    }
+}
+
+/// Struct to hold what is loaded once before the first run: the files that
+/// options refer to and credentials that are fetched up front
+struct RunInputs {
+    body_pool: Vec<BodyPoolEntry>,
+    data_rows: Vec<HashMap<String, String>>,
+    oauth: Option<Arc<OAuthTokenSource>>,
+    signer: Option<Arc<sigv4::SigV4Signer>>,
+    targets: Vec<targets::RequestSpec>, // The request mix of --openapi or --sitemap
+    steps: Vec<scenario::Step>,         // The steps of --scenario
+    replay: Vec<replay::ReplayEntry>,   // The requests of --replay-log
+    replay_skipped: usize,              // Lines of --replay-log that could not be parsed
+    control: Option<TcpListener>,       // The socket of --control-port
+    body_template: Option<body_template::BodyTemplate>,
+    schema: Option<schema::ResponseSchema>, // The JSON Schema of --assert-schema
+    golden: Option<Vec<u8>>,                // The expected body of --golden
+    retry_on: HashMap<u16, Duration>,       // The statuses of --retry-on and their backoff
+    network_latency: Option<scenario::ThinkTime>, // The round trip time of --network-latency
+    #[cfg(feature = "otel")]
+    tracer: Option<otel::Tracer>, // The span exporter of --otel-endpoint
+}
+
+impl RunInputs {
+    fn load(args: &Cli) -> Result<Self, String> {
+        let body_pool = match &args.body_pool {
+            Some(path) => load_body_pool(path)?,
+            None => vec![],
+        };
+        let data_rows = match &args.data_file {
+            Some(path) => load_data_file(path)?,
+            None => vec![],
+        };
+        if let Some(percent) = args.timeout_jitter {
+            if !(0.0..100.0).contains(&percent) {
+                return Err(format!(
+                    "--timeout-jitter must be at least 0 and below 100, got {}",
+                    percent
+                ));
+            }
+        }
+        if let Some(address) = args.local_address {
+            // Connections would fail one by one otherwise, binding to an
+            // address the machine doesn't have
+            TcpListener::bind((address, 0)).map_err(|e| {
+                format!(
+                    "--local-address {} cannot be used on this machine: {}",
+                    address, e
+                )
+            })?;
+        }
+        if args.http3 && !cfg!(feature = "http3") {
+            return Err("--http3 requires building with --features http3 and RUSTFLAGS=\"--cfg reqwest_unstable\"".to_string());
+        }
+        if args.otel_endpoint.is_some() && !cfg!(feature = "otel") {
+            return Err("--otel-endpoint requires building with --features otel".to_string());
+        }
+        if let Some(tls_version) = args.tls_version {
+            build_client(args, &Arc::new(AtomicU64::new(0))).map_err(|e| {
+                format!(
+                    "--tls-version {} cannot be configured with the active TLS backend ({}); TLS 1.3 requires building with --features rustls",
+                    tls_version.name(),
+                    e
+                )
+            })?;
+        }
+        let body_template = match &args.body_template {
+            Some(path) => {
+                let mut rng = StdRng::from_os_rng();
+                let sample = TemplateContext {
+                    counter: 0,
+                    uuid: random_uuid(&mut rng),
+                    row: data_rows.first().cloned().unwrap_or_default(),
+                };
+                Some(body_template::BodyTemplate::load(
+                    path,
+                    &sample.template_data(&mut rng),
+                )?)
+            }
+            None => None,
+        };
+        if args.compress_body.is_some()
+            && args.body.is_none()
+            && body_pool.is_empty()
+            && body_template.is_none()
+        {
+            return Err(
+                "--compress-body requires a request body (--body, --body-pool or --body-template)"
+                    .to_string(),
+            );
+        }
+        let oauth = match &args.oauth_token_url {
+            Some(token_url) => Some(Arc::new(OAuthTokenSource::new(args, token_url)?)),
+            None => None,
+        };
+        let signer = if args.aws_sigv4 {
+            let region = args
+                .aws_region
+                .clone()
+                .or_else(|| std::env::var("AWS_REGION").ok())
+                .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+                .ok_or("--aws-sigv4 requires --aws-region or AWS_REGION")?;
+            let service = args
+                .aws_service
+                .as_deref()
+                .ok_or("--aws-sigv4 requires --aws-service")?;
+            Some(Arc::new(sigv4::SigV4Signer::new(
+                &region,
+                service,
+                args.aws_profile.as_deref(),
+            )?))
+        } else {
+            None
+        };
+        let targets = match (&args.openapi, &args.sitemap) {
+            (Some(path), _) => targets::load_openapi(path, args.url.as_deref())?,
+            (_, Some(url)) => targets::load_sitemap(url)?,
+            _ => vec![],
+        };
+        let steps = match &args.scenario {
+            Some(path) => scenario::load_scenario(path, args.url.as_deref())?,
+            None => vec![],
+        };
+        let replay = match &args.replay_log {
+            Some(path) => {
+                let base_url = args.url.as_deref().ok_or(
+                    "--replay-log requires --url, the base URL the logged paths are sent to",
+                )?;
+                replay::load_replay_log(path, args.log_format, base_url)?
+            }
+            None => replay::ReplayLog {
+                entries: vec![],
+                skipped: 0,
+            },
+        };
+        if let Some(percent) = args.inject_errors {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!(
+                    "--inject-errors must be between 0 and 100, got {}",
+                    percent
+                ));
+            }
+        }
+        if let Some(rate) = args.max_rps_per_worker {
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(format!(
+                    "--max-rps-per-worker must be a positive number, got {}",
+                    rate
+                ));
+            }
+        }
+        if let Some(rate) = args.log_sample {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!(
+                    "--log-sample must be between 0 and 1, got {}",
+                    rate
+                ));
+            }
+        }
+        if args.ns_timestamps && args.format != OutputFormat::Json {
+            return Err("--ns-timestamps requires --format json".to_string());
+        }
+        if !(args.change_tolerance >= 0.0 && args.change_tolerance.is_finite()) {
+            return Err(format!(
+                "--change-tolerance must be at least 0, got {}",
+                args.change_tolerance
+            ));
+        }
+        if let Some(multiplier) = args.rate_multiplier {
+            if !(multiplier > 0.0 && multiplier.is_finite()) {
+                return Err(format!(
+                    "--rate-multiplier must be above 0, got {}",
+                    multiplier
+                ));
+            }
+        }
+        if let Some(percent) = args.trim_mean {
+            if !(0.0..50.0).contains(&percent) {
+                return Err(format!(
+                    "--trim-mean must be at least 0 and below 50, got {}",
+                    percent
+                ));
+            }
+        }
+        if let Some(percent) = args.abandon_percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(format!(
+                    "--abandon-percent must be above 0 and at most 100, got {}",
+                    percent
+                ));
+            }
+        }
+        let control = args.control_port.map(control::bind).transpose()?;
+        if args.assert_schema.is_some() && args.read_body == ReadBody::Never {
+            return Err(
+                "--assert-schema needs the response bodies, drop --read-body never".to_string(),
+            );
+        }
+        if let Some(checksum) = &args.expected_checksum {
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "--expected-checksum must be a SHA-256 digest of 64 hex digits, got {}",
+                    checksum
+                ));
+            }
+        }
+        let hashing = args.expected_checksum.is_some() || args.detect_duplicate_checksum;
+        if hashing && args.read_body == ReadBody::Never {
+            return Err(
+                "--expected-checksum and --detect-duplicate-checksum need the response bodies, drop --read-body never"
+                    .to_string(),
+            );
+        }
+        let schema = args
+            .assert_schema
+            .as_deref()
+            .map(schema::ResponseSchema::load)
+            .transpose()?;
+        let retry_on = parse_retry_on(&args.retry_on)?;
+        if args.validate_json && args.read_body == ReadBody::Never {
+            return Err(
+                "--validate-json needs the response bodies, drop --read-body never".to_string(),
+            );
+        }
+        if args.golden.is_some() && args.read_body == ReadBody::Never {
+            return Err("--golden needs the response bodies, drop --read-body never".to_string());
+        }
+        let golden = args
+            .golden
+            .as_ref()
+            .map(|path| {
+                std::fs::read(path).map_err(|e| format!("cannot read golden file {}: {}", path, e))
+            })
+            .transpose()?;
+        Ok(RunInputs {
+            body_pool,
+            data_rows,
+            oauth,
+            signer,
+            targets,
+            steps,
+            replay: replay.entries,
+            replay_skipped: replay.skipped,
+            control,
+            body_template,
+            schema,
+            golden,
+            retry_on,
+            network_latency: args
+                .network_latency
+                .as_deref()
+                .map(parse_network_latency)
+                .transpose()?,
+            #[cfg(feature = "otel")]
+            tracer: args
+                .otel_endpoint
+                .as_deref()
+                .map(otel::Tracer::start)
+                .transpose()?,
+        })
+    }
+}
+
+/// Struct to hold why a run was stopped before all requests were sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AbortReason {
+    #[serde(with = "distributed::status_code")]
+    status: StatusCode,
+    request: u64, // Index of the request that received the status
+}
+
+/// Function to read the command line arguments, dropping a leading `run`: the
+/// options of an HTTP load test belong to the top-level command, so that
+/// `overloader run -u URL` parses as `overloader -u URL`
+fn command_line() -> Vec<std::ffi::OsString> {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    if args.get(1).is_some_and(|arg| arg == "run") {
+        args.remove(1);
+    }
+    args
+}
+
+fn main() {
+    let matches = Cli::command().get_matches_from(command_line());
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(Command::Profile(profile)) = &args.command {
+        if let Err(e) = profiles::run(profile) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::History(history)) = &args.command {
+        if let Err(e) = history::run(history) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(path) = args.config.clone() {
+        args = load_config(args, &matches, &path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    if let Some(name) = args.profile.clone() {
+        args = profiles::load_profile(&name)
+            .and_then(|options| {
+                merge_options(args, &matches, options, &format!("profile {}", name))
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+    }
+    if let Err(e) = check_option_combinations(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Some(name) = &args.save_profile {
+        match profiles::save_profile(name, profile_options(&args)) {
+            Ok(path) => println!("Saved profile {} to {}", name, path.display()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = args.request.clone() {
+        if let Err(e) = request_file::apply(&mut args, &path) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(Command::Ws(ws)) = &args.command {
+        websocket::run(ws, args.precision);
+        return;
+    }
+    if let Some(Command::Serve(serve)) = &args.command {
+        if let Err(e) = mock::run(serve) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let agent = match &args.command {
+        Some(Command::Agent(agent)) => Some(agent.listen.as_str()),
+        _ => args.agent.then_some(args.listen.as_str()),
+    };
+    if let Some(listen) = agent {
+        if let Err(e) = distributed::run_agent(listen) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // A scenario or request mix is reported under the file or sitemap it
+    // came from
+    let source = if args.repeatable_load {
+        Some(
+            mock::start(Duration::from_millis(args.mock_latency)).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }),
+        )
+    } else {
+        args.scenario
+            .clone()
+            .or_else(|| args.replay_log.clone())
+            .or_else(|| args.openapi.clone())
+            .or_else(|| args.sitemap.clone())
+    };
+    let urls = match (&args.url_file, source) {
+        (Some(path), _) => load_url_file(path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        (None, Some(source)) => vec![source],
+        (None, None) if args.url_a.is_some() => {
+            args.url_a.iter().chain(&args.url_b).cloned().collect()
+        }
+        (None, None) => vec![args.url.clone().unwrap_or_else(|| {
+            eprintln!("Error: no target URL given (use --url or set `url` in the config file)");
+            std::process::exit(1);
+        })],
+    };
+    if let Some(size) = args.generate_body.clone() {
+        let mut rng = match args.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        let (body, content_type) = parse_size(&size)
+            .and_then(|size| generate_body(size, args.generate_body_type, &mut rng))
+            .unwrap_or_else(|e| {
+                eprintln!("Error: --generate-body: {}", e);
+                std::process::exit(1);
+            });
+        // Given up front, the type isn't inferred from the body on every
+        // request
+        if !has_content_type(&args.headers) {
+            args.headers
+                .push(format!("{}: {}", CONTENT_TYPE, content_type));
+        }
+        args.body = Some(body);
+    }
+    if let Err(e) = check_concurrency(&args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if args.raise_fd_limit {
+        match fdlimit::raise() {
+            Ok((before, after)) if after > before && !args.quiet => println!(
+                "Raised the file descriptor limit from {} to {}",
+                before, after
+            ),
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+    if let Some(depth) = args.pipeline {
+        let mut headers = args.headers.clone();
+        if let Some(body) = args
+            .body
+            .as_deref()
+            .filter(|_| !has_content_type(&args.headers))
+        {
+            headers.push(format!("Content-Type: {}", infer_content_type(body)));
+        }
+        let method: Method = args.method.clone().into();
+        let connections = args.users.max(1) as u32;
+        let pipeline = pipeline::Pipeline {
+            url: &urls[0],
+            method: method.as_str(),
+            headers: &headers,
+            body: args.body.as_deref(),
+            connections,
+            // One request per user would not fill a single pipeline
+            requests: args
+                .requests
+                .unwrap_or(connections as u64 * depth as u64 * 10),
+            depth,
+            timeout: Duration::from_secs(args.timeout),
+            precision: args.precision,
+        };
+        if let Err(e) = pipeline::run(&pipeline) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.expect_continue {
+        let Some(body) = args.body.as_deref() else {
+            eprintln!(
+                "Error: --expect-continue needs a request body, use --body or --generate-body"
+            );
+            std::process::exit(1);
+        };
+        let mut headers = args.headers.clone();
+        if !has_content_type(&args.headers) {
+            headers.push(format!("Content-Type: {}", infer_content_type(body)));
+        }
+        let method: Method = args.method.clone().into();
+        let connections = args.users.max(1) as u32;
+        let expect = expect_continue::ExpectContinue {
+            url: &urls[0],
+            method: method.as_str(),
+            headers: &headers,
+            body,
+            connections,
+            requests: args.requests.unwrap_or(connections as u64),
+            timeout: Duration::from_secs(args.timeout),
+            precision: args.precision,
+        };
+        if let Err(e) = expect_continue::run(&expect) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.tui {
+        if std::io::stdout().is_terminal() {
+            // Errors printed during the run would scribble over the dashboard
+            args.quiet = true;
+        } else {
+            eprintln!("Warning: --tui needs a terminal, showing the usual output instead");
+            args.tui = false;
+        }
+    }
+
+    let inputs = RunInputs::load(&args).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+
+    if args.http3 {
+        let url = prewarm_url(&urls[0], &inputs);
+        match probe_connection_setup(&args, &url) {
+            Ok(setup) if setup.version == Version::HTTP_3 => println!(
+                "Connected to {} over HTTP/3: connection setup {} ms (first request {} ms, next request on the same connection {} ms)",
+                url,
+                setup.first_ms.saturating_sub(setup.next_ms),
+                setup.first_ms,
+                setup.next_ms
+            ),
+            // Plain http:// URLs are never sent over HTTP/3
+            result if args.http3_fallback => {
+                let e = result.map_or_else(|e| e, |setup| format!("it answered over {:?}", setup.version));
+                eprintln!(
+                    "Warning: {} does not answer over HTTP/3 ({}), falling back to HTTP/1.1 and HTTP/2",
+                    url, e
+                );
+                args.http3 = false;
+            }
+            result => {
+                let e = result.map_or_else(|e| e, |setup| format!("it answered over {:?}", setup.version));
+                eprintln!(
+                    "Error: {} does not answer over HTTP/3: {} (use --http3-fallback to fall back to HTTP/1.1 and HTTP/2)",
+                    url, e
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &args.replay_log {
+        println!(
+            "Replaying {} requests from {} ({} malformed {} skipped)",
+            inputs.replay.len(),
+            path,
+            inputs.replay_skipped,
+            if args.log_format == replay::LogFormat::Har {
+                "entries"
+            } else {
+                "lines"
+            }
+        );
+    }
+
+    if let Some(address) = inputs.control.as_ref().and_then(|l| l.local_addr().ok()) {
+        println!(
+            "Control socket listening on {} (commands: set-concurrency <N>, status, stop)",
+            address
+        );
+    }
+
+    if args.pause_on_error {
+        if std::io::stdin().is_terminal() {
+            eprintln!("Warning: --pause-on-error pauses the run; the reported duration and throughput, and --max-duration, include the time spent paused");
+        } else {
+            eprintln!("Warning: --pause-on-error is disabled, stdin is not a terminal");
+        }
+    }
+
+    if args.sample_response {
+        display_sample_response(&urls[0], &args, &inputs);
+    }
+
+    let failures = if args.url_a.is_some() {
+        run_ab_test(&urls[0], &urls[1], &args, &inputs)
+    } else if args.cache_test {
+        run_cache_test(&urls[0], &args, &inputs)
+    } else if args.sweep.is_empty() {
+        run_urls(urls, &args, &inputs)
+    } else {
+        run_sweep(&urls[0], &args, &inputs)
+    };
+    #[cfg(feature = "otel")]
+    if let Some(tracer) = &inputs.tracer {
+        tracer.finish();
+    }
+    if !args.quiet && has_gates(&args) {
+        display_banner(failures.is_empty(), &Palette::new(&args));
+    }
+    if !failures.is_empty() {
+        for failure in &failures {
+            eprintln!("FAILED: {}", failure);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Function to run a test once, on this machine or on the agents of
+/// --coordinator
+fn run_test(url: &str, args: &Cli, inputs: &RunInputs) -> LoadTestRun {
+    if args.coordinator {
+        distributed::run_on_agents(url, args).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    } else {
+        call_api(url.to_owned(), args, inputs).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        })
+    }
+}
+
+/// Function to benchmark every URL in turn and report each run, returning the
+/// gates that failed
+fn run_urls(urls: Vec<String>, args: &Cli, inputs: &RunInputs) -> Vec<String> {
+    let mut runs = vec![];
+    let mut failures = vec![];
+    for (index, url) in urls.into_iter().enumerate() {
+        if args.url_file.is_some() {
+            println!("\n=== {} ===", url);
+        }
+        let run = run_test(&url, args, inputs);
+        match &args.summary_only_on_change {
+            Some(path) => report_on_change(&run, &url, args, path).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }),
+            None => report_run(&run, &url, args),
+        }
+
+        if let Some(output) = &args.output {
+            save_results(&run, &url, args, output).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+        }
+        if let Some(db) = &args.db {
+            history::record(&run, &url, args, db).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+        }
+        if let Some(hdr_file) = &args.hdr_file {
+            save_hdr_histogram(&run, hdr_file).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+        }
+        if let Some(influx_file) = &args.influx_file {
+            // The points of every URL of --url-file go to the same file
+            save_influx(&run, influx_file, index > 0).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            });
+        }
+        if let Some(path) = &args.raw_latencies {
+            save_raw_latencies(&run, path, args.raw_latencies_timestamps, index > 0)
+                .unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                });
+        }
+
+        for failure in check_gates(&run, args) {
+            if args.url_file.is_some() {
+                failures.push(format!("{}: {}", url, failure));
+            } else {
+                failures.push(failure);
+            }
+        }
+        runs.push((url, run));
+    }
+    if args.url_file.is_some() {
+        display_url_summary(&runs, &SuccessCriteria::new(args), args.precision);
+    }
+    failures
+}
+
+/// Function to report a run of --summary-only-on-change: in full when its key
+/// metrics changed from the saved summary, which it then replaces, or in a
+/// single line when they didn't
+fn report_on_change(run: &LoadTestRun, url: &str, args: &Cli, path: &str) -> Result<(), String> {
+    let summary = change::RunSummary::new(run, &SuccessCriteria::new(args));
+    let changes = change::load(path)?
+        .get(url)
+        .map(|previous| summary.changes(previous, args.change_tolerance));
+    if changes.as_ref().is_some_and(|changes| changes.is_empty()) {
+        println!(
+            "No significant change from {} (tolerance {}%)",
+            path, args.change_tolerance
+        );
+        return Ok(());
+    }
+    report_run(run, url, args);
+    match changes {
+        Some(changes) => println!("\nChanged from {}: {}", path, changes.join(", ")),
+        None => println!("\nNo summary of {} in {} yet, saving this run", url, path),
+    }
+    change::save(path, url, summary)
+}
+
+/// Function to send the same load to both targets of --url-a and --url-b at
+/// once and report them side by side, returning the gates that failed
+fn run_ab_test(url_a: &str, url_b: &str, args: &Cli, inputs: &RunInputs) -> Vec<String> {
+    println!(
+        "A/B test: sending the same load to {} (A) and {} (B) at the same time",
+        url_a, url_b
+    );
+    let (a, b) = thread::scope(|scope| {
+        let a = spawn_named(scope, "loadster-ab-a".to_string(), || {
+            run_test(url_a, args, inputs)
+        })?;
+        let b = spawn_named(scope, "loadster-ab-b".to_string(), || {
+            run_test(url_b, args, inputs)
+        })?;
+        Ok::<_, String>((a.join().unwrap(), b.join().unwrap()))
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    });
+    let mut failures = vec![];
+    for (name, run) in [("A", &a), ("B", &b)] {
+        for failure in check_gates(run, args) {
+            failures.push(format!("{}: {}", name, failure));
+        }
+    }
+    let comparison = compare::Comparison::new(url_a, &a, url_b, &b, &SuccessCriteria::new(args));
+    comparison.display(args.precision);
+    if let Some(output) = &args.output {
+        comparison.save(args.format, output).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    failures
+}
+
+/// Function to run the cold and the warm phase of --cache-test and report them
+/// side by side, returning the gates that failed
+fn run_cache_test(url: &str, args: &Cli, inputs: &RunInputs) -> Vec<String> {
+    // The phases send the same `{counter}` values, so the warm phase repeats
+    // the URLs of the cold one; the nonce keeps them apart from the URLs of
+    // earlier runs, which a cache may still hold
+    let busted = cache_bust_url(url, rand::random());
+    let criteria = SuccessCriteria::new(args);
+    let mut phases = vec![];
+    let mut failures = vec![];
+    for phase in ["Cold", "Warm"] {
+        let run = run_test(&busted, args, inputs);
+        println!(
+            "Cache test: {} phase, {} requests, {:.*} req/s",
+            phase.to_lowercase(),
+            run.responses.len(),
+            args.precision,
+            throughput(run.responses.len(), run.elapsed)
+        );
+        for failure in check_gates(&run, args) {
+            failures.push(format!("{} phase: {}", phase.to_lowercase(), failure));
+        }
+        phases.push((phase, SweepRow::new(args.users, &run, &criteria)));
+    }
+    display_cache_test(url, &busted, &phases, args.precision);
+    failures
+}
+
+/// Function to add a query parameter to a URL that is unique to every request
+/// of a run and to the `nonce` of the run
+fn cache_bust_url(url: &str, nonce: u32) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, format!("#{}", fragment)),
+        None => (url, String::new()),
+    };
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}loadster-cache-bust={:08x}-{{counter}}{}",
+        url, separator, nonce, fragment
+    )
+}
+
+/// Function to append a path segment that no server should know to the path
+/// of a URL, for --inject-errors
+fn inject_error(url: &str) -> String {
+    let (path, rest) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    format!(
+        "{}/loadster-injected-error{}",
+        path.trim_end_matches('/'),
+        rest
+    )
+}
+
+/// Function to display the cold and the warm phase of --cache-test side by
+/// side, with how much faster the warm phase was
+fn display_cache_test(url: &str, busted: &str, phases: &[(&str, SweepRow)], precision: usize) {
+    println!("\nCache Test: {}", url);
+    println!("Requested URL\t{}", busted);
+    println!("Phase\tRequests\tFailed\tThroughput\tAvg Latency\tP50\tP95\tP99");
+    for (phase, row) in phases {
+        println!(
+            "{}\t{}\t{}\t{:.*} req/s\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            phase,
+            row.requests,
+            row.failed,
+            precision,
+            row.throughput,
+            precision,
+            row.avg_ms,
+            row.p50_ms,
+            row.p95_ms,
+            row.p99_ms
+        );
+    }
+    let [(_, cold), (_, warm)] = phases else {
+        return;
+    };
+    // Latencies under a millisecond count as one, so a cache hit that takes
+    // no measurable time doesn't divide by zero
+    let speedup = |cold: u64, warm: u64| cold.max(1) as f64 / warm.max(1) as f64;
+    println!(
+        "P50 Speedup (cold / warm)\t{:.*}x",
+        precision,
+        speedup(cold.p50_ms, warm.p50_ms)
+    );
+    println!(
+        "P95 Speedup (cold / warm)\t{:.*}x",
+        precision,
+        speedup(cold.p95_ms, warm.p95_ms)
+    );
+}
+
+/// Function to run the test once per concurrency level of --sweep and report
+/// the levels side by side, returning the gates that failed
+fn run_sweep(url: &str, args: &Cli, inputs: &RunInputs) -> Vec<String> {
+    let mut levels = vec![];
+    let mut failures = vec![];
+    for &users in &args.sweep {
+        let mut level_args = args.clone();
+        level_args.users = users;
+        let run = run_test(url, &level_args, inputs);
+        println!(
+            "Sweep: {} users, {} requests, {:.*} req/s",
+            users,
+            run.responses.len(),
+            args.precision,
+            throughput(run.responses.len(), run.elapsed)
+        );
+        for failure in check_gates(&run, &level_args) {
+            failures.push(format!("{} users: {}", users, failure));
+        }
+        levels.push((users, run));
+    }
+
+    let criteria = SuccessCriteria::new(args);
+    let rows: Vec<SweepRow> = levels
+        .iter()
+        .map(|(users, run)| SweepRow::new(*users, run, &criteria))
+        .collect();
+    display_sweep(url, &rows, args.precision);
+    if let Some(output) = &args.output {
+        save_sweep(&rows, url, args, output).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    }
+    failures
+}
+
+/// Function to merge the options of a config file into the parsed arguments.
+///
+/// Keys mirror the `Cli` field names (`snake_case` or `kebab-case`). Any flag
+/// given explicitly on the command line wins over the value from the file.
+fn load_config(cli: Cli, matches: &ArgMatches, path: &str) -> Result<Cli, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read config file {}: {}", path, e))?;
+    let file: serde_json::Map<String, Value> =
+        toml::from_str(&contents).map_err(|e| format!("invalid config file {}: {}", path, e))?;
+    merge_options(cli, matches, file, &format!("config file {}", path))
+}
+
+/// Function to apply the options of a config file or profile to the command
+/// line arguments, leaving the flags given on the command line as they are.
+/// `source` names the file or profile in errors.
+fn merge_options(
+    cli: Cli,
+    matches: &ArgMatches,
+    file: serde_json::Map<String, Value>,
+    source: &str,
+) -> Result<Cli, String> {
+    let mut merged = match serde_json::to_value(&cli) {
+        Ok(Value::Object(map)) => map,
+        _ => unreachable!("Cli always serializes to an object"),
+    };
+    let mut unknown_keys = vec![];
+    for (key, value) in file {
+        let key = key.replace('-', "_");
+        if !merged.contains_key(&key) {
+            unknown_keys.push(key);
+            continue;
+        }
+        if matches.value_source(&key) != Some(ValueSource::CommandLine) {
+            merged.insert(key, value);
+        }
+    }
+    if !unknown_keys.is_empty() {
+        return Err(format!(
+            "unknown key(s) in {}: {}",
+            source,
+            unknown_keys.join(", ")
+        ));
+    }
+
+    let mut config: Cli = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| format!("invalid {}: {}", source, e))?;
+    config.config = cli.config;
+    config.profile = cli.profile;
+    config.save_profile = cli.save_profile;
+    config.command = cli.command;
+    Ok(config)
+}
+
+/// Function to collect the options of a run that differ from their defaults,
+/// to save as a profile
+fn profile_options(args: &Cli) -> serde_json::Map<String, Value> {
+    // Without arguments, every option but the target has its default
+    let defaults = Cli::command()
+        .ignore_errors(true)
+        .get_matches_from(["overloader"]);
+    let defaults = Cli::from_arg_matches(&defaults).expect("every option has a default");
+    let (Ok(Value::Object(options)), Ok(Value::Object(defaults))) =
+        (serde_json::to_value(args), serde_json::to_value(defaults))
+    else {
+        unreachable!("Cli always serializes to an object")
+    };
+    options
+        .into_iter()
+        .filter(|(key, value)| !value.is_null() && defaults.get(key) != Some(value))
+        .collect()
+}
+
+/// Options that only work together with another option, as pairs of the
+/// option and the one it needs
+const REQUIRED_OPTIONS: &[(&str, &str)] = &[
+    ("histogram_scale", "histogram"),
+    ("ns_timestamps", "output"),
+    ("raw_latencies_timestamps", "raw_latencies"),
+    ("db_responses", "db"),
+    ("log_bias_slow", "log_sample"),
+    ("rate_multiplier", "preserve_timing"),
+    ("http3_fallback", "http3"),
+    ("oauth_scope", "oauth_token_url"),
+    ("generate_body_type", "generate_body"),
+    ("adaptive_thinktime", "target_p95"),
+    ("target_p95", "adaptive_thinktime"),
+    ("url_a", "url_b"),
+    ("url_b", "url_a"),
+    ("coordinator", "agents"),
+    ("change_tolerance", "summary_only_on_change"),
+    ("fail_fast", "scenario"),
+    ("preserve_timing", "replay_log"),
+    ("retry_on", "retries"),
+];
+
+/// Options that cannot be used together, as an option and those it conflicts
+/// with
+const CONFLICTING_OPTIONS: &[(&str, &[&str])] = &[
+    ("db", &["sweep", "cache_test"]),
+    ("http3", &["local_address", "tls_version"]),
+    ("generate_body", &["body", "body_pool", "body_template"]),
+    ("agent", &["coordinator"]),
+    ("body_pool", &["body"]),
+    ("url_file", &["url", "output"]),
+    ("hdr_file", &["url_file"]),
+    ("quiet", &["verbose"]),
+    ("openapi", &["url_file", "sitemap"]),
+    ("sitemap", &["url", "url_file"]),
+    ("pause_on_error", &["coordinator"]),
+    ("control_port", &["coordinator", "repeatable_load"]),
+    ("ignore_body", &["read_body"]),
+    ("body_template", &["body", "body_pool", "scenario"]),
+    ("bandwidth", &["ignore_body"]),
+    ("prewarm_connections", &["repeatable_load"]),
+    ("assert_schema", &["ignore_body"]),
+    ("assert_min_reuse", &["http3"]),
+    ("expected_checksum", &["ignore_body"]),
+    ("detect_duplicate_checksum", &["ignore_body"]),
+    ("golden", &["ignore_body"]),
+    ("summary_only_on_change", &["sweep", "cache_test"]),
+    ("validate_json", &["ignore_body"]),
+    ("jitter", &["repeatable_load", "preserve_timing"]),
+    ("max_rps_per_worker", &["repeatable_load"]),
+];
+
+/// Function to refuse options given without the option they need, or together
+/// with one they conflict with. clap only sees the command line, so this is
+/// checked once the config file and profile are merged in, counting the
+/// options that differ from their defaults as given.
+fn check_option_combinations(args: &Cli) -> Result<(), String> {
+    let given = profile_options(args);
+    let flag = |key: &str| format!("--{}", key.replace('_', "-"));
+    for (option, needed) in REQUIRED_OPTIONS {
+        if given.contains_key(*option) && !given.contains_key(*needed) {
+            return Err(format!("{} requires {}", flag(option), flag(needed)));
+        }
+    }
+    for (option, conflicts) in CONFLICTING_OPTIONS {
+        if !given.contains_key(*option) {
+            continue;
+        }
+        if let Some(other) = conflicts.iter().find(|other| given.contains_key(**other)) {
+            return Err(format!(
+                "{} cannot be used with {}",
+                flag(option),
+                flag(other)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The most concurrent users loadster starts without --allow-unsafe-concurrency
+const MAX_SAFE_USERS: i64 = 10_000;
+
+/// Function to refuse concurrency levels the thread-per-user model can't
+/// handle: every user is an OS thread with a stack of its own, so beyond a few
+/// thousand the process runs into the thread and memory limits of the system
+/// and fails in ways that don't point at the cause
+fn check_concurrency(args: &Cli) -> Result<(), String> {
+    let users = args
+        .sweep
+        .iter()
+        .chain([&args.users])
+        .map(|&users| users as i64)
         .max()
+        .unwrap_or_default()
+        // An A/B test runs the users twice, once per target
+        * if args.url_a.is_some() { 2 } else { 1 };
+    if users <= MAX_SAFE_USERS || args.allow_unsafe_concurrency {
+        return Ok(());
+    }
+    Err(format!(
+        "{} concurrent users would start as many threads, more than the {} loadster starts safely with one thread per user. \
+         Lower --users, spread the load over several machines with --coordinator, or pass --allow-unsafe-concurrency \
+         after raising the thread limits of the system (ulimit -u, kernel.threads-max); an async engine would scale further",
+        users, MAX_SAFE_USERS
+    ))
+}
+
+/// Function to parse --network-latency: a fixed round trip time in
+/// milliseconds (`80`), a uniform range (`50-150`) or the mean of an
+/// exponential distribution (`exp:80`)
+fn parse_network_latency(latency: &str) -> Result<scenario::ThinkTime, String> {
+    let invalid = || {
+        format!(
+            "invalid --network-latency {}, use MS, MIN-MAX or exp:MEAN, e.g. 80, 50-150 or exp:80",
+            latency
+        )
+    };
+    let ms = |value: &str| value.trim().parse::<u64>().map_err(|_| invalid());
+    if let Some(mean) = latency.strip_prefix("exp:") {
+        return Ok(scenario::ThinkTime::Exponential { mean: ms(mean)? });
+    }
+    match latency.split_once('-') {
+        Some((min, max)) => {
+            let (min, max) = (ms(min)?, ms(max)?);
+            if min > max {
+                return Err(format!(
+                    "--network-latency {} has a minimum above its maximum",
+                    latency
+                ));
+            }
+            Ok(scenario::ThinkTime::Uniform { min, max })
+        }
+        None => Ok(scenario::ThinkTime::Fixed(ms(latency)?)),
+    }
+}
+
+/// Function to parse the statuses of --retry-on, each with its backoff in
+/// milliseconds (`429:1000`) or without one (`502`)
+fn parse_retry_on(entries: &[String]) -> Result<HashMap<u16, Duration>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let invalid = || {
+                format!(
+                    "invalid --retry-on {}, use STATUS or STATUS:BACKOFF_MS, e.g. 503:100",
+                    entry
+                )
+            };
+            let (status, backoff) = match entry.split_once(':') {
+                Some((status, backoff)) => (status, Some(backoff)),
+                None => (entry.as_str(), None),
+            };
+            let status = status
+                .trim()
+                .parse()
+                .ok()
+                .filter(|status| (100..1000).contains(status))
+                .ok_or_else(invalid)?;
+            let backoff = match backoff {
+                Some(backoff) => backoff.trim().parse().map_err(|_| invalid())?,
+                None => 0,
+            };
+            Ok((status, Duration::from_millis(backoff)))
+        })
+        .collect()
+}
+
+/// Function to read how long a response asks the client to wait before
+/// retrying, from a `Retry-After` header of seconds or an HTTP date
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+/// Function to parse a size such as `1mb`, `500kb` or `2048` into bytes;
+/// k, m and g (with or without a trailing b or ib) are powers of 1024
+fn parse_size(size: &str) -> Result<usize, String> {
+    let lower = size.trim().to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("invalid size {}, use e.g. 512, 500kb or 1mb", size)),
+    };
+    let count: usize = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size {}, use e.g. 512, 500kb or 1mb", size))?;
+    count
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size {} is too large", size))
+}
+
+/// Function to generate a body of exactly `size` bytes for --generate-body,
+/// with its content type. Random fills draw from `rng`.
+fn generate_body(
+    size: usize,
+    fill: BodyFill,
+    rng: &mut StdRng,
+) -> Result<(String, &'static str), String> {
+    let mut random = |len: usize| -> String {
+        (0..len)
+            .map(|_| rng.sample(rand::distr::Alphanumeric) as char)
+            .collect()
+    };
+    match fill {
+        BodyFill::Random => Ok((random(size), "application/octet-stream")),
+        BodyFill::Zero => Ok(("\0".repeat(size), "application/octet-stream")),
+        BodyFill::Json => {
+            const PREFIX: &str = "{\"padding\":\"";
+            const SUFFIX: &str = "\"}";
+            let padding = size
+                .checked_sub(PREFIX.len() + SUFFIX.len())
+                .ok_or_else(|| {
+                    format!(
+                        "a JSON body needs at least {} bytes, got {}",
+                        PREFIX.len() + SUFFIX.len(),
+                        size
+                    )
+                })?;
+            Ok((
+                format!("{}{}{}", PREFIX, random(padding), SUFFIX),
+                "application/json",
+            ))
+        }
+    }
+}
+
+/// Function to load the URLs of a URL file, skipping blank lines and lines
+/// starting with `#`
+fn load_url_file(path: &str) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read URL file {}: {}", path, e))?;
+    let urls: Vec<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    if urls.is_empty() {
+        return Err(format!("URL file {} contains no URLs", path));
+    }
+    Ok(urls)
+}
+
+/// Function to load the request bodies of a body pool.
+///
+/// A directory contributes one body per file, in file name order; a regular
+/// file contributes one body per non-empty line.
+fn load_body_pool(path: &str) -> Result<Vec<BodyPoolEntry>, String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("cannot read body pool {}: {}", path, e))?;
+    let mut pool = vec![];
+    if metadata.is_dir() {
+        let mut files: Vec<_> = std::fs::read_dir(path)
+            .map_err(|e| format!("cannot read body pool {}: {}", path, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.is_file())
+            .collect();
+        files.sort();
+        for file in files {
+            let body = std::fs::read_to_string(&file)
+                .map_err(|e| format!("cannot read body pool file {}: {}", file.display(), e))?;
+            let name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            pool.push(BodyPoolEntry { name, body });
+        }
+    } else {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read body pool {}: {}", path, e))?;
+        for (number, line) in contents.lines().enumerate() {
+            if !line.trim().is_empty() {
+                pool.push(BodyPoolEntry {
+                    name: format!("line {}", number + 1),
+                    body: line.to_string(),
+                });
+            }
+        }
+    }
+    if pool.is_empty() {
+        return Err(format!("body pool {} contains no bodies", path));
+    }
+    Ok(pool)
+}
+
+/// Function to load the rows of a CSV data file, keyed by the column names in
+/// its first line. Fields are separated by commas and are not quoted.
+fn load_data_file(path: &str) -> Result<Vec<HashMap<String, String>>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read data file {}: {}", path, e))?;
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let columns: Vec<String> = match lines.next() {
+        Some(header) => header.split(',').map(|c| c.trim().to_string()).collect(),
+        None => return Err(format!("data file {} is empty", path)),
+    };
+
+    let mut rows = vec![];
+    for (number, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != columns.len() {
+            return Err(format!(
+                "data file {} row {} has {} fields, expected {}",
+                path,
+                number + 1,
+                fields.len(),
+                columns.len()
+            ));
+        }
+        let row = columns
+            .iter()
+            .cloned()
+            .zip(fields.iter().map(|f| f.trim().to_string()))
+            .collect();
+        rows.push(row);
+    }
+    if rows.is_empty() {
+        return Err(format!("data file {} contains no rows", path));
+    }
+    Ok(rows)
+}
+
+/// Function to generate a random (version 4) UUID
+fn random_uuid(rng: &mut StdRng) -> String {
+    let bits: u128 = rng.random();
+    let bits = (bits & !(0xf << 76) | (0x4 << 76)) & !(0x3 << 62) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Function to substitute the `{placeholder}`s of a template.
+///
+/// Supports `{counter}`, `{uuid}`, `{timestamp}` (seconds since UNIX_EPOCH)
+/// and the columns of the data file. Anything else in braces, such as a JSON
+/// object, is left untouched.
+fn render_template(template: &str, context: &TemplateContext) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let name_len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        let name = &after[..name_len];
+        let value = if name_len > 0 && after[name_len..].starts_with('}') {
+            match name {
+                "counter" => Some(context.counter.to_string()),
+                "uuid" => Some(context.uuid.clone()),
+                "timestamp" => Some(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                        .to_string(),
+                ),
+                _ => context.row.get(name).cloned(),
+            }
+        } else {
+            None
+        };
+        match value {
+            Some(value) => {
+                rendered.push_str(&value);
+                rest = &after[name_len + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Function to guess the content type of a request body
+fn infer_content_type(body: &str) -> &'static str {
+    let trimmed = body.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<Value>(body).is_ok()
+    {
+        "application/json"
+    } else if trimmed.starts_with('<') {
+        "application/xml"
+    } else if !body.is_empty()
+        && !body.contains(char::is_whitespace)
+        && body.split('&').all(|pair| pair.contains('='))
+    {
+        "application/x-www-form-urlencoded"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Function to build the HTTP client shared by all requests of a run.
+///
+/// Every new connection the client opens is counted in `connections_opened`.
+fn build_client(args: &Cli, connections_opened: &Arc<AtomicU64>) -> Result<Client, reqwest::Error> {
+    let connections_opened = Arc::clone(connections_opened);
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(args.timeout))
+        .tcp_nodelay(args.tcp_nodelay.is_on())
+        .connector_layer(MapRequestLayer::new(move |destination| {
+            connections_opened.fetch_add(1, Ordering::Relaxed);
+            destination
+        }));
+    if let Some(connections) = args.connections {
+        builder = builder.pool_max_idle_per_host(connections as usize);
+    }
+    match args.keepalive_timeout {
+        // Without idle connections in the pool, none is reused
+        Some(0) => builder = builder.pool_max_idle_per_host(0),
+        Some(seconds) => builder = builder.pool_idle_timeout(Duration::from_secs(seconds)),
+        None => {}
+    }
+    if let Some(address) = args.local_address {
+        builder = builder.local_address(address);
+    }
+    #[cfg(feature = "rustls")]
+    {
+        builder = builder.use_rustls_tls();
+    }
+    #[cfg(feature = "http3")]
+    if args.http3 {
+        builder = builder.http3_prior_knowledge();
+    }
+    if let Some(tls_version) = args.tls_version {
+        // A handshake that can't agree on the version fails the request
+        builder = builder
+            .min_tls_version(tls_version.version())
+            .max_tls_version(tls_version.version());
+    }
+    builder.build()
+}
+
+/// Function to check whether the headers given on the command line already
+/// set a content type
+fn has_content_type(headers: &[String]) -> bool {
+    has_header(headers, CONTENT_TYPE.as_str())
+}
+
+/// Function to check whether the headers given as `Name: value` include `name`
+fn has_header(headers: &[String], name: &str) -> bool {
+    headers.iter().any(|header| {
+        header
+            .split(':')
+            .next()
+            .is_some_and(|header| header.trim().eq_ignore_ascii_case(name))
+    })
+}
+
+/// Function to format an access token as an Authorization header
+fn bearer_header(token: &str) -> String {
+    format!("{}: Bearer {}", AUTHORIZATION, token)
+}
+
+/// Function to build a request from an already rendered URL, headers and body
+fn build_request(
+    client: &Client,
+    method: &Method,
+    url: &str,
+    headers: &[String],
+    body: Option<String>,
+    infer_body_type: bool,
+    compression: Option<BodyCompression>,
+) -> RequestBuilder {
+    let mut request = client
+        .request(method.clone(), url)
+        .header(USER_AGENT, "loadster 1.0.0");
+
+    for header in headers {
+        let parts: Vec<&str> = header.splitn(2, ':').collect();
+        if parts.len() == 2 {
+            request = request.header(parts[0], parts[1]);
+        }
+    }
+
+    if let Some(body) = body {
+        if infer_body_type {
+            request = request.header(CONTENT_TYPE, infer_content_type(&body));
+        }
+        match compression {
+            Some(compression) => {
+                request = request
+                    .header(CONTENT_ENCODING, compression.content_encoding())
+                    .body(compression.compress(body.as_bytes()));
+            }
+            None => request = request.body(body),
+        }
+    }
+    request
+}
+
+/// Struct to hold what a request is built from: the options and inputs of the
+/// run, and the URL and method of requests that don't come from a scenario,
+/// request mix or replay log
+struct RequestPlanner<'a> {
+    args: &'a Cli,
+    inputs: &'a RunInputs,
+    url: &'a str,
+    method: &'a Method,
+    has_content_type: bool, // Whether --headers sets the Content-Type
+}
+
+/// Struct to hold a request built by a planner, with the choices made for it
+struct PlannedRequest<'a> {
+    request: Request,
+    url: String, // With its placeholders filled in
+    method: &'a Method,
+    url_template: String, // What --url-template-report groups it under
+    pooled: Option<&'a BodyPoolEntry>,
+    injected: bool, // Whether --inject-errors sent it to a failing path
+    body_len: u64,  // Before compression
+    timeout: Duration,
+}
+
+impl<'a> RequestPlanner<'a> {
+    /// Build request `i`, or step `step` of scenario iteration `i`, drawing
+    /// its random choices from `rng`. `extra_headers` are added to the others.
+    fn plan(
+        &self,
+        client: &Client,
+        i: u64,
+        step: Option<usize>,
+        rng: &mut StdRng,
+        extra_headers: &[String],
+    ) -> Result<PlannedRequest<'a>, String> {
+        let args = self.args;
+        let body_pool = &self.inputs.body_pool;
+        let data_rows = &self.inputs.data_rows;
+        let pooled = if body_pool.is_empty() {
+            None
+        } else {
+            let index = match args.body_pool_order {
+                PoolOrder::Random => rng.random_range(0..body_pool.len()),
+                PoolOrder::RoundRobin => i as usize % body_pool.len(),
+            };
+            Some(&body_pool[index])
+        };
+        let context = TemplateContext {
+            counter: i,
+            uuid: random_uuid(rng),
+            row: if data_rows.is_empty() {
+                HashMap::new()
+            } else {
+                data_rows[i as usize % data_rows.len()].clone()
+            },
+        };
+        let timeout = match args.timeout_jitter {
+            Some(percent) => {
+                let base = args.timeout as f64 * 1000.0;
+                let spread = base * percent / 100.0;
+                Duration::from_millis(rng.random_range(base - spread..=base + spread) as u64)
+            }
+            None => Duration::from_secs(args.timeout),
+        };
+
+        let replay = &self.inputs.replay;
+        let target = if !replay.is_empty() {
+            Some(&replay[(i % replay.len() as u64) as usize].request)
+        } else {
+            let targets = &self.inputs.targets;
+            // Request indices are claimed from one shared counter, so in
+            // order they visit every URL once before any of them twice
+            (!targets.is_empty()).then(|| match args.url_order {
+                UrlOrder::Random => targets::pick(targets, rng),
+                UrlOrder::RoundRobin | UrlOrder::Sequential => {
+                    &targets[(i % targets.len() as u64) as usize]
+                }
+            })
+        };
+        let scenario_step = step.map(|step| &self.inputs.steps[step]);
+        let (url, method) = match (scenario_step, target) {
+            (Some(step), _) => (step.url.as_str(), &step.method),
+            (None, Some(target)) => (target.url.as_str(), &target.method),
+            (None, None) => (self.url, self.method),
+        };
+        // --url-template-report groups the requests of a mix or replay log by
+        // method and path, so operations that share a path tell apart
+        let url_template = match target {
+            Some(_) => format!("{} {}", method, url.split('?').next().unwrap_or(url)),
+            None => url.to_string(),
+        };
+        let url = render_template(url, &context);
+        let injected = args
+            .inject_errors
+            .is_some_and(|percent| rng.random_bool(percent / 100.0));
+        let url = if injected { inject_error(&url) } else { url };
+        let body = match (scenario_step, pooled, target) {
+            (Some(step), _, _) => step.body.as_ref(),
+            (None, Some(entry), _) => Some(&entry.body),
+            (None, None, Some(target)) => target.body.as_ref().or(args.body.as_ref()),
+            (None, None, None) => args.body.as_ref(),
+        }
+        .map(|body| render_template(body, &context));
+        let body = match (&self.inputs.body_template, body) {
+            (Some(template), None) => Some(
+                template
+                    .render(&context.template_data(rng))
+                    .map_err(|e| format!("cannot render --body-template: {}", e))?,
+            ),
+            (_, body) => body,
+        };
+        let step_headers = scenario_step.map_or(&[][..], |step| &step.headers);
+        let target_headers = match (scenario_step, target) {
+            (None, Some(target)) => &target.headers[..],
+            _ => &[],
+        };
+        // The headers of a replayed request replace those of --headers with
+        // the same name; the others apply to it as defaults
+        let mut headers: Vec<String> = args
+            .headers
+            .iter()
+            .filter(|header| {
+                let name = header.split(':').next().unwrap_or_default();
+                !has_header(target_headers, name.trim())
+            })
+            .chain(target_headers)
+            .chain(step_headers)
+            .map(|header| render_template(header, &context))
+            .chain(extra_headers.iter().cloned())
+            .collect();
+        if let Some(oauth) = &self.inputs.oauth {
+            headers.push(bearer_header(&oauth.access_token()));
+        }
+        if args.ignore_body && !has_header(&headers, RANGE.as_str()) {
+            headers.push(format!("{}: bytes=0-0", RANGE));
+        }
+        let body_len = body.as_ref().map_or(0, |body| body.len() as u64);
+        let request = build_request(
+            client,
+            method,
+            &url,
+            &headers,
+            body,
+            !self.has_content_type
+                && !has_content_type(step_headers)
+                && !has_content_type(target_headers),
+            args.compress_body,
+        )
+        .build()
+        .map_err(|e| e.to_string())?;
+        Ok(PlannedRequest {
+            request,
+            url,
+            method,
+            url_template,
+            pooled,
+            injected,
+            body_len,
+            timeout,
+        })
+    }
+}
+
+/// Function to send a single request, outside of the measured run, and print
+/// the full response so assertions and content types can be checked up front.
+/// It is built as the first request of the run is.
+fn display_sample_response(url: &str, args: &Cli, inputs: &RunInputs) {
+    // Bodies longer than this are cut off when printed
+    const MAX_PRINTED_BODY: usize = 4096;
+
+    let method = args.method.clone().into();
+    let planner = RequestPlanner {
+        args,
+        inputs,
+        url,
+        method: &method,
+        has_content_type: has_content_type(&args.headers),
+    };
+    let step = (!inputs.steps.is_empty()).then_some(0);
+    let mut rng = StdRng::from_os_rng();
+    let result = build_client(args, &Arc::new(AtomicU64::new(0)))
+        .map_err(|e| e.to_string())
+        .and_then(|client| {
+            let mut request = planner.plan(&client, 0, step, &mut rng, &[])?.request;
+            if let Some(signer) = &inputs.signer {
+                signer.sign(&mut request);
+            }
+            client.execute(request).map_err(|e| e.to_string())
+        });
+
+    println!("\nSample Response");
+    let res = match result {
+        Ok(res) => res,
+        Err(e) => {
+            println!("Request failed: {}", e);
+            return;
+        }
+    };
+    println!("{:?} {}", res.version(), res.status());
+    for (name, value) in res.headers() {
+        println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    match read_body(res, MAX_PRINTED_BODY as u64) {
+        Ok((body, bytes)) => {
+            println!("\n{}", String::from_utf8_lossy(&body));
+            if bytes > body.len() as u64 {
+                println!(
+                    "... (body truncated, showing {} of {} bytes)",
+                    body.len(),
+                    bytes
+                );
+            }
+        }
+        Err(e) => println!("\nerror reading response body: {}", e),
+    }
+}
+
+/// Struct to hold what belongs to a single worker of a run
+struct WorkerState {
+    index: u32,
+    breaker: CircuitBreaker,
+    client: Client, // Only this worker's with --repeatable-load, so its connection is its own
+    sent: u64,      // Number of requests this worker claimed
+    answered: u64,  // Number of responses this worker received
+    latency_total: Duration, // Latency of those responses, summed
+    bucket: Option<TokenBucket>, // The rate limit of --max-rps-per-worker
+}
+
+impl WorkerState {
+    /// The mean latency of the responses this worker received so far
+    fn mean_latency(&self) -> Option<Duration> {
+        (self.answered > 0).then(|| self.latency_total / self.answered as u32)
+    }
+}
+
+/// Struct to hold the fixed arrival times of --repeatable-load: the requests
+/// are due one `interval` after the other, request `i` sent by worker
+/// `i % users`
+struct Schedule {
+    interval: Duration,
+    users: u64,
+}
+
+impl Schedule {
+    /// Build the schedule of a run, leaving each worker twice the mock
+    /// server's latency between two of its requests
+    fn new(args: &Cli) -> Self {
+        let users = args.users.max(1) as u64;
+        Schedule {
+            interval: Duration::from_millis(args.mock_latency * 2) / users as u32,
+            users,
+        }
+    }
+
+    /// The time request `i` is due, from the start of the run
+    fn due(&self, i: u64) -> Duration {
+        Duration::from_nanos((self.interval.as_nanos() * i as u128) as u64)
+    }
+}
+
+/// Struct to hold what the workers of a run share
+struct RunState<'a> {
+    url: &'a str,
+    args: &'a Cli,
+    inputs: &'a RunInputs,
+    method: Method,
+    has_content_type: bool,
+    read_bodies: bool,
+    criteria: SuccessCriteria,
+    highlight: bool,
+    request_seed: u64, // Seeds the random number generator of every request
+    run_start: Instant,
+    total_requests: u64,
+    max_duration: Option<Duration>,
+    next_request: AtomicU64,
+    connection_slots: Option<Semaphore>,
+    data: Mutex<Vec<ResponseDetails>>,
+    samples: Mutex<RequestSamples>,
+    stop: AtomicBool,
+    max_duration_reached: AtomicBool,
+    aborted: Mutex<Option<AbortReason>>,
+    original_body_bytes: AtomicU64,
+    sent_body_bytes: AtomicU64,
+    retries: AtomicU64,
+    retry_classes: Mutex<BTreeMap<String, u64>>, // Resent attempts by the class of their status
+    breaker_trips: AtomicU64,
+    request_errors: AtomicU64,     // Requests that got no response
+    resource_exhausted: AtomicU64, // Those of them that failed for lack of file descriptors
+    fd_hint_shown: AtomicBool, // Whether the hint to raise the file descriptor limit was printed
+    abandoned: AtomicU64,      // Requests dropped mid-flight by --abandon-percent
+    timeouts: Mutex<Vec<u64>>,
+    pause: Option<PauseGate>,
+    schedule: Option<Schedule>,
+    schedule_lag: Mutex<Vec<u64>>,
+    think_time_us: AtomicU64, // The pause after each request of --adaptive-thinktime
+    abandoned_steps: Vec<AtomicU64>, // Times each --scenario step was skipped by --fail-fast
+    lock_wait_ns: Option<AtomicU64>, // Time spent waiting for the locks below, with --self-metrics
+    concurrency: Option<Concurrency>, // The workers that may send requests, with --control-port
+    error_bodies: Option<Mutex<HashMap<(u16, String), u64>>>, // Counts of distinct error bodies, with --capture-error-bodies
+    golden_mismatch: Mutex<Option<GoldenMismatch>>, // The first body that differed from --golden
+    malformed_json: Mutex<Vec<MalformedJson>>, // The first bodies that didn't parse with --validate-json
+    connection_requests: Mutex<HashMap<SocketAddr, u64>>, // Responses by the local address of their connection
+    connection_close_warned: AtomicBool, // Whether --warn-on-connection-close warned already
+}
+
+impl RunState<'_> {
+    /// Claim the index of the next request to send, or None once all requests
+    /// are claimed or the run is stopped. Requests in flight still finish.
+    ///
+    /// With --repeatable-load, the worker gets its next request of the
+    /// schedule instead, once it is due.
+    fn next_request(&self, worker: &mut WorkerState) -> Option<u64> {
+        if let Some(pause) = &self.pause {
+            pause.wait();
+        }
+        if let Some(concurrency) = &self.concurrency {
+            concurrency.wait(worker.index, || self.finished());
+        }
+        if self.stop.load(Ordering::SeqCst) {
+            return None;
+        }
+        if let Some(max_duration) = self.max_duration {
+            if self.run_start.elapsed() >= max_duration {
+                if !self.stop.swap(true, Ordering::SeqCst) {
+                    self.max_duration_reached.store(true, Ordering::SeqCst);
+                }
+                return None;
+            }
+        }
+        let i = match &self.schedule {
+            Some(schedule) => worker.index as u64 + worker.sent * schedule.users,
+            None => self.next_request.fetch_add(1, Ordering::SeqCst),
+        };
+        if i >= self.total_requests {
+            return None;
+        }
+        worker.sent += 1;
+        if let Some(due) = self.due(i) {
+            let due = self.run_start + due;
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            let lag = Instant::now().saturating_duration_since(due);
+            self.schedule_lag
+                .lock()
+                .unwrap()
+                .push(lag.as_micros() as u64);
+        }
+        Some(i)
+    }
+
+    /// The time request `i` is due, from the start of the run, when requests
+    /// follow a schedule (--repeatable-load or --preserve-timing)
+    fn due(&self, i: u64) -> Option<Duration> {
+        if let Some(schedule) = &self.schedule {
+            return Some(schedule.due(i));
+        }
+        let replay = &self.inputs.replay;
+        if !self.args.preserve_timing || replay.is_empty() {
+            return None;
+        }
+        // Every pass over the log starts where the one before ended
+        let len = replay.len() as u64;
+        let pass = (i / len) as u32;
+        let offset = replay[(i % len) as usize].offset + replay[replay.len() - 1].offset * pass;
+        Some(offset.div_f64(self.args.rate_multiplier.unwrap_or(1.0)))
+    }
+
+    /// Whether the run is stopped, out of time or all of its requests are
+    /// claimed
+    fn finished(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+            || self.next_request.load(Ordering::SeqCst) >= self.total_requests
+            || self
+                .max_duration
+                .is_some_and(|max_duration| self.run_start.elapsed() >= max_duration)
+    }
+
+    /// Carry out a command of the control socket, returning the reply
+    fn control(&self, command: control::ControlCommand) -> String {
+        let Some(concurrency) = &self.concurrency else {
+            return "error: the run has no control socket".to_string();
+        };
+        match command {
+            control::ControlCommand::SetConcurrency(users) => {
+                concurrency.set(users);
+                println!("Control: concurrency set to {}", users);
+                format!("ok concurrency {}", users)
+            }
+            control::ControlCommand::Status => format!(
+                "ok concurrency {} responses {}",
+                concurrency.get(),
+                self.lock(&self.data).len()
+            ),
+            control::ControlCommand::Stop => {
+                self.stop.store(true, Ordering::SeqCst);
+                // Parked workers notice the stop right away
+                concurrency.set(concurrency.get());
+                println!("Control: stopping the run");
+                "ok stopping".to_string()
+            }
+        }
+    }
+
+    /// Count a request that got no response, telling the requests that ran out
+    /// of file descriptors apart and giving a hint the first time one does
+    fn request_failed(&self, e: &reqwest::Error) {
+        self.request_errors.fetch_add(1, Ordering::Relaxed);
+        if fdlimit::is_exhausted(e) {
+            self.resource_exhausted.fetch_add(1, Ordering::Relaxed);
+            if !self.fd_hint_shown.swap(true, Ordering::Relaxed) {
+                eprintln!("{}", fdlimit::hint(self.args.users));
+            }
+        }
+    }
+
+    /// Send request `i` and drop it after `after`, closing its connection like
+    /// a client that disconnects. A response head that arrives first is
+    /// dropped without its body.
+    fn abandon(&self, i: u64, client: &Client, request: &Request, after: Duration) {
+        let mut attempt = request
+            .try_clone()
+            .expect("request bodies are held in memory");
+        *attempt.timeout_mut() = Some(after.max(Duration::from_millis(1)));
+        if let Some(signer) = &self.inputs.signer {
+            signer.sign(&mut attempt);
+        }
+        match client.execute(attempt) {
+            Err(e) if !e.is_timeout() => {
+                if !self.args.quiet {
+                    eprintln!("Request failed: {}", e);
+                }
+                self.request_failed(&e);
+            }
+            _ => {
+                self.abandoned.fetch_add(1, Ordering::Relaxed);
+                if self.args.verbose {
+                    println!("i: {} ,Abandoned after: {} ms", i, after.as_millis());
+                }
+            }
+        }
+    }
+
+    /// Send the steps of --scenario iteration `i` in order from `worker`,
+    /// pausing for the think time of each step before the next one. With
+    /// --fail-fast, a failed step abandons the steps after it.
+    fn run_scenario(&self, i: u64, worker: &mut WorkerState) {
+        // Think times are drawn apart from the requests, so --seed still
+        // reproduces both
+        let mut rng = StdRng::seed_from_u64(!self.request_seed.wrapping_add(i));
+        let steps = &self.inputs.steps;
+        for step in 0..steps.len() {
+            if step > 0 {
+                if let Some(think_time) = steps[step - 1].think_time {
+                    // The pause is not part of any request, so it is not
+                    // measured in their latency
+                    thread::sleep(think_time.sample(&mut rng));
+                }
+            }
+            if self.stop.load(Ordering::SeqCst) {
+                return;
+            }
+            if !self.send(i, Some(step), worker) && self.args.fail_fast {
+                for abandoned in &self.abandoned_steps[step + 1..] {
+                    abandoned.fetch_add(1, Ordering::Relaxed);
+                }
+                if self.args.verbose {
+                    println!("i: {} ,Scenario abandoned after step: {}", i, step + 1);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Send request `i` from `worker`, or step `step` of scenario iteration
+    /// `i`, and record its response. Returns whether the response meets the
+    /// success criteria.
+    fn send(&self, i: u64, step: Option<usize>, worker: &mut WorkerState) -> bool {
+        let args = self.args;
+        let quiet = args.quiet;
+        let verbose = args.verbose;
+        let compression = args.compress_body;
+
+        // Every request draws from its own generator, so --seed reproduces
+        // each request no matter which worker sends it
+        let mut rng = StdRng::seed_from_u64(self.request_seed.wrapping_add(i));
+        #[cfg(feature = "otel")]
+        let span = self
+            .inputs
+            .tracer
+            .as_ref()
+            .map(|_| otel::SpanContext::new());
+        #[cfg(feature = "otel")]
+        let traceparent: Vec<String> = span.iter().map(|span| span.traceparent()).collect();
+        #[cfg(not(feature = "otel"))]
+        let traceparent: Vec<String> = vec![];
+        let planner = RequestPlanner {
+            args,
+            inputs: self.inputs,
+            url: self.url,
+            method: &self.method,
+            has_content_type: self.has_content_type,
+        };
+        let planned = match planner.plan(&worker.client, i, step, &mut rng, &traceparent) {
+            Ok(planned) => planned,
+            Err(e) => {
+                if !quiet {
+                    eprintln!("Request failed: {}", e);
+                }
+                self.request_errors.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+        let PlannedRequest {
+            request,
+            url,
+            method,
+            url_template,
+            pooled,
+            injected,
+            body_len,
+            timeout,
+        } = planned;
+        if compression.is_some() {
+            let sent = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map_or(0, |body| body.len() as u64);
+            self.original_body_bytes
+                .fetch_add(body_len, Ordering::Relaxed);
+            self.sent_body_bytes.fetch_add(sent, Ordering::Relaxed);
+            if verbose {
+                println!("i: {} ,Body compressed: {} -> {} bytes", i, body_len, sent);
+            }
+        }
+        // Every attempt sent counts against the rate, retries included; the
+        // first waits before it holds a connection slot
+        if let Some(bucket) = &mut worker.bucket {
+            bucket.take();
+        }
+        let _connection_slot = self.connection_slots.as_ref().map(|slots| slots.acquire());
+        // The simulated round trip holds on to the connection, but isn't
+        // counted in the request time
+        let round_trip = self
+            .inputs
+            .network_latency
+            .map_or(Duration::ZERO, |latency| latency.sample(&mut rng));
+        if let Some(percent) = args.abandon_percent {
+            if rng.random_bool(percent / 100.0) {
+                // Dropped somewhere within a typical response time, or once
+                // the head arrives before there is one
+                let after = worker.mean_latency().map_or(timeout, |mean| {
+                    mean.mul_f64(rng.random::<f64>()).min(timeout)
+                });
+                self.abandon(i, &worker.client, &request, after);
+                return false;
+            }
+        }
+        // Failed attempts are resent as they are; only the attempt that
+        // got a response, and no status of --retry-on, is measured
+        let mut attempts = 0;
+        let (res, start, ttfb) = loop {
+            let mut attempt = request
+                .try_clone()
+                .expect("request bodies are held in memory");
+            // try_clone() doesn't copy the timeout
+            *attempt.timeout_mut() = Some(timeout);
+            if let (Some(bucket), true) = (&mut worker.bucket, attempts > 0) {
+                bucket.take();
+            }
+            // Signed last, the signature covers the time the request is sent
+            if !round_trip.is_zero() {
+                thread::sleep(round_trip / 2);
+            }
+            if let Some(signer) = &self.inputs.signer {
+                signer.sign(&mut attempt);
+            }
+            let start = Instant::now();
+            let res = worker.client.execute(attempt);
+            // Attempts without a response are resent at once, statuses of
+            // --retry-on after their backoff or the Retry-After of the server,
+            // never longer than the timeout
+            let retry = match &res {
+                Err(e) => Some(("err".to_string(), Duration::ZERO, e.to_string())),
+                Ok(res) => self
+                    .inputs
+                    .retry_on
+                    .get(&res.status().as_u16())
+                    .map(|backoff| {
+                        let status = res.status();
+                        let wait = match status {
+                            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                                retry_after(res).unwrap_or(*backoff)
+                            }
+                            _ => *backoff,
+                        };
+                        (
+                            format!("{}xx", status.as_u16() / 100),
+                            wait.min(timeout),
+                            format!("status {}", status),
+                        )
+                    }),
+            };
+            match retry {
+                Some((class, wait, reason))
+                    if attempts < args.retries && !self.stop.load(Ordering::SeqCst) =>
+                {
+                    attempts += 1;
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    *self.lock(&self.retry_classes).entry(class).or_default() += 1;
+                    if verbose {
+                        println!(
+                            "i: {} ,Retry {} of {} after: {}",
+                            i, attempts, args.retries, reason
+                        );
+                    }
+                    drop(res);
+                    thread::sleep(wait);
+                }
+                _ => {
+                    // execute() returns once the head arrived; the body is
+                    // read afterwards
+                    let ttfb = start.elapsed();
+                    break (res, start, ttfb);
+                }
+            }
+        };
+        let started_at_offset = start.duration_since(self.run_start);
+        let started_at_offset_ms = started_at_offset.as_millis() as u64;
+        let started_at_offset_ns = started_at_offset.as_nanos() as u64;
+
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                if e.is_timeout() {
+                    self.timeouts
+                        .lock()
+                        .unwrap()
+                        .push(timeout.as_millis() as u64);
+                    if !quiet {
+                        eprintln!(
+                            "Request failed: {} (timeout: {} ms)",
+                            e,
+                            timeout.as_millis()
+                        );
+                    }
+                } else if !quiet {
+                    eprintln!("Request failed: {}", e);
+                }
+                self.pause_on_error(i, &request, &format!("Request failed: {}", e));
+                self.request_failed(&e);
+                self.record_outcome(i, worker, false);
+                #[cfg(feature = "otel")]
+                if let (Some(tracer), Some(span)) = (&self.inputs.tracer, span) {
+                    tracer.record(
+                        span,
+                        method.as_str(),
+                        &url,
+                        Err(e.to_string()),
+                        start.elapsed(),
+                    );
+                }
+                return false;
+            }
+        };
+        let status = res.status();
+        let version = format!("{:?}", res.version());
+        // The local address tells the connections apart, as long as the
+        // system doesn't give a closed connection's port to a new one
+        if let Some(info) = res.extensions().get::<HttpInfo>() {
+            *self
+                .lock(&self.connection_requests)
+                .entry(info.local_addr())
+                .or_default() += 1;
+        }
+        let connection_close = res
+            .headers()
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|option| option.trim().eq_ignore_ascii_case("close"));
+        if connection_close
+            && args.warn_on_connection_close
+            && !self.connection_close_warned.swap(true, Ordering::Relaxed)
+        {
+            eprintln!(
+                "Warning: the server closed the connection of request {} with Connection: close; connections it closes must be opened again for the next requests",
+                i
+            );
+        }
+        let remote_ip = if args.split_by_ip {
+            res.remote_addr().map(|address| address.ip())
+        } else {
+            None
+        };
+        // The response of a failure is printed in full, head and body
+        let accepted = self.criteria.accepts_status(status);
+        let pausing = self.pause.is_some() && !accepted;
+        let head = pausing.then(|| format_response_head(&res));
+        // HEAD responses never carry a body, even when they advertise a
+        // Content-Length. Unread bodies are sized by their Content-Length, so
+        // on-assert reads those without one, e.g. chunked, to count them.
+        let sizing = res.content_length().is_none()
+            && args.read_body == ReadBody::OnAssert
+            && !args.ignore_body;
+        let capturing = self.error_bodies.is_some() && !accepted;
+        // --bandwidth throttles the reading of every body, and successful
+        // bodies are checked against --assert-schema
+        let validating = self.inputs.schema.is_some() && accepted;
+        let hashing =
+            (args.expected_checksum.is_some() || args.detect_duplicate_checksum) && accepted;
+        let comparing = self.inputs.golden.is_some() && accepted;
+        let parsing = args.validate_json && accepted;
+        let body_read = (self.read_bodies
+            || pausing
+            || capturing
+            || validating
+            || hashing
+            || comparing
+            || parsing
+            || sizing
+            || args.bandwidth.is_some())
+            && method != Method::HEAD;
+        let json_content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().contains("json"));
+        let mut checksum = None;
+        let read = if method == Method::HEAD {
+            Ok((vec![], 0))
+        } else if body_read {
+            let mut reader = HashingReader::new(res, hashing);
+            let read = match args.bandwidth {
+                Some(rate) => {
+                    read_body(ThrottledReader::new(&mut reader, rate), args.max_body_size)
+                }
+                None => read_body(&mut reader, args.max_body_size),
+            };
+            checksum = reader.checksum();
+            read
+        } else if args.ignore_body {
+            Ok((vec![], full_length(&res)))
+        } else {
+            Ok((vec![], res.content_length().unwrap_or(0)))
+        };
+        let (body, bytes) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                if !quiet {
+                    eprintln!("Request failed: error reading response body: {}", e);
+                }
+                self.request_errors.fetch_add(1, Ordering::Relaxed);
+                self.record_outcome(i, worker, false);
+                return false;
+            }
+        };
+        let duration = start.elapsed();
+        if !round_trip.is_zero() {
+            thread::sleep(round_trip / 2);
+        }
+        #[cfg(feature = "otel")]
+        if let (Some(tracer), Some(span)) = (&self.inputs.tracer, span) {
+            tracer.record(span, method.as_str(), &url, Ok(status.as_u16()), duration);
+        }
+        worker.answered += 1;
+        worker.latency_total += duration;
+        if let (Some(rate), false) = (args.log_sample, quiet) {
+            // Weighted against the average so far, the rate holds on average
+            let weight = match worker.mean_latency() {
+                Some(mean) if args.log_bias_slow && !mean.is_zero() => {
+                    duration.as_secs_f64() / mean.as_secs_f64()
+                }
+                _ => 1.0,
+            };
+            if rng.random_bool((rate * weight).min(1.0)) {
+                println!(
+                    "Sampled request: i: {} ,{} {} took {} ms, Status: {}",
+                    i,
+                    method,
+                    url,
+                    duration.as_millis(),
+                    status
+                );
+            }
+        }
+        if let Some(threshold) = args.warn_slow {
+            let time = duration.as_millis() as u64;
+            if time > threshold && !quiet {
+                let warning = format!(
+                    "Slow request: i: {} ,{} took {} ms (threshold {} ms), Status: {}",
+                    i, url, time, threshold, status
+                );
+                if self.highlight {
+                    eprintln!("\x1b[1;33m{}\x1b[0m", warning);
+                } else {
+                    eprintln!("{}", warning);
+                }
+            }
+        }
+        if args.abort_on_status.contains(&status.as_u16())
+            && !self.stop.swap(true, Ordering::SeqCst)
+        {
+            *self.aborted.lock().unwrap() = Some(AbortReason { status, request: i });
+        }
+        if verbose {
+            println!("i: {} ,Status: {}", i, status);
+            if let Some(entry) = pooled {
+                println!("i: {} ,Body pool entry: {}", i, entry.name);
+            }
+            if body_read && bytes > body.len() as u64 {
+                println!(
+                    "i: {} ,Body truncated: read {} of {} bytes",
+                    i,
+                    body.len(),
+                    bytes
+                );
+            }
+        }
+        if let (Some(error_bodies), true) = (&self.error_bodies, capturing) {
+            let text = String::from_utf8_lossy(&body).trim().to_string();
+            *self
+                .lock(error_bodies)
+                .entry((status.as_u16(), text))
+                .or_default() += 1;
+        }
+        let schema = match (&self.inputs.schema, validating && body_read) {
+            (Some(schema), true) => Some(schema.check(&body, json_content_type)),
+            _ => None,
+        };
+        if let (true, Some(schema::SchemaCheck::Violations(violations))) = (verbose, &schema) {
+            println!("i: {} ,Schema violations: {}", i, violations.join(", "));
+        }
+        // A body beyond --max-body-size matches when its size does and the
+        // part kept is the start of the golden file
+        let golden = match (&self.inputs.golden, comparing && body_read) {
+            (Some(golden), true) => {
+                let matched = bytes == golden.len() as u64 && golden.starts_with(&body);
+                if !matched {
+                    let mut first = self.lock(&self.golden_mismatch);
+                    if first.is_none() {
+                        *first = Some(GoldenMismatch::new(golden, &body, i));
+                    }
+                }
+                Some(matched)
+            }
+            _ => None,
+        };
+        if let (true, Some(false)) = (verbose, golden) {
+            println!("i: {} ,Body differs from the golden file", i);
+        }
+        // Empty bodies have nothing to parse, and those cut off at
+        // --max-body-size would never parse
+        let json_valid = (parsing && body_read && !body.is_empty() && bytes == body.len() as u64)
+            .then(
+                || match serde_json::from_slice::<serde::de::IgnoredAny>(&body) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        if verbose {
+                            println!("i: {} ,Body is not valid JSON: {}", i, e);
+                        }
+                        let mut malformed = self.lock(&self.malformed_json);
+                        if malformed.len() < MalformedJson::MAX_SAMPLES {
+                            malformed.push(MalformedJson::new(&body, e, i));
+                        }
+                        false
+                    }
+                },
+            );
+        if let Some(head) = head {
+            let response = format!("{}\n\n{}", head, String::from_utf8_lossy(&body));
+            self.pause_on_error(i, &request, &response);
+        }
+        let mut data = self.lock(&self.data);
+        let response_details = ResponseDetails {
+            status,
+            time: duration.as_millis() as u64,
+            ttfb: ttfb.as_millis() as u64,
+            started_at_offset_ms,
+            started_at_offset_ns,
+            ended_at_offset_ns: started_at_offset_ns + duration.as_nanos() as u64,
+            bytes,
+            bytes_read: body.len() as u64,
+            body_read,
+            url,
+            method: method.to_string(),
+            url_template,
+            worker: worker.index,
+            step,
+            schema,
+            remote_ip,
+            version,
+            checksum,
+            golden,
+            json_valid,
+            connection_close,
+            injected,
+        };
+        let succeeded = is_successful(&response_details, &self.criteria);
+        self.lock(&self.samples).record(&response_details);
+        data.push(response_details);
+        drop(data);
+        self.record_outcome(i, worker, succeeded);
+        succeeded
+    }
+
+    /// Count the final outcome of request `i` towards the circuit breaker of
+    /// `worker`, after its retries and under the success criteria
+    fn record_outcome(&self, i: u64, worker: &mut WorkerState, succeeded: bool) {
+        if succeeded {
+            worker.breaker.record_success();
+        } else if worker.breaker.record_failure() {
+            self.breaker_trips.fetch_add(1, Ordering::Relaxed);
+            if self.args.verbose {
+                println!(
+                    "i: {} ,Circuit breaker tripped, paused for {} ms",
+                    i,
+                    worker.breaker.cooldown.as_millis()
+                );
+            }
+        }
+    }
+
+    /// Lock state shared by the workers, adding the time spent waiting for
+    /// it to the lock wait of --self-metrics
+    fn lock<'m, T>(&self, mutex: &'m Mutex<T>) -> MutexGuard<'m, T> {
+        let Some(lock_wait_ns) = &self.lock_wait_ns else {
+            return mutex.lock().unwrap();
+        };
+        let start = Instant::now();
+        let guard = mutex.lock().unwrap();
+        lock_wait_ns.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        guard
+    }
+
+    /// Print a failed request and its response, then hold every worker until
+    /// the user chooses to continue, stop pausing or abort the run
+    fn pause_on_error(&self, i: u64, request: &Request, response: &str) {
+        let Some(pause) = &self.pause else {
+            return;
+        };
+        if self.stop.load(Ordering::SeqCst) || !pause.pause() {
+            return;
+        }
+        println!("\nRequest {} failed", i);
+        println!("{} {}", request.method(), request.url());
+        for (name, value) in request.headers() {
+            println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+        }
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            println!("\n{}", String::from_utf8_lossy(body));
+        }
+        println!("\n{}", response);
+        print!("\nPaused: press Enter to continue, s and Enter to stop pausing, q and Enter to abort the run: ");
+        let _ = std::io::stdout().flush();
+        let mut answer = String::new();
+        // EOF on stdin is taken as an abort
+        let read = std::io::stdin().read_line(&mut answer).unwrap_or(0);
+        match answer.trim() {
+            _ if read == 0 => self.stop.store(true, Ordering::SeqCst),
+            "q" => self.stop.store(true, Ordering::SeqCst),
+            "s" => pause.enabled.store(false, Ordering::SeqCst),
+            _ => {}
+        }
+        pause.resume();
+    }
+}
+
+/// Function to format the status line and headers of a response
+fn format_response_head(res: &Response) -> String {
+    let mut head = format!("{:?} {}", res.version(), res.status());
+    for (name, value) in res.headers() {
+        head.push_str(&format!(
+            "\n{}: {}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    head
+}
+
+/// Function to pick the URL --prewarm-connections connects to: the first URL
+/// of the scenario, request mix or replay log, else the target URL, with its
+/// placeholders filled in as for the first request
+fn prewarm_url(url: &str, inputs: &RunInputs) -> String {
+    let url = inputs
+        .steps
+        .first()
+        .map(|step| step.url.as_str())
+        .or_else(|| inputs.targets.first().map(|target| target.url.as_str()))
+        .or_else(|| {
+            inputs
+                .replay
+                .first()
+                .map(|entry| entry.request.url.as_str())
+        })
+        .unwrap_or(url);
+    let context = TemplateContext {
+        counter: 0,
+        uuid: random_uuid(&mut StdRng::from_os_rng()),
+        row: inputs.data_rows.first().cloned().unwrap_or_default(),
+    };
+    render_template(url, &context)
+}
+
+/// Struct to hold how long the first request on a new connection took,
+/// compared to the next request on the same connection
+struct ConnectionSetup {
+    first_ms: u64,
+    next_ms: u64,
+    version: Version, // The protocol the server answered with
+}
+
+/// Function to send two HEAD requests to `url` one after the other from a
+/// new client. The first opens the connection and the second reuses it, so
+/// the difference between them is the connection setup: the QUIC handshake
+/// over HTTP/3, the TCP and TLS handshakes otherwise.
+fn probe_connection_setup(args: &Cli, url: &str) -> Result<ConnectionSetup, String> {
+    let client = build_client(args, &Arc::new(AtomicU64::new(0))).map_err(|e| e.to_string())?;
+    let mut times = vec![];
+    let mut version = Version::HTTP_11;
+    for _ in 0..2 {
+        let start = Instant::now();
+        let res = client
+            .head(url)
+            .header(USER_AGENT, "loadster 1.0.0")
+            .send()
+            .map_err(|e| match std::error::Error::source(&e) {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            })?;
+        times.push(start.elapsed().as_millis() as u64);
+        version = res.version();
+    }
+    Ok(ConnectionSetup {
+        first_ms: times[0],
+        next_ms: times[1],
+        version,
+    })
+}
+
+/// Function to start a thread of a scope under a name, e.g.
+/// `loadster-worker-3`, which profilers, thread dumps and panic messages show.
+/// Fails when the system refuses another thread, as it does once a run hits
+/// its thread or memory limits.
+fn spawn_named<'scope, T: Send + 'scope>(
+    scope: &'scope thread::Scope<'scope, '_>,
+    name: String,
+    f: impl FnOnce() -> T + Send + 'scope,
+) -> Result<thread::ScopedJoinHandle<'scope, T>, String> {
+    thread::Builder::new()
+        .name(name.clone())
+        .spawn_scoped(scope, f)
+        .map_err(|e| format!("cannot start thread {}: {}", name, e))
+}
+
+/// Function to open `count` connections to the host of `url` by sending as
+/// many HEAD requests at once. The connections stay idle in the pool of
+/// `client`, for the workers to pick up.
+fn prewarm_connections(client: &Client, url: &str, count: u32) {
+    // The threads wait for the gate to open, once all of them are started or
+    // the system refuses another one
+    let gate = RwLock::new(());
+    let failures = AtomicU64::new(0);
+    let mut first_error = Mutex::new(None);
+    thread::scope(|scope| {
+        let opening = gate.write().unwrap();
+        for i in 0..count {
+            let spawned = spawn_named(scope, format!("loadster-prewarm-{}", i), || {
+                drop(gate.read().unwrap());
+                let sent = client.head(url).header(USER_AGENT, "loadster 1.0.0").send();
+                if let Err(e) = sent {
+                    failures.fetch_add(1, Ordering::Relaxed);
+                    first_error.lock().unwrap().get_or_insert(e.to_string());
+                }
+            });
+            if let Err(e) = spawned {
+                failures.fetch_add(u64::from(count - i), Ordering::Relaxed);
+                first_error.lock().unwrap().get_or_insert(e);
+                break;
+            }
+        }
+        drop(opening);
+    });
+    if let Some(e) = first_error.get_mut().unwrap().take() {
+        eprintln!(
+            "Warning: {} of {} prewarmed connections failed: {}",
+            failures.load(Ordering::Relaxed),
+            count,
+            e
+        );
+    }
+}
+
+/// Function to call the API concurrently
+///
+/// Each of the `users` workers sends requests one after the other until
+/// `--requests` have been claimed (one per user by default), the run is
+/// aborted or `--max-duration` has passed.
+fn call_api(url: String, args: &Cli, inputs: &RunInputs) -> Result<LoadTestRun, String> {
+    // --repeatable-load draws the same random choices on every run
+    let seed = args.seed.or(args.repeatable_load.then_some(0));
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let samples = RequestSamples::new(args.sample_size, StdRng::from_rng(&mut rng));
+    let connections_opened = Arc::new(AtomicU64::new(0));
+    let client_error = |e: reqwest::Error| format!("cannot build the HTTP client: {}", e);
+    let client = build_client(args, &connections_opened).map_err(client_error)?;
+    let worker_clients = (0..args.users.max(0))
+        .map(|_| {
+            if args.repeatable_load {
+                build_client(args, &connections_opened)
+            } else {
+                Ok(client.clone())
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(client_error)?;
+    let prewarmed = args.prewarm_connections.map(|count| {
+        prewarm_connections(&client, &prewarm_url(&url, inputs), count);
+        // The run only counts the connections it opens itself
+        connections_opened.swap(0, Ordering::Relaxed)
+    });
+    // A sequential pass over a request mix sends each URL once
+    let sequential = (args.url_order == UrlOrder::Sequential && !inputs.targets.is_empty())
+        .then_some(inputs.targets.len() as u64);
+    // A replay log is replayed once unless --requests says otherwise, and a
+    // run steered through --control-port goes on until it is stopped
+    let unbounded = inputs.control.is_some()
+        && sequential.is_none()
+        && args.requests.is_none()
+        && args.max_duration.is_none()
+        && inputs.replay.is_empty();
+    let total_requests = match (args.requests, sequential) {
+        (Some(requests), Some(urls)) => requests.min(urls),
+        (None, Some(urls)) => urls,
+        (Some(requests), None) => requests,
+        (None, None) if unbounded => u64::MAX,
+        (None, None) if inputs.replay.is_empty() => args.users.max(0) as u64,
+        (None, None) => inputs.replay.len() as u64,
+    };
+    // Read both clocks together, so the wall clock anchors the offsets
+    let (started_at, run_start) = (SystemTime::now(), Instant::now());
+    let state = RunState {
+        url: &url,
+        args,
+        inputs,
+        method: args.method.clone().into(),
+        has_content_type: has_content_type(&args.headers),
+        // On-assert reads the bodies of the responses an option inspects, and
+        // of those it can't size otherwise
+        read_bodies: args.read_body == ReadBody::Always,
+        criteria: SuccessCriteria::new(args),
+        highlight: std::io::stderr().is_terminal() && !args.no_color && !no_color_env(),
+        request_seed: rng.random(),
+        run_start,
+        total_requests,
+        max_duration: args.max_duration.map(Duration::from_secs),
+        next_request: AtomicU64::new(0),
+        // Bounding the requests in flight bounds the connections the pool opens
+        connection_slots: args.connections.map(Semaphore::new),
+        data: Mutex::new(vec![]),
+        samples: Mutex::new(samples),
+        stop: AtomicBool::new(false),
+        max_duration_reached: AtomicBool::new(false),
+        aborted: Mutex::new(None),
+        original_body_bytes: AtomicU64::new(0),
+        sent_body_bytes: AtomicU64::new(0),
+        retries: AtomicU64::new(0),
+        retry_classes: Mutex::new(BTreeMap::new()),
+        breaker_trips: AtomicU64::new(0),
+        request_errors: AtomicU64::new(0),
+        resource_exhausted: AtomicU64::new(0),
+        fd_hint_shown: AtomicBool::new(false),
+        abandoned: AtomicU64::new(0),
+        timeouts: Mutex::new(vec![]),
+        // Pausing needs someone at the terminal to resume the run
+        pause: (args.pause_on_error && std::io::stdin().is_terminal()).then(PauseGate::new),
+        schedule: args.repeatable_load.then(|| Schedule::new(args)),
+        schedule_lag: Mutex::new(vec![]),
+        think_time_us: AtomicU64::new(0),
+        abandoned_steps: inputs.steps.iter().map(|_| AtomicU64::new(0)).collect(),
+        lock_wait_ns: args.self_metrics.then(|| AtomicU64::new(0)),
+        concurrency: inputs
+            .control
+            .as_ref()
+            .map(|_| Concurrency::new(args.users.max(0) as u32)),
+        error_bodies: args
+            .capture_error_bodies
+            .then(|| Mutex::new(HashMap::new())),
+        golden_mismatch: Mutex::new(None),
+        malformed_json: Mutex::new(vec![]),
+        connection_requests: Mutex::new(HashMap::new()),
+        connection_close_warned: AtomicBool::new(false),
+    };
+    let control_finished = AtomicBool::new(false);
+    // The number of workers still sending requests
+    let running = (Mutex::new(0u32), Condvar::new());
+
+    let (elapsed, think_times) = thread::scope(|scope| {
+        let finished = args
+            .report_interval
+            .map(|seconds| {
+                let (finished, finish) = mpsc::channel::<()>();
+                let interval = Duration::from_secs(seconds);
+                let data = &state.data;
+                let criteria = &state.criteria;
+                let lock_wait_ns = state.lock_wait_ns.as_ref();
+                spawn_named(scope, "loadster-progress".to_string(), move || {
+                    report_progress(data, criteria, lock_wait_ns, interval, finish)
+                })
+                .map(|_| finished)
+            })
+            .transpose()?;
+        let controller = args
+            .target_p95
+            .filter(|_| args.adaptive_thinktime)
+            .map(|target_p95| {
+                let (finished, finish) = mpsc::channel::<()>();
+                let (think_time, data) = (&state.think_time_us, &state.data);
+                let controller = spawn_named(scope, "loadster-thinktime".to_string(), move || {
+                    thinktime::control(think_time, data, target_p95, finish)
+                })?;
+                Ok::<_, String>((finished, controller))
+            })
+            .transpose()?;
+        let dashboard = args
+            .tui
+            .then(|| {
+                let (finished, finish) = mpsc::channel::<()>();
+                let dashboard = tui::Dashboard {
+                    url: &url,
+                    data: &state.data,
+                    criteria: &state.criteria,
+                    request_errors: &state.request_errors,
+                    stop: &state.stop,
+                    total_requests: (!unbounded)
+                        .then(|| total_requests * inputs.steps.len().max(1) as u64),
+                    max_duration: state.max_duration,
+                };
+                spawn_named(scope, "loadster-tui".to_string(), move || {
+                    tui::run(&dashboard, finish)
+                })
+                .map(|_| finished)
+            })
+            .transpose()?;
+        let state = &state;
+        let running = &running;
+        let spawn_worker = move |index: u32, client: Client| {
+            *running.0.lock().unwrap() += 1;
+            // Named so profiles, thread dumps and panic messages show which
+            // worker a thread is
+            let spawned = spawn_named(scope, format!("loadster-worker-{}", index), move || {
+                let _running = RunningWorker(running);
+                let mut worker = WorkerState {
+                    index,
+                    breaker: CircuitBreaker::new(
+                        args.circuit_breaker_threshold,
+                        Duration::from_millis(args.circuit_breaker_cooldown),
+                    ),
+                    client,
+                    sent: 0,
+                    answered: 0,
+                    latency_total: Duration::ZERO,
+                    bucket: args.max_rps_per_worker.map(TokenBucket::new),
+                };
+                // Requests seed their generators upwards from the seed, the
+                // jitter of every worker downwards
+                let mut jitter = args.jitter.map(|max| {
+                    let rng = StdRng::seed_from_u64(
+                        state.request_seed.wrapping_sub(u64::from(index) + 1),
+                    );
+                    (rng, max)
+                });
+                let mut pause = || {
+                    if let Some((rng, max)) = &mut jitter {
+                        thread::sleep(Duration::from_millis(rng.random_range(0..=*max)));
+                    }
+                };
+                while let Some(i) = state.next_request(&mut worker) {
+                    // Before the request rather than after, so the last one
+                    // doesn't prolong the run
+                    pause();
+                    if inputs.steps.is_empty() {
+                        state.send(i, None, &mut worker);
+                    } else {
+                        state.run_scenario(i, &mut worker);
+                    }
+                    if args.adaptive_thinktime {
+                        let think_time = state.think_time_us.load(Ordering::Relaxed);
+                        thread::sleep(Duration::from_micros(think_time));
+                    }
+                }
+            });
+            if spawned.is_err() {
+                *running.0.lock().unwrap() -= 1;
+            }
+            spawned.map(|_| ())
+        };
+        for (index, client) in worker_clients.into_iter().enumerate() {
+            if let Err(e) = spawn_worker(index as u32, client) {
+                // The workers already started stop after their request
+                state.stop.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        if let Some(listener) = &inputs.control {
+            let control_finished = &control_finished;
+            let client = &client;
+            spawn_named(scope, "loadster-control".to_string(), move || {
+                let mut spawned = args.users.max(0) as u32;
+                control::serve(listener, control_finished, |command| {
+                    // Workers are started the first time the concurrency
+                    // needs them, and parked rather than ended when it drops
+                    if let control::ControlCommand::SetConcurrency(users) = command {
+                        if users as i64 > MAX_SAFE_USERS && !args.allow_unsafe_concurrency {
+                            return format!(
+                                "error: more than {} users need --allow-unsafe-concurrency",
+                                MAX_SAFE_USERS
+                            );
+                        }
+                        while spawned < users && !state.finished() {
+                            if let Err(e) = spawn_worker(spawned, client.clone()) {
+                                return format!("error: {}", e);
+                            }
+                            spawned += 1;
+                        }
+                    }
+                    state.control(command)
+                });
+            })
+            .inspect_err(|_| state.stop.store(true, Ordering::SeqCst))?;
+        }
+        // Workers started from the control socket are waited for too
+        let mut workers = running.0.lock().unwrap();
+        while *workers > 0 {
+            workers = running.1.wait(workers).unwrap();
+        }
+        drop(workers);
+        let elapsed = state.run_start.elapsed();
+        control_finished.store(true, Ordering::SeqCst);
+        drop(finished);
+        drop(dashboard);
+        let think_times = controller.map_or_else(Vec::new, |(finished, controller)| {
+            drop(finished);
+            controller
+                .join()
+                .expect("the think time controller doesn't panic")
+        });
+        Ok((elapsed, think_times))
+    })?;
+
+    let compression = args.compress_body.map(|compression| CompressionStats {
+        compression,
+        original_bytes: state.original_body_bytes.load(Ordering::Relaxed),
+        compressed_bytes: state.sent_body_bytes.load(Ordering::Relaxed),
+    });
+    Ok(LoadTestRun {
+        started_at,
+        elapsed,
+        responses: state.data.into_inner().unwrap(),
+        aborted: state.aborted.into_inner().unwrap(),
+        samples: state.samples.into_inner().unwrap(),
+        compression,
+        connections_opened: connections_opened.load(Ordering::Relaxed),
+        prewarmed,
+        retries: state.retries.load(Ordering::Relaxed),
+        retry_classes: state.retry_classes.into_inner().unwrap(),
+        breaker_trips: state.breaker_trips.load(Ordering::Relaxed),
+        request_errors: state.request_errors.load(Ordering::Relaxed),
+        resource_exhausted: state.resource_exhausted.load(Ordering::Relaxed),
+        abandoned: state.abandoned.load(Ordering::Relaxed),
+        timeouts: state.timeouts.into_inner().unwrap(),
+        // Every iteration of a scenario sends all of its steps; a run without
+        // an end planned all the requests it claimed
+        requests: if unbounded {
+            state.next_request.load(Ordering::SeqCst)
+        } else {
+            total_requests
+        } * inputs.steps.len().max(1) as u64,
+        max_duration_reached: state.max_duration_reached.load(Ordering::SeqCst),
+        schedule_lag: state.schedule_lag.into_inner().unwrap(),
+        think_times,
+        lock_wait_ns: state
+            .lock_wait_ns
+            .map(|lock_wait_ns| lock_wait_ns.into_inner()),
+        error_bodies: state.error_bodies.map_or_else(Vec::new, |bodies| {
+            error_body_list(bodies.into_inner().unwrap())
+        }),
+        golden_mismatch: state.golden_mismatch.into_inner().unwrap(),
+        malformed_json: state.malformed_json.into_inner().unwrap(),
+        requests_per_connection: state
+            .connection_requests
+            .into_inner()
+            .unwrap()
+            .into_values()
+            .collect(),
+        scenario: (!inputs.steps.is_empty()).then(|| scenario::ScenarioStats {
+            steps: inputs.steps.iter().map(|step| step.name.clone()).collect(),
+            abandoned: state
+                .abandoned_steps
+                .iter()
+                .map(|abandoned| abandoned.load(Ordering::Relaxed))
+                .collect(),
+        }),
+    })
+}
+
+/// Function to print a rolling summary of the responses recorded during each
+/// interval, until `finish` is dropped at the end of the run
+///
+/// With --self-metrics, the line ends with loadster's own memory use, thread
+/// count and the time its workers waited for locks during the interval.
+fn report_progress(
+    data: &Mutex<Vec<ResponseDetails>>,
+    criteria: &SuccessCriteria,
+    lock_wait_ns: Option<&AtomicU64>,
+    interval: Duration,
+    finish: mpsc::Receiver<()>,
+) {
+    let mut reported = 0;
+    let mut intervals = 0;
+    let mut reported_lock_wait_ns = 0;
+    while let Err(RecvTimeoutError::Timeout) = finish.recv_timeout(interval) {
+        intervals += 1;
+        let (mut times, errors) = {
+            let data = data.lock().unwrap();
+            let new = &data[reported..];
+            let times: Vec<u64> = new.iter().map(|d| d.time).collect();
+            let errors = new.iter().filter(|d| !is_successful(d, criteria)).count();
+            reported = data.len();
+            (times, errors)
+        };
+        times.sort_unstable();
+        // Print the whole line at once so it doesn't interleave with verbose
+        // per-request output
+        let mut line = format!(
+            "[{:>5}s] Requests: {} ({:.2} req/s) | P95: {} ms | Errors: {}",
+            intervals * interval.as_secs(),
+            times.len(),
+            times.len() as f64 / interval.as_secs_f64(),
+            percentile(&times, 0.95),
+            errors
+        );
+        if let Some(lock_wait_ns) = lock_wait_ns {
+            let total = lock_wait_ns.load(Ordering::Relaxed);
+            let metrics = process::ProcessMetrics::read();
+            line.push_str(&format!(
+                " | RSS: {} | Threads: {} | Lock wait: {:.2} ms",
+                process::format_mib(metrics.rss_kib, 1),
+                process::format_count(metrics.threads),
+                (total - reported_lock_wait_ns) as f64 / 1e6
+            ));
+            reported_lock_wait_ns = total;
+        }
+        line.push('\n');
+        let _ = std::io::stdout().lock().write_all(line.as_bytes());
+    }
+}
+
+/// Function to size the body of a response to a `Range: bytes=0-0` request
+/// (--ignore-body): the full length in its Content-Range header when the
+/// server honoured the range, else its Content-Length
+fn full_length(res: &Response) -> u64 {
+    res.headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.rsplit_once('/'))
+        .and_then(|(_, length)| length.trim().parse().ok())
+        .or_else(|| res.content_length())
+        .unwrap_or(0)
+}
+
+/// A reader that reads at most `rate` bytes per second from `inner`, for
+/// --bandwidth. Reads are cut into pieces of 50 ms worth of bytes, so the data
+/// trickles in rather than arriving in bursts.
+struct ThrottledReader<R> {
+    inner: R,
+    rate: u64, // Bytes per second
+    start: Instant,
+    read: u64,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    fn new(inner: R, rate: u64) -> Self {
+        ThrottledReader {
+            inner,
+            rate,
+            start: Instant::now(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let piece = (self.rate / 20).max(1).min(buf.len() as u64) as usize;
+        let read = self.inner.read(&mut buf[..piece])?;
+        self.read += read as u64;
+        // Wait until the bytes so far are due at the rate
+        let due = self.start + Duration::from_secs_f64(self.read as f64 / self.rate as f64);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        Ok(read)
+    }
+}
+
+/// A reader that feeds everything read from `inner` into a SHA-256 hash, for
+/// --expected-checksum and --detect-duplicate-checksum. The body is hashed
+/// as it streams in, in full however little of it --max-body-size keeps.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Sha256>, // None when the body isn't hashed
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R, hashing: bool) -> Self {
+        HashingReader {
+            inner,
+            hasher: hashing.then(Sha256::new),
+        }
+    }
+
+    /// The hex digest of everything read, when hashing
+    fn checksum(self) -> Option<String> {
+        self.hasher.map(|hasher| {
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        })
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Function to read a response body, keeping at most `max_size` bytes in
+/// memory (0 means unlimited). Anything beyond the limit is drained from the
+/// connection and only counted. Returns the kept bytes and the full body size.
+fn read_body(mut res: impl Read, max_size: u64) -> std::io::Result<(Vec<u8>, u64)> {
+    let mut body = vec![];
+    if max_size == 0 {
+        res.read_to_end(&mut body)?;
+        let bytes = body.len() as u64;
+        return Ok((body, bytes));
+    }
+    (&mut res).take(max_size).read_to_end(&mut body)?;
+    let rest = std::io::copy(&mut res, &mut std::io::sink())?;
+    let bytes = body.len() as u64 + rest;
+    Ok((body, bytes))
+}
+
+/// Function to print every part of the report of a single run
+fn report_run(run: &LoadTestRun, url: &str, args: &Cli) {
+    let precision = args.precision;
+    let palette = Palette::new(args);
+    if let Some(reason) = &run.aborted {
+        println!(
+            "\nTest aborted: request {} received status {}, showing partial results",
+            reason.request, reason.status
+        );
+    }
+    if run.max_duration_reached {
+        println!(
+            "\nTest stopped at --max-duration: {} of {} requests completed",
+            run.responses.len(),
+            run.requests
+        );
+    }
+    if args.ignore_body {
+        println!("\nBodies ignored (--ignore-body): only the first byte of each response was asked for, so the times exclude the body transfer");
+    }
+    let criteria = SuccessCriteria::new(args);
+    display_results(run, &criteria, precision, &palette);
+    generate_report(
+        run,
+        &criteria,
+        url,
+        args.min_samples,
+        args.trim_mean,
+        precision,
+        &palette,
+    );
+    // A run that degraded is flagged whether the trend was asked for or not
+    if let Some(trend) =
+        latency_trend(&run.responses).filter(|trend| args.latency_trend || trend.degraded)
+    {
+        display_latency_trend(&trend, precision, &palette);
+    }
+    if args.histogram {
+        let buckets = latency_histogram(run, args.histogram_scale);
+        histogram::display(&buckets, args.histogram_scale, precision);
+    }
+    if let Some(resamples) = args.bootstrap {
+        display_bootstrap(&run.responses, resamples, args.seed);
+    }
+    if let Some(scenario) = &run.scenario {
+        display_scenario(&run.responses, &criteria, scenario, precision);
+    } else {
+        display_url_breakdown(
+            &run.responses,
+            &criteria,
+            args.url_template_report,
+            precision,
+        );
+    }
+    if args.split_by_ip {
+        display_ip_breakdown(&run.responses, &criteria, precision);
+    }
+    if args.per_worker_stats {
+        display_worker_stats(run, &criteria, args.users.max(0) as u32, precision);
+    }
+    if let Some(cap) = args.max_rps_per_worker {
+        display_worker_rates(run, args.users.max(0) as u32, cap, precision);
+    }
+    if args.size_stats {
+        display_response_sizes(&run.responses, precision);
+    }
+    if let Some(stats) = &run.compression {
+        display_compression(stats, precision);
+    }
+    display_connections(run, args, precision);
+    if args.connection_stats {
+        display_requests_per_connection(run, precision);
+    }
+    if run.retries > 0 || run.breaker_trips > 0 {
+        display_retries(run);
+    }
+    if !run.timeouts.is_empty() {
+        display_timeouts(&run.timeouts);
+    }
+    if args.repeatable_load {
+        display_repeatable_load(run, args, precision);
+    } else if !run.schedule_lag.is_empty() {
+        display_replay_timing(&run.schedule_lag, args.rate_multiplier);
+    }
+    if let (Some(target_p95), true) = (args.target_p95, !run.think_times.is_empty()) {
+        display_think_times(&run.think_times, target_p95);
+    }
+    if let Some(lock_wait_ns) = run.lock_wait_ns {
+        display_self_metrics(run, lock_wait_ns, precision);
+    }
+    if let Some(rate) = args.bandwidth {
+        display_download_speed(&run.responses, rate, precision);
+    }
+    if args.capture_error_bodies {
+        display_error_bodies(&run.error_bodies);
+    }
+    if args.assert_schema.is_some() {
+        display_schema_validation(&run.responses);
+    }
+    if args.expected_checksum.is_some() || args.detect_duplicate_checksum {
+        display_checksums(&run.responses, &criteria);
+    }
+    if let Some(path) = &args.golden {
+        display_golden(run, path);
+    }
+    if args.validate_json {
+        display_json_validation(run);
+    }
+    if let Some(percent) = args.inject_errors {
+        display_injected_errors(&run.responses, &criteria, percent, precision);
+    }
+    let assertions = check_assertions(run, args);
+    if !assertions.is_empty() {
+        display_assertions(&assertions, &palette);
+    }
+    if args.show_samples {
+        display_samples(&run.samples);
+    }
+}
+
+/// Function to display the results of every step of a --scenario, including
+/// how often --fail-fast abandoned it after an earlier step failed
+fn display_scenario(
+    data: &[ResponseDetails],
+    criteria: &SuccessCriteria,
+    scenario: &scenario::ScenarioStats,
+    precision: usize,
+) {
+    println!("\nScenario Steps");
+    println!("Step\tRequests\tFailed\tAbandoned\tAvg Latency\tP50\tP95\tP99");
+    for (index, name) in scenario.steps.iter().enumerate() {
+        let details: Vec<&ResponseDetails> =
+            data.iter().filter(|d| d.step == Some(index)).collect();
+        let mut times: Vec<u64> = details.iter().map(|d| d.time).collect();
+        times.sort_unstable();
+        let failed = details
+            .iter()
+            .filter(|d| !is_successful(d, criteria))
+            .count();
+        let avg = times.iter().sum::<u64>() as f64 / times.len().max(1) as f64;
+        println!(
+            "{}. {}\t{}\t{}\t{}\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            index + 1,
+            name,
+            times.len(),
+            failed,
+            scenario.abandoned[index],
+            precision,
+            avg,
+            percentile(&times, 0.50),
+            percentile(&times, 0.95),
+            percentile(&times, 0.99)
+        );
+    }
+}
+
+/// Function to display the results per URL when a run sent more than one,
+/// e.g. because the URL has placeholders. With `by_template` the requests are
+/// grouped by the URL before its placeholders were filled in instead.
+fn display_url_breakdown(
+    data: &[ResponseDetails],
+    criteria: &SuccessCriteria,
+    by_template: bool,
+    precision: usize,
+) {
+    let rows = url_rows(data, criteria, by_template);
+    if rows.len() < 2 {
+        return;
+    }
+
+    if by_template {
+        println!("\nResults by URL Template");
+    } else {
+        println!("\nResults by URL");
+    }
+    println!("URL\tRequests\tFailed\tAvg Latency\tP50\tP95\tP99");
+    for (url, times, failed) in &rows {
+        println!(
+            "{}\t{}\t{}\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            url,
+            times.len(),
+            failed,
+            precision,
+            times.iter().sum::<u64>() as f64 / times.len() as f64,
+            percentile(times, 0.50),
+            percentile(times, 0.95),
+            percentile(times, 0.99)
+        );
+    }
+}
+
+/// Function to group the requests by URL, or by URL template with
+/// `by_template`, into rows of the URL, the sorted latencies and the number of
+/// failures, the most requested first
+fn url_rows<'a>(
+    data: &'a [ResponseDetails],
+    criteria: &SuccessCriteria,
+    by_template: bool,
+) -> Vec<(&'a str, Vec<u64>, usize)> {
+    let mut groups: BTreeMap<&str, Vec<&ResponseDetails>> = BTreeMap::new();
+    for detail in data {
+        let key = if by_template {
+            &detail.url_template
+        } else {
+            &detail.url
+        };
+        groups.entry(key).or_default().push(detail);
+    }
+
+    let mut rows: Vec<_> = groups
+        .into_iter()
+        .map(|(url, details)| {
+            let mut times: Vec<u64> = details.iter().map(|d| d.time).collect();
+            times.sort_unstable();
+            let failed = details
+                .iter()
+                .filter(|d| !is_successful(d, criteria))
+                .count();
+            (url, times, failed)
+        })
+        .collect();
+    // Most requested first, ties in URL order
+    rows.sort_by_key(|(_, times, _)| std::cmp::Reverse(times.len()));
+    rows
+}
+
+/// Function to display the results per IP address of the server, for
+/// hostnames that resolve to more than one backend
+fn display_ip_breakdown(data: &[ResponseDetails], criteria: &SuccessCriteria, precision: usize) {
+    let mut groups: BTreeMap<Option<IpAddr>, Vec<&ResponseDetails>> = BTreeMap::new();
+    for detail in data {
+        groups.entry(detail.remote_ip).or_default().push(detail);
+    }
+
+    println!("\nResults by Backend IP");
+    println!("IP\tRequests\tFailed\tAvg Latency\tP50\tP95\tP99");
+    for (ip, details) in &groups {
+        let mut times: Vec<u64> = details.iter().map(|d| d.time).collect();
+        times.sort_unstable();
+        let failed = details
+            .iter()
+            .filter(|d| !is_successful(d, criteria))
+            .count();
+        println!(
+            "{}\t{}\t{}\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string()),
+            times.len(),
+            failed,
+            precision,
+            times.iter().sum::<u64>() as f64 / times.len() as f64,
+            percentile(&times, 0.50),
+            percentile(&times, 0.95),
+            percentile(&times, 0.99)
+        );
+    }
+    if groups.len() == 1 {
+        println!("Every response came from one IP address; connections are reused, so new IPs only show up on new connections");
+    }
+}
+
+/// Function to display how the requests were spread over the workers, to
+/// spot workers that starved or were stuck on slow connections
+fn display_worker_stats(
+    run: &LoadTestRun,
+    criteria: &SuccessCriteria,
+    workers: u32,
+    precision: usize,
+) {
+    // Workers that got no response at all still get a row
+    let mut by_worker: BTreeMap<u32, Vec<&ResponseDetails>> =
+        (0..workers).map(|worker| (worker, vec![])).collect();
+    for detail in &run.responses {
+        by_worker.entry(detail.worker).or_default().push(detail);
+    }
+
+    println!("\nPer-Worker Results");
+    println!("Worker\tRequests\tRate\tFailed\tAvg Latency\tMax Latency");
+    for (worker, details) in &by_worker {
+        let total: u64 = details.iter().map(|d| d.time).sum();
+        println!(
+            "{}\t{}\t{:.*} req/s\t{}\t{:.*} ms\t{} ms",
+            worker,
+            details.len(),
+            precision,
+            worker_rate(details, run.elapsed),
+            details
+                .iter()
+                .filter(|d| !is_successful(d, criteria))
+                .count(),
+            precision,
+            total as f64 / details.len().max(1) as f64,
+            details.iter().map(|d| d.time).max().unwrap_or(0)
+        );
+    }
+    let counts: Vec<usize> = by_worker.values().map(Vec::len).collect();
+    println!(
+        "Requests per Worker\tmin {}, max {}",
+        counts.iter().min().unwrap_or(&0),
+        counts.iter().max().unwrap_or(&0)
+    );
+}
+
+/// Function to calculate the rate at which a worker sent the requests that
+/// got a response: the requests after its first one over the time since it
+/// was sent, so a worker held to a rate reaches it exactly. Workers with a
+/// single response fall back to the duration of the run.
+fn worker_rate(details: &[&ResponseDetails], elapsed: Duration) -> f64 {
+    let starts = details.iter().map(|d| d.started_at_offset_ns);
+    match (starts.clone().min(), starts.max()) {
+        (Some(first), Some(last)) if last > first => {
+            (details.len() - 1) as f64 / ((last - first) as f64 / 1e9)
+        }
+        _ => throughput(details.len(), elapsed),
+    }
+}
+
+/// Function to display the rates the workers achieved under
+/// --max-rps-per-worker, over the wall-clock duration of the run
+fn display_worker_rates(run: &LoadTestRun, workers: u32, cap: f64, precision: usize) {
+    let mut by_worker: Vec<Vec<&ResponseDetails>> = vec![vec![]; workers as usize];
+    for detail in &run.responses {
+        if let Some(details) = by_worker.get_mut(detail.worker as usize) {
+            details.push(detail);
+        }
+    }
+    let mut rates: Vec<f64> = by_worker
+        .iter()
+        .map(|details| worker_rate(details, run.elapsed))
+        .collect();
+    if rates.is_empty() {
+        return;
+    }
+    rates.sort_unstable_by(f64::total_cmp);
+    let at_cap = rates.iter().filter(|&&rate| rate >= cap * 0.95).count();
+    println!("\nPer-Worker Rate");
+    println!("Cap\t{:.*} req/s per worker", precision, cap);
+    println!(
+        "Achieved\tmin {:.*}, avg {:.*}, max {:.*} req/s",
+        precision,
+        rates[0],
+        precision,
+        rates.iter().sum::<f64>() / rates.len() as f64,
+        precision,
+        rates[rates.len() - 1]
+    );
+    println!(
+        "Workers at the Cap\t{} of {} (within 5%)",
+        at_cap,
+        rates.len()
+    );
+}
+
+/// Struct to color the terminal report: failures in red, throughput that
+/// meets --min-throughput in green (red when it doesn't) and latencies over
+/// --warn-slow in yellow. Only the styling differs, the text stays the same.
+struct Palette {
+    enabled: bool,
+    slow_threshold: Option<u64>,
+    min_throughput: Option<f64>,
+}
+
+impl Palette {
+    /// Colors are on unless --no-color or NO_COLOR is set, or stdout is not a
+    /// terminal
+    fn new(args: &Cli) -> Self {
+        Palette {
+            enabled: !args.no_color && !no_color_env() && std::io::stdout().is_terminal(),
+            slow_threshold: args.warn_slow,
+            min_throughput: args.min_throughput,
+        }
+    }
+
+    fn paint(&self, text: impl std::fmt::Display, color: Option<&str>) -> String {
+        match color {
+            Some(color) if self.enabled => format!("\x1b[{}m{}\x1b[0m", color, text),
+            _ => text.to_string(),
+        }
+    }
+
+    fn failures(&self, count: usize) -> String {
+        self.paint(count, (count > 0).then_some(RED))
+    }
+
+    fn throughput(&self, text: String, value: f64) -> String {
+        let color = self
+            .min_throughput
+            .map(|min| if value >= min { GREEN } else { RED });
+        self.paint(text, color)
+    }
+
+    fn latency(&self, text: impl std::fmt::Display, ms: f64) -> String {
+        let slow = self
+            .slow_threshold
+            .is_some_and(|threshold| ms > threshold as f64);
+        self.paint(text, slow.then_some(YELLOW))
+    }
+
+    fn latency_ms(&self, ms: u64) -> String {
+        self.latency(ms, ms as f64)
+    }
+
+    fn warning(&self, text: impl std::fmt::Display) -> String {
+        self.paint(text, Some(YELLOW))
+    }
+
+    fn status(&self, status: StatusCode) -> String {
+        let color = if status.is_server_error() {
+            Some(RED)
+        } else if status.is_client_error() {
+            Some(YELLOW)
+        } else {
+            None
+        };
+        self.paint(status, color)
+    }
+}
+
+/// Function to check the NO_COLOR convention (https://no-color.org): colors
+/// are off when the variable is set and not empty
+fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+const RED: &str = "1;31";
+const GREEN: &str = "32";
+const YELLOW: &str = "1;33";
+
+/// Function to display how many connections were opened and how often a
+/// request could reuse one instead
+fn display_connections(run: &LoadTestRun, args: &Cli, precision: usize) {
+    let requests = run.responses.len() as u64;
+    println!("\nConnections");
+    if args.http3 {
+        // QUIC connections bypass the TCP connector that counts connections
+        println!("Connections Opened\tn/a (not counted over HTTP/3)");
+    } else {
+        println!("Connections Opened\t{}", run.connections_opened);
+        println!("Connection Reuse\t{:.*}%", precision, connection_reuse(run));
+        match args.keepalive_timeout {
+            Some(0) => println!("Keep-Alive\toff (--keepalive-timeout 0)"),
+            Some(seconds) => println!("Keep-Alive Timeout\t{} s", seconds),
+            None => {}
+        }
+    }
+    let mut versions: BTreeMap<&str, usize> = BTreeMap::new();
+    for detail in &run.responses {
+        *versions.entry(&detail.version).or_default() += 1;
+    }
+    if !versions.is_empty() {
+        let versions: Vec<String> = versions
+            .iter()
+            .map(|(version, count)| format!("{} ({})", version, count))
+            .collect();
+        println!("Protocols\t{}", versions.join(", "));
+    }
+    if let Some(prewarmed) = run.prewarmed {
+        // Which connection served a request isn't known, so this is the
+        // connections the users needed at once less those the run opened
+        let needed = (args.users.max(0) as u64)
+            .min(args.connections.unwrap_or(u64::MAX))
+            .min(requests);
+        let reused = needed.saturating_sub(run.connections_opened).min(prewarmed);
+        println!("Prewarmed Connections\t{}", prewarmed);
+        println!("Prewarmed Connections Reused\t{} (estimated)", reused);
+    }
+    if args.tcp_nodelay.is_on() {
+        println!("TCP_NODELAY\ton");
+    } else {
+        println!("TCP_NODELAY\toff (Nagle's algorithm may hold back small writes)");
+    }
+    if let Some(tls_version) = args.tls_version {
+        // The TLS backends don't expose the negotiated cipher; the version is
+        // the pinned one whenever a handshake succeeded
+        println!("TLS Version\t{} (pinned)", tls_version.name());
+    }
+}
+
+/// Function to estimate the percentage of responses that came over a
+/// connection already open. Which connection served a request isn't known,
+/// so every connection the run opened counts as one response without reuse.
+fn connection_reuse(run: &LoadTestRun) -> f64 {
+    let requests = run.responses.len() as u64;
+    if requests == 0 {
+        return 0.0;
+    }
+    requests.saturating_sub(run.connections_opened) as f64 / requests as f64 * 100.0
+}
+
+/// Function to display how --adaptive-thinktime adjusted the think time,
+/// second by second
+fn display_think_times(steps: &[thinktime::ThinkTimeStep], target_p95: u64) {
+    println!("\nAdaptive Think Time (target P95 {} ms)", target_p95);
+    if let Some(last) = steps.last() {
+        println!("Final Think Time\t{} ms", last.think_time_ms);
+    }
+    println!("Second\tResponses\tP95 (ms)\tThink Time (ms)");
+    for step in steps {
+        println!(
+            "{}\t{}\t{}\t{}",
+            step.second, step.responses, step.p95, step.think_time_ms
+        );
+    }
+}
+
+/// Function to display how often requests were resent and workers paused
+fn display_retries(run: &LoadTestRun) {
+    println!("\nRetries");
+    println!("Retried Attempts\t{}", run.retries);
+    for (class, count) in &run.retry_classes {
+        println!("Retried {}\t{}", class, count);
+    }
+    println!("Circuit Breaker Trips\t{}", run.breaker_trips);
+}
+
+/// Function to display how many requests timed out and after how long, which
+/// varies between requests with --timeout-jitter
+fn display_timeouts(timeouts: &[u64]) {
+    let mut timeouts = timeouts.to_vec();
+    timeouts.sort_unstable();
+    println!("\nTimeouts");
+    println!("Timed Out Requests\t{}", timeouts.len());
+    println!(
+        "Effective Timeout\tmin {} ms, median {} ms, max {} ms",
+        timeouts[0],
+        percentile(&timeouts, 0.50),
+        timeouts[timeouts.len() - 1]
+    );
+}
+
+/// Function to display how closely a --repeatable-load run kept to its
+/// schedule and how much time loadster added to the mock server's latency
+fn display_repeatable_load(run: &LoadTestRun, args: &Cli, precision: usize) {
+    let schedule = Schedule::new(args);
+    let mut lag = run.schedule_lag.clone();
+    lag.sort_unstable();
+    let mut overhead: Vec<u64> = run
+        .responses
+        .iter()
+        .map(|d| d.time.saturating_sub(args.mock_latency))
+        .collect();
+    overhead.sort_unstable();
+    println!("\nRepeatable Load");
+    println!("Mock Latency\t{} ms", args.mock_latency);
+    println!(
+        "Arrival Interval\t{:.*} ms",
+        precision,
+        schedule.interval.as_secs_f64() * 1000.0
+    );
+    println!(
+        "Schedule Lag\tP50 {} us, P99 {} us, Max {} us",
+        percentile(&lag, 0.50),
+        percentile(&lag, 0.99),
+        percentile(&lag, 1.0)
+    );
+    println!(
+        "Overhead\tP50 {} ms, P99 {} ms, Max {} ms",
+        percentile(&overhead, 0.50),
+        percentile(&overhead, 0.99),
+        percentile(&overhead, 1.0)
+    );
+}
+
+/// Function to display the distribution of response sizes and the latency of
+/// each size bucket, which shows whether heavy responses are the slow ones
+fn display_response_sizes(data: &[ResponseDetails], precision: usize) {
+    // Upper bounds of the buckets, each four times the one before
+    const BUCKETS: [u64; 8] = [
+        1 << 10,
+        1 << 12,
+        1 << 14,
+        1 << 16,
+        1 << 18,
+        1 << 20,
+        1 << 22,
+        u64::MAX,
+    ];
+    // Width of the bar of the largest bucket
+    const BAR_WIDTH: usize = 40;
+
+    if data.iter().all(|d| d.bytes == 0) {
+        return;
+    }
+    let sizes = response_sizes(data);
+    println!("\nResponse Sizes");
+    println!("Percentile\tSize (bytes)");
+    for (name, fraction) in [("P50", 0.50), ("P95", 0.95), ("P99", 0.99), ("Max", 1.0)] {
+        println!("{}\t{}", name, percentile(&sizes, fraction));
+    }
+
+    let mut buckets = vec![(0, 0); BUCKETS.len()];
+    for detail in data {
+        let bucket = BUCKETS.iter().position(|&bound| detail.bytes < bound);
+        let (count, time) = &mut buckets[bucket.unwrap_or(BUCKETS.len() - 1)];
+        *count += 1;
+        *time += detail.time;
+    }
+    let first = buckets
+        .iter()
+        .position(|&(count, _)| count > 0)
+        .unwrap_or(0);
+    let last = buckets
+        .iter()
+        .rposition(|&(count, _)| count > 0)
+        .unwrap_or(0);
+    let most = buckets.iter().map(|&(count, _)| count).max().unwrap_or(1);
+    println!("\nSize\tRequests\tAvg Latency\tShare");
+    for (i, &(count, time)) in buckets.iter().enumerate().take(last + 1).skip(first) {
+        let lower = if i == 0 { 0 } else { BUCKETS[i - 1] };
+        let range = if BUCKETS[i] == u64::MAX {
+            format!(">= {}", format_size(lower))
+        } else {
+            format!("{}-{}", format_size(lower), format_size(BUCKETS[i]))
+        };
+        let avg = if count > 0 {
+            time as f64 / count as f64
+        } else {
+            0.0
+        };
+        println!(
+            "{}\t{}\t{:.*} ms\t{}",
+            range,
+            count,
+            precision,
+            avg,
+            "#".repeat((count * BAR_WIDTH as u64).div_ceil(most) as usize)
+        );
+    }
+}
+
+/// Function to bucket the latencies of a run for --histogram
+fn latency_histogram(
+    run: &LoadTestRun,
+    scale: histogram::HistogramScale,
+) -> Vec<histogram::Bucket> {
+    let mut times: Vec<u64> = run.responses.iter().map(|d| d.time).collect();
+    times.sort_unstable();
+    histogram::buckets(&times, scale)
+}
+
+/// Function to collect the sorted sizes of the responses, in bytes
+fn response_sizes(data: &[ResponseDetails]) -> Vec<u64> {
+    let mut sizes: Vec<u64> = data.iter().map(|d| d.bytes).collect();
+    sizes.sort_unstable();
+    sizes
+}
+
+/// Function to format a power-of-two size in bytes, KiB or MiB
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0 => "0".to_string(),
+        _ if bytes >= 1 << 20 => format!("{} MiB", bytes >> 20),
+        _ => format!("{} KiB", bytes >> 10),
+    }
+}
+
+/// Function to display how closely --preserve-timing kept to the arrival
+/// times of the replay log, scaled by --rate-multiplier. Requests are late
+/// when every user is busy.
+fn display_replay_timing(schedule_lag: &[u64], rate_multiplier: Option<f64>) {
+    let mut lag: Vec<u64> = schedule_lag.iter().map(|lag| lag / 1000).collect();
+    lag.sort_unstable();
+    let late = lag.iter().filter(|&&lag| lag > 0).count();
+    println!("\nReplay Timing");
+    if let Some(multiplier) = rate_multiplier {
+        println!("Rate Multiplier\t{}x the rate of the log", multiplier);
+    }
+    println!("Late Requests\t{} of {}", late, lag.len());
+    println!(
+        "Lag\tP50 {} ms, P99 {} ms, Max {} ms",
+        percentile(&lag, 0.50),
+        percentile(&lag, 0.99),
+        percentile(&lag, 1.0)
+    );
+    if late > 0 {
+        let remedy = match rate_multiplier {
+            Some(_) => "raise --users or lower --rate-multiplier",
+            None => "raise --users",
+        };
+        println!(
+            "Late requests were sent as soon as a user was free, so the log's rate was not reached; {}",
+            remedy
+        );
+    }
+}
+
+/// Function to display how fast the bodies of --bandwidth were downloaded,
+/// from the time between the headers and the end of every body
+fn display_download_speed(data: &[ResponseDetails], rate: u64, precision: usize) {
+    let bodies: Vec<&ResponseDetails> = data.iter().filter(|d| d.body_read).collect();
+    let bytes: u64 = bodies.iter().map(|d| d.bytes).sum();
+    let body_ms: u64 = bodies.iter().map(|d| d.time.saturating_sub(d.ttfb)).sum();
+    println!("\nDownload Speed");
+    println!("Bandwidth Limit\t{} bytes/s per worker", rate);
+    if body_ms == 0 {
+        println!("Effective Speed\tn/a (no body took measurable time)");
+        return;
+    }
+    println!(
+        "Effective Speed\t{:.*} bytes/s ({} bytes in {} ms of body transfer)",
+        precision,
+        bytes as f64 * 1000.0 / body_ms as f64,
+        bytes,
+        body_ms
+    );
+}
+
+/// Function to display the most common bodies of failed responses
+fn display_error_bodies(bodies: &[ErrorBody]) {
+    // Bodies are cut off at this many characters, on a single line
+    const MAX_SHOWN_CHARS: usize = 200;
+    const MAX_SHOWN_BODIES: usize = 10;
+
+    println!("\nError Bodies");
+    if bodies.is_empty() {
+        println!("No failed responses");
+        return;
+    }
+    let total: u64 = bodies.iter().map(|body| body.count).sum();
+    println!(
+        "{} failed responses, {} distinct bodies",
+        total,
+        bodies.len()
+    );
+    println!("Count\tStatus\tBody");
+    for body in bodies.iter().take(MAX_SHOWN_BODIES) {
+        let text = body.body.split_whitespace().collect::<Vec<_>>().join(" ");
+        let shown: String = text.chars().take(MAX_SHOWN_CHARS).collect();
+        let shown = match (shown.is_empty(), shown.len() < text.len()) {
+            (true, _) => "(empty)".to_string(),
+            (false, true) => format!("{}...", shown),
+            (false, false) => shown,
+        };
+        println!("{}\t{}\t{}", body.count, body.status, shown);
+    }
+    if bodies.len() > MAX_SHOWN_BODIES {
+        println!(
+            "... and {} more distinct bodies",
+            bodies.len() - MAX_SHOWN_BODIES
+        );
+    }
+}
+
+/// Function to display loadster's own resource use over the run, to tell a
+/// saturated load generator from a slow server
+fn display_self_metrics(run: &LoadTestRun, lock_wait_ns: u64, precision: usize) {
+    let metrics = process::ProcessMetrics::read();
+    let per_request = lock_wait_ns as f64 / run.responses.len().max(1) as f64;
+    println!("\nLoad Generator");
+    println!(
+        "Peak Memory (RSS)\t{}",
+        process::format_mib(metrics.peak_rss_kib, precision)
+    );
+    println!(
+        "Lock Wait\t{:.*} ms total, {:.*} us per request",
+        precision,
+        lock_wait_ns as f64 / 1e6,
+        precision,
+        per_request / 1e3
+    );
+}
+
+/// Function to display how much --compress-body shrank the request bodies
+fn display_compression(stats: &CompressionStats, precision: usize) {
+    let ratio = if stats.original_bytes > 0 {
+        stats.compressed_bytes as f64 / stats.original_bytes as f64 * 100.0
+    } else {
+        0.0
+    };
+    println!("\nRequest Body Compression");
+    println!("Encoding\t{}", stats.compression.content_encoding());
+    println!("Original Size\t{} bytes", stats.original_bytes);
+    println!(
+        "Compressed Size\t{} bytes ({:.*}% of original)",
+        stats.compressed_bytes, precision, ratio
+    );
+}
+
+/// Function to display the results of the load test
+fn display_results(
+    run: &LoadTestRun,
+    criteria: &SuccessCriteria,
+    precision: usize,
+    palette: &Palette,
+) {
+    let data = &run.responses;
+    let total_requests = data.len() + unanswered(run);
+    let successful_requests = data.iter().filter(|d| is_successful(d, criteria)).count();
+    let failed_requests = total_requests - successful_requests;
+    let total_time: u64 = data.iter().map(|d| d.time).sum();
+    let avg_time = mean(total_time, data.len());
+
+    // Calculate additional metrics
+    let mut times: Vec<u64> = data.iter().map(|d| d.time).collect();
+    times.sort_unstable();
+    let median_time = median(&times);
+    let min_time = times.first().unwrap_or(&0); // Prefix with underscore
+    let max_time = times.last().unwrap_or(&0);
+
+    println!("\nLoad Test Results:");
+    println!("Total Requests: {}", total_requests);
+    println!("Successful Requests: {}", successful_requests);
+    println!("Failed Requests: {}", palette.failures(failed_requests));
+    println!("Total Time: {} ms", total_time);
+    println!("Average Time per Request: {:.*} ms", precision, avg_time);
+    println!("Median Time: {:.*} ms", precision, median_time);
+    println!("Minimum Time: {} ms", min_time);
+    println!("Maximum Time: {} ms", max_time);
+}
+
+/// Function to count the requests of a run that got no response: those that
+/// failed before one arrived and those abandoned with --abandon-percent
+fn unanswered(run: &LoadTestRun) -> usize {
+    (run.request_errors + run.abandoned) as usize
+}
+
+/// Function to average a total over a count, 0 when there is nothing to
+/// average
+fn mean(total: u64, count: usize) -> f64 {
+    if count == 0 {
+        return 0.0;
+    }
+    total as f64 / count as f64
+}
+
+/// Function to find the median of sorted times, averaging the two middle
+/// times of an even number of them
+fn median(sorted_times: &[u64]) -> f64 {
+    let len = sorted_times.len();
+    match len {
+        0 => 0.0,
+        _ if len.is_multiple_of(2) => {
+            (sorted_times[len / 2 - 1] + sorted_times[len / 2]) as f64 / 2.0
+        }
+        _ => sorted_times[len / 2] as f64,
+    }
+}
+
+/// Function to average sorted times without the lowest and highest `percent`
+/// of them each, so a few extreme outliers (e.g. a timeout) don't skew it
+fn trimmed_mean(sorted_times: &[u64], percent: f64) -> f64 {
+    let trimmed = (sorted_times.len() as f64 * percent / 100.0).floor() as usize;
+    let kept = &sorted_times[trimmed..sorted_times.len() - trimmed];
+    if kept.is_empty() {
+        return 0.0;
+    }
+    kept.iter().sum::<u64>() as f64 / kept.len() as f64
+}
+
+/// Function to estimate 95% confidence intervals of percentiles (as
+/// fractions) of sorted times by bootstrapping: the times are resampled with
+/// replacement `resamples` times, and the interval of each percentile spans
+/// the middle 95% of its values over the resamples. Returns the lower and
+/// upper bound of every percentile.
+fn bootstrap_percentiles(
+    sorted_times: &[u64],
+    fractions: &[f64],
+    resamples: u32,
+    rng: &mut StdRng,
+) -> Vec<(u64, u64)> {
+    if sorted_times.is_empty() {
+        return vec![(0, 0); fractions.len()];
+    }
+    let n = sorted_times.len();
+    let mut estimates = vec![Vec::with_capacity(resamples as usize); fractions.len()];
+    // A resample is kept as how often it drew each of the sorted times, so it
+    // is in order without sorting it
+    let mut draws = vec![0u32; n];
+    for _ in 0..resamples {
+        draws.fill(0);
+        for _ in 0..n {
+            draws[rng.random_range(0..n)] += 1;
+        }
+        for (fraction, estimates) in fractions.iter().zip(&mut estimates) {
+            let index = percentile_index(n, *fraction);
+            let mut seen = 0;
+            let position = draws
+                .iter()
+                .position(|&count| {
+                    seen += count as usize;
+                    seen > index
+                })
+                .unwrap_or(n - 1);
+            estimates.push(sorted_times[position]);
+        }
+    }
+    estimates
+        .into_iter()
+        .map(|mut estimates| {
+            estimates.sort_unstable();
+            (percentile(&estimates, 0.025), percentile(&estimates, 0.975))
+        })
+        .collect()
+}
+
+/// Function to pick a percentile (as a fraction, e.g. 0.95) from sorted times
+/// by nearest rank: the lowest time at least that fraction of the times are
+/// at or below. The median of the report averages the two middle times
+/// instead, see `median`.
+fn percentile(sorted_times: &[u64], fraction: f64) -> u64 {
+    if sorted_times.is_empty() {
+        return 0;
+    }
+    sorted_times[percentile_index(sorted_times.len(), fraction)]
+}
+
+/// Function to find the index of a percentile among `len` sorted times, by
+/// nearest rank as `percentile` picks it
+fn percentile_index(len: usize, fraction: f64) -> usize {
+    // Without the epsilon, 0.95 * 20 rounds up past 19 to the next rank
+    let rank = (len as f64 * fraction - 1e-9).ceil() as usize;
+    rank.clamp(1, len) - 1
+}
+
+/// Function to display the P50, P95 and P99 latencies with their 95%
+/// confidence intervals from --bootstrap
+fn display_bootstrap(data: &[ResponseDetails], resamples: u32, seed: Option<u64>) {
+    const FRACTIONS: [(&str, f64); 3] = [("P50", 0.50), ("P95", 0.95), ("P99", 0.99)];
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut times: Vec<u64> = data.iter().map(|d| d.time).collect();
+    times.sort_unstable();
+    let fractions = FRACTIONS.map(|(_, fraction)| fraction);
+    let intervals = bootstrap_percentiles(&times, &fractions, resamples, &mut rng);
+    println!(
+        "\nLatency Confidence Intervals (95%, {} bootstrap resamples)",
+        resamples
+    );
+    println!("Percentile\tEstimate (ms)\tLower (ms)\tUpper (ms)");
+    for ((name, fraction), (lower, upper)) in FRACTIONS.iter().zip(intervals) {
+        println!(
+            "{}\t{}\t{}\t{}",
+            name,
+            percentile(&times, *fraction),
+            lower,
+            upper
+        );
+    }
+}
+
+/// Function to display the runs of a URL file side by side, slowest (by P95)
+/// first
+fn display_url_summary(
+    runs: &[(String, LoadTestRun)],
+    criteria: &SuccessCriteria,
+    precision: usize,
+) {
+    let mut rows: Vec<_> = runs
+        .iter()
+        .map(|(url, run)| {
+            let mut times: Vec<u64> = run.responses.iter().map(|d| d.time).collect();
+            times.sort_unstable();
+            let failed = run
+                .responses
+                .iter()
+                .filter(|d| !is_successful(d, criteria))
+                .count();
+            let avg = times.iter().sum::<u64>() as f64 / times.len().max(1) as f64;
+            (url, run, times, failed, avg)
+        })
+        .collect();
+    rows.sort_by_key(|(_, _, times, _, _)| std::cmp::Reverse(percentile(times, 0.95)));
+
+    println!("\nURL Summary (slowest first)");
+    println!("URL\tRequests\tFailed\tThroughput\tAvg Latency\tP50\tP95\tP99");
+    for (url, run, times, failed, avg) in &rows {
+        println!(
+            "{}\t{}\t{}\t{:.*} req/s\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            url,
+            times.len(),
+            failed,
+            precision,
+            throughput(run.responses.len(), run.elapsed),
+            precision,
+            avg,
+            percentile(times, 0.50),
+            percentile(times, 0.95),
+            percentile(times, 0.99)
+        );
+    }
+}
+
+/// Struct to hold the summary of one concurrency level of --sweep
+#[derive(Serialize)]
+struct SweepRow {
+    users: i32,
+    requests: usize,
+    failed: usize,
+    throughput: f64, // Requests per second
+    avg_ms: f64,
+    p50_ms: u64,
+    p95_ms: u64,
+    p99_ms: u64,
+}
+
+impl SweepRow {
+    fn new(users: i32, run: &LoadTestRun, criteria: &SuccessCriteria) -> Self {
+        let mut times: Vec<u64> = run.responses.iter().map(|d| d.time).collect();
+        times.sort_unstable();
+        SweepRow {
+            users,
+            requests: times.len(),
+            failed: run
+                .responses
+                .iter()
+                .filter(|d| !is_successful(d, criteria))
+                .count(),
+            throughput: throughput(run.responses.len(), run.elapsed),
+            avg_ms: times.iter().sum::<u64>() as f64 / times.len().max(1) as f64,
+            p50_ms: percentile(&times, 0.50),
+            p95_ms: percentile(&times, 0.95),
+            p99_ms: percentile(&times, 0.99),
+        }
+    }
+}
+
+/// Function to display the concurrency levels of --sweep, in the order they
+/// were run
+fn display_sweep(url: &str, rows: &[SweepRow], precision: usize) {
+    println!("\nConcurrency Sweep: {}", url);
+    println!("Users\tRequests\tFailed\tThroughput\tAvg Latency\tP50\tP95\tP99");
+    for row in rows {
+        println!(
+            "{}\t{}\t{}\t{:.*} req/s\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            row.users,
+            row.requests,
+            row.failed,
+            precision,
+            row.throughput,
+            precision,
+            row.avg_ms,
+            row.p50_ms,
+            row.p95_ms,
+            row.p99_ms
+        );
+    }
+}
+
+/// Function to describe a failure to write the output file at `path`
+fn write_error<E: std::fmt::Display>(path: &str) -> impl Fn(E) -> String + '_ {
+    move |e| format!("cannot write to {}: {}", path, e)
+}
+
+/// Function to save the concurrency levels of --sweep to a file, one row per
+/// level, ready to be charted
+fn save_sweep(rows: &[SweepRow], url: &str, args: &Cli, output: &str) -> Result<(), String> {
+    let mut file = File::create(output).map_err(write_error(output))?;
+    if args.format == OutputFormat::Json {
+        let document = serde_json::json!({ "url": url, "levels": rows });
+        serde_json::to_writer_pretty(&mut file, &document).map_err(write_error(output))?;
+        writeln!(file).map_err(write_error(output))?;
+        return Ok(());
+    }
+
+    let separator = if args.format == OutputFormat::Csv {
+        ","
+    } else {
+        "\t"
+    };
+    writeln!(file, "# url: {}", url).map_err(write_error(output))?;
+    writeln!(
+        file,
+        "{}",
+        [
+            "users",
+            "requests",
+            "failed",
+            "throughput",
+            "avg_ms",
+            "p50_ms",
+            "p95_ms",
+            "p99_ms"
+        ]
+        .join(separator)
+    )
+    .map_err(write_error(output))?;
+    for row in rows {
+        let values = [
+            row.users.to_string(),
+            row.requests.to_string(),
+            row.failed.to_string(),
+            format!("{:.*}", args.precision, row.throughput),
+            format!("{:.*}", args.precision, row.avg_ms),
+            row.p50_ms.to_string(),
+            row.p95_ms.to_string(),
+            row.p99_ms.to_string(),
+        ];
+        writeln!(file, "{}", values.join(separator)).map_err(write_error(output))?;
+    }
+    Ok(())
+}
+
+/// Function to calculate the throughput in requests per second over the
+/// wall-clock duration of the run
+fn throughput(total_requests: usize, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 {
+        total_requests as f64 / seconds
+    } else {
+        0.0
+    }
+}
+
+/// Function to compute the goodput of a run: the bytes of successful
+/// responses per second of wall-clock time. Failed responses, redirects
+/// included, delivered nothing useful. Returns the bytes and the rate.
+fn goodput(run: &LoadTestRun, criteria: &SuccessCriteria) -> (u64, f64) {
+    let bytes: u64 = run
+        .responses
+        .iter()
+        .filter(|d| is_successful(d, criteria))
+        .map(|d| d.bytes)
+        .sum();
+    let seconds = run.elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        bytes as f64 / seconds
+    } else {
+        0.0
+    };
+    (bytes, rate)
+}
+
+/// Function to count the responses of each status code, ordered by code so
+/// the report and the output file are the same from run to run
+fn response_codes(responses: &[ResponseDetails]) -> BTreeMap<StatusCode, usize> {
+    let mut codes = BTreeMap::new();
+    for detail in responses {
+        *codes.entry(detail.status).or_insert(0) += 1;
+    }
+    codes
+}
+
+/// Struct to hold the number of requests in a class of status codes
+#[derive(Debug, Clone, Serialize)]
+struct StatusClass {
+    class: String, // `2xx` to `5xx`, `err` for requests that got no response, `exhausted` for those out of file descriptors, or `abandoned`
+    count: u64,
+    percentage: f64, // Of every request sent, including those that got no response
+}
+
+/// Function to group the responses by the first digit of their status code,
+/// with the requests that got no response, such as connection errors, as their
+/// own `err` class. The 2xx to 5xx classes are always listed, 1xx only when a
+/// response had one, `exhausted` only when requests ran out of file
+/// descriptors and `abandoned` only with requests dropped by --abandon-percent.
+fn status_classes(run: &LoadTestRun) -> Vec<StatusClass> {
+    let mut counts = [0u64; 6];
+    for detail in &run.responses {
+        counts[(detail.status.as_u16() / 100).clamp(1, 5) as usize] += 1;
+    }
+    let total = (run.responses.len() as u64 + run.request_errors + run.abandoned).max(1) as f64;
+    let classes = (1..=5)
+        .filter(|&class| class > 1 || counts[class] > 0)
+        .map(|class| (format!("{}xx", class), counts[class]));
+    let abandoned = (run.abandoned > 0).then(|| ("abandoned".to_string(), run.abandoned));
+    let exhausted =
+        (run.resource_exhausted > 0).then(|| ("exhausted".to_string(), run.resource_exhausted));
+    let errors = run.request_errors - run.resource_exhausted;
+    classes
+        .chain([("err".to_string(), errors)])
+        .chain(exhausted)
+        .chain(abandoned)
+        .map(|(class, count)| StatusClass {
+            class,
+            count,
+            percentage: count as f64 * 100.0 / total,
+        })
+        .collect()
+}
+
+/// Struct to hold how latency moved over the course of a run
+#[derive(Debug, Clone, Serialize)]
+struct LatencyTrend {
+    slope_ms_per_minute: f64, // Of the least-squares line through latency against start time
+    significant: bool,        // Whether the slope is more than twice its standard error
+    first_quarter_p95: u64,   // P95 of the requests started in the first quarter of the run, in ms
+    last_quarter_p95: u64,    // The same for the last quarter
+    degraded: bool,           // Whether the last quarter's P95 is well above the first quarter's
+}
+
+/// Function to fit a trend line to the latencies of a run by start time, and
+/// compare the P95 of its first and last quarters, to catch a server that
+/// slows down as the run goes on (leaks, throttling) while the overall
+/// percentiles look fine. None for runs too short to tell: under 20
+/// responses, or started over less than 10 seconds.
+fn latency_trend(data: &[ResponseDetails]) -> Option<LatencyTrend> {
+    // The last quarter is degraded when its P95 is this many times the first
+    // quarter's, and at least this many milliseconds slower
+    const DEGRADED_RATIO: f64 = 1.5;
+    const DEGRADED_MIN_MS: u64 = 5;
+    const MIN_RESPONSES: usize = 20;
+    // Over a shorter span, noise of a millisecond or two in the latencies
+    // scales up to a steep slope per minute
+    const MIN_SPAN_MS: u64 = 10_000;
+
+    let first = data.iter().map(|d| d.started_at_offset_ms).min()?;
+    let last = data.iter().map(|d| d.started_at_offset_ms).max()?;
+    if data.len() < MIN_RESPONSES || last - first < MIN_SPAN_MS {
+        return None;
+    }
+    let n = data.len() as f64;
+    let mean_x = data
+        .iter()
+        .map(|d| d.started_at_offset_ms as f64)
+        .sum::<f64>()
+        / n;
+    let mean_y = data.iter().map(|d| d.time as f64).sum::<f64>() / n;
+    let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+    for detail in data {
+        let dx = detail.started_at_offset_ms as f64 - mean_x;
+        let dy = detail.time as f64 - mean_y;
+        sxx += dx * dx;
+        sxy += dx * dy;
+        syy += dy * dy;
+    }
+    let slope = sxy / sxx; // ms of latency per ms of run time
+    let residuals = (syy - slope * sxy).max(0.0);
+    let standard_error = (residuals / (n - 2.0) / sxx).sqrt();
+    let significant = slope.abs() > 2.0 * standard_error;
+
+    let quarter_p95 = |from: u64, to: u64| {
+        let mut times: Vec<u64> = data
+            .iter()
+            .filter(|d| (from..to).contains(&d.started_at_offset_ms))
+            .map(|d| d.time)
+            .collect();
+        times.sort_unstable();
+        percentile(&times, 0.95)
+    };
+    let quarter = (last - first) / 4;
+    let first_quarter_p95 = quarter_p95(first, first + quarter.max(1));
+    let last_quarter_p95 = quarter_p95(last - quarter, last + 1);
+    let degraded = last_quarter_p95 as f64 > first_quarter_p95 as f64 * DEGRADED_RATIO
+        && last_quarter_p95 >= first_quarter_p95 + DEGRADED_MIN_MS;
+    Some(LatencyTrend {
+        slope_ms_per_minute: slope * 60_000.0,
+        significant,
+        first_quarter_p95,
+        last_quarter_p95,
+        degraded,
+    })
+}
+
+/// Function to display the latency trend of a run, flagging a last quarter
+/// that is much slower than the first
+fn display_latency_trend(trend: &LatencyTrend, precision: usize, palette: &Palette) {
+    println!("\nLatency Trend");
+    println!(
+        "Slope\t{:+.*} ms per minute ({})",
+        precision,
+        trend.slope_ms_per_minute,
+        match (trend.significant, trend.slope_ms_per_minute > 0.0) {
+            (false, _) => "no significant trend",
+            (true, true) => "significantly increasing",
+            (true, false) => "significantly decreasing",
+        }
+    );
+    println!("First Quarter P95\t{} ms", trend.first_quarter_p95);
+    println!("Last Quarter P95\t{} ms", trend.last_quarter_p95);
+    if trend.degraded {
+        println!(
+            "{}",
+            palette.warning(format!(
+                "WARNING: latency degraded during the run, the P95 of the last quarter is {:.*}x that of the first",
+                precision,
+                trend.last_quarter_p95 as f64 / trend.first_quarter_p95.max(1) as f64
+            ))
+        );
+    }
+}
+
+/// Function to give the number of responses a percentile needs to have
+/// `min_samples` of them at or above it
+fn required_samples(fraction: f64, min_samples: u64) -> u64 {
+    (min_samples as f64 / (1.0 - fraction)).round() as u64
+}
+
+/// Function to generate a detailed load test report
+fn generate_report(
+    run: &LoadTestRun,
+    criteria: &SuccessCriteria,
+    url: &str,
+    min_samples: u64,
+    trim_mean: Option<f64>,
+    precision: usize,
+    palette: &Palette,
+) {
+    let data = &run.responses;
+    let responses = data.len();
+    let total_requests = responses + unanswered(run);
+    let successful_requests = data.iter().filter(|d| is_successful(d, criteria)).count();
+    let failed_requests = total_requests - successful_requests;
+    let total_time: u64 = data.iter().map(|d| d.time).sum();
+    let avg_time = mean(total_time, responses);
+
+    // Calculate additional metrics
+    let mut times: Vec<u64> = data.iter().map(|d| d.time).collect();
+    times.sort_unstable();
+    let median_time = median(&times);
+    let max_time = times.last().unwrap_or(&0);
+    let p95_time = percentile(&times, 0.95);
+    let p99_time = percentile(&times, 0.99);
+
+    // Calculate response code distribution
+    let response_codes = response_codes(data);
+
+    // Calculate throughput over the wall-clock duration; the summed request
+    // times overstate the duration as soon as requests run concurrently
+    let duration_seconds = run.elapsed.as_secs_f64();
+    let throughput = throughput(responses, run.elapsed);
+    let (good_bytes, goodput) = goodput(run, criteria);
+
+    println!("\nLoad Test Report");
+    println!("Summary");
+    println!("Metric\tValue");
+    println!("Target URL\t{}", url);
+    println!("Total Requests\t{}", total_requests);
+    println!("Successful Requests\t{}", successful_requests);
+    println!("Failed Requests\t{}", palette.failures(failed_requests));
+    println!("Duration\t{:.*} seconds", precision, duration_seconds);
+    println!(
+        "Throughput\t{} req/s",
+        palette.throughput(format!("{:.*}", precision, throughput), throughput)
+    );
+    println!(
+        "Goodput\t{:.*} bytes/s ({} bytes in successful responses)",
+        precision, goodput, good_bytes
+    );
+    println!(
+        "Avg Latency\t{} ms",
+        palette.latency(format!("{:.*}", precision, avg_time), avg_time)
+    );
+    if let Some(percent) = trim_mean {
+        let trimmed = trimmed_mean(&times, percent);
+        println!(
+            "Trimmed Mean Latency ({}% trimmed each end)\t{} ms",
+            percent,
+            palette.latency(format!("{:.*}", precision, trimmed), trimmed)
+        );
+    }
+    // Percentiles from too few responses are marked, so one slow request
+    // isn't read as the tail of the distribution
+    let caveat = |fraction: f64| {
+        if (responses as u64) < required_samples(fraction, min_samples) {
+            " *"
+        } else {
+            ""
+        }
+    };
+    println!(
+        "P95 Latency\t{} ms{}",
+        palette.latency_ms(p95_time),
+        caveat(0.95)
+    );
+    println!(
+        "P99 Latency\t{} ms{}",
+        palette.latency_ms(p99_time),
+        caveat(0.99)
+    );
+
+    println!("\nResponse Codes");
+    println!("Code\tCount\tPercentage");
+    for (code, count) in &response_codes {
+        println!(
+            "{}\t{}\t{:.*}%",
+            palette.status(*code),
+            count,
+            precision,
+            (*count as f64 / responses as f64) * 100.0
+        );
+    }
+
+    println!("\nResponse Classes");
+    println!("Class\tCount\tPercentage");
+    for class in status_classes(run) {
+        println!(
+            "{}\t{}\t{:.*}%",
+            class.class, class.count, precision, class.percentage
+        );
+    }
+
+    // Time to first byte leaves out the transfer of the body, so it shows
+    // how quickly the server responds whatever the size of the response
+    let mut ttfbs: Vec<u64> = data.iter().map(|d| d.ttfb).collect();
+    ttfbs.sort_unstable();
+    println!("\nLatency Distribution");
+    println!("Percentile\tLatency (ms)\tTTFB (ms)");
+    let p75_time = percentile(&times, 0.75);
+    let median_ttfb = median(&ttfbs);
+    println!(
+        "P50\t{}\t{}",
+        palette.latency(format!("{:.*}", precision, median_time), median_time),
+        palette.latency(format!("{:.*}", precision, median_ttfb), median_ttfb)
+    );
+    for (name, time, fraction) in [
+        ("P75", p75_time, 0.75),
+        ("P95", p95_time, 0.95),
+        ("P99", p99_time, 0.99),
+        ("Max", *max_time, 1.0),
+    ] {
+        let caveat = if fraction < 1.0 { caveat(fraction) } else { "" };
+        println!(
+            "{}{}\t{}\t{}",
+            name,
+            caveat,
+            palette.latency_ms(time),
+            palette.latency_ms(percentile(&ttfbs, fraction))
+        );
+    }
+    for (name, fraction) in [("P75", 0.75), ("P95", 0.95), ("P99", 0.99)] {
+        let required = required_samples(fraction, min_samples);
+        if (responses as u64) < required {
+            println!(
+                "{}",
+                palette.warning(format!(
+                    "* {} is unreliable: {} responses, it needs at least {} for --min-samples {}",
+                    name, responses, required, min_samples
+                ))
+            );
+        }
+    }
+
+    // Additional metrics
+    let min_success_time = data
+        .iter()
+        .filter(|d| is_successful(d, criteria))
+        .map(|d| d.time)
+        .min()
         .unwrap_or(0);
-    let avg_success_time: f64 = data
+    let max_success_time = data
         .iter()
-        .filter(|d| d.status.is_success())
+        .filter(|d| is_successful(d, criteria))
         .map(|d| d.time)
-        .sum::<u64>() as f64
-        / successful_requests as f64;
+        .max()
+        .unwrap_or(0);
+    let avg_success_time = mean(
+        data.iter()
+            .filter(|d| is_successful(d, criteria))
+            .map(|d| d.time)
+            .sum(),
+        successful_requests,
+    );
+
+    let total_bytes: u64 = data.iter().map(|d| d.bytes).sum();
+    let truncated_responses = data.iter().filter(|d| d.truncated()).count();
+
+    println!("\nAdditional Metrics");
+    println!("Min Successful Request Time: {} ms", min_success_time);
+    println!("Max Successful Request Time: {} ms", max_success_time);
+    println!(
+        "Avg Successful Request Time: {:.*} ms",
+        precision, avg_success_time
+    );
+    if data.iter().any(|d| d.body_read) {
+        println!("Total Response Bytes: {}", total_bytes);
+    } else {
+        println!(
+            "Total Response Bytes: {} (from Content-Length or Content-Range, bodies were not read)",
+            total_bytes
+        );
+    }
+    if truncated_responses > 0 {
+        println!(
+            "Responses Over Max Body Size: {} (only the first bytes were read)",
+            truncated_responses
+        );
+    }
+
+    // A server that closes its connections makes every next request on them
+    // pay for a new connection, often because keep-alive is turned off
+    let closed = data.iter().filter(|d| d.connection_close).count();
+    if closed > 0 {
+        println!(
+            "Responses With Connection: close: {} ({:.*}%, the server closed these connections)",
+            closed,
+            precision,
+            closed as f64 / responses as f64 * 100.0
+        );
+    }
+}
+
+/// Function to display how many requests each connection served. Many
+/// connections serving a single request each mean keep-alive isn't working,
+/// whether the client or the server closes them.
+fn display_requests_per_connection(run: &LoadTestRun, precision: usize) {
+    let mut per_connection = run.requests_per_connection.clone();
+    if per_connection.is_empty() {
+        return;
+    }
+    per_connection.sort_unstable();
+    println!("\nRequests per Connection");
+    println!("Connections\tMin\tAvg\tP50\tMax");
+    println!(
+        "{}\t{}\t{:.*}\t{}\t{}",
+        per_connection.len(),
+        per_connection[0],
+        precision,
+        per_connection.iter().sum::<u64>() as f64 / per_connection.len() as f64,
+        percentile(&per_connection, 0.50),
+        per_connection[per_connection.len() - 1]
+    );
+    let single = per_connection.iter().filter(|&&count| count == 1).count();
+    if single > 0 {
+        println!(
+            "Connections That Served a Single Request\t{} ({:.*}%)",
+            single,
+            precision,
+            single as f64 / per_connection.len() as f64 * 100.0
+        );
+    }
+}
+
+/// Function to display the responses to the requests of --inject-errors next
+/// to the others, so the errors the run caused on purpose are told apart
+/// from those of the server
+fn display_injected_errors(
+    data: &[ResponseDetails],
+    criteria: &SuccessCriteria,
+    percent: f64,
+    precision: usize,
+) {
+    let (injected, organic): (Vec<&ResponseDetails>, Vec<&ResponseDetails>) =
+        data.iter().partition(|d| d.injected);
+    let failed = |details: &[&ResponseDetails]| {
+        details
+            .iter()
+            .filter(|d| !is_successful(d, criteria))
+            .count()
+    };
+    let share = |count: usize, total: usize| count as f64 * 100.0 / total.max(1) as f64;
+    let (injected_failed, organic_failed) = (failed(&injected), failed(&organic));
+    println!("\nInjected Errors");
+    println!("Kind\tRequests\tFailed\tError Rate");
+    for (kind, details, failed) in [
+        ("Injected", &injected, injected_failed),
+        ("Organic", &organic, organic_failed),
+    ] {
+        println!(
+            "{}\t{}\t{}\t{:.*}%",
+            kind,
+            details.len(),
+            failed,
+            precision,
+            share(failed, details.len())
+        );
+    }
+    println!(
+        "Injected Share\t{:.*}% of the responses (target {}%)",
+        precision,
+        share(injected.len(), data.len()),
+        percent
+    );
+    // A server that answers every path, such as a single-page app with a
+    // catch-all route, never fails them
+    if injected_failed < injected.len() {
+        println!(
+            "Injected Requests That Succeeded\t{} (the server answered a path that doesn't exist)",
+            injected.len() - injected_failed
+        );
+    }
+}
+
+/// Function to display the slowest and the example requests kept during the run
+fn display_samples(samples: &RequestSamples) {
+    if samples.capacity == 0 || samples.seen == 0 {
+        return;
+    }
+
+    println!("\nSlowest Requests");
+    println!("Started At (ms)\tStatus\tLatency (ms)\tBytes");
+    for detail in &samples.slowest {
+        println!(
+            "{}\t{}\t{}\t{}",
+            detail.started_at_offset_ms,
+            detail.status.as_u16(),
+            detail.time,
+            detail.bytes
+        );
+    }
+
+    let mut sample = samples.sample.clone();
+    sample.sort_by_key(|d| d.started_at_offset_ms);
+    println!(
+        "\nExample Requests (random sample of {} out of {})",
+        sample.len(),
+        samples.seen
+    );
+    println!("Started At (ms)\tStatus\tLatency (ms)\tBytes");
+    for detail in &sample {
+        println!(
+            "{}\t{}\t{}\t{}",
+            detail.started_at_offset_ms,
+            detail.status.as_u16(),
+            detail.time,
+            detail.bytes
+        );
+    }
+}
+
+/// Function to display how many bodies differed from the --golden file, with
+/// a unified diff of the first one that did
+fn display_golden(run: &LoadTestRun, path: &str) {
+    let compared = run.responses.iter().filter(|d| d.golden.is_some()).count();
+    let mismatched = run
+        .responses
+        .iter()
+        .filter(|d| d.golden == Some(false))
+        .count();
+    println!("\nGolden File");
+    println!("File\t{}", path);
+    println!("Compared Responses\t{}", compared);
+    println!("Matching Responses\t{}", compared - mismatched);
+    println!("Mismatched Responses\t{}", mismatched);
+    if let Some(mismatch) = &run.golden_mismatch {
+        println!(
+            "First Mismatch\trequest {}, {} lines removed, {} lines added",
+            mismatch.request, mismatch.removed, mismatch.added
+        );
+        print!("{}", mismatch.diff);
+    }
+}
+
+/// Function to display how many bodies --validate-json parsed and a sample of
+/// those that didn't
+fn display_json_validation(run: &LoadTestRun) {
+    let parsed = run
+        .responses
+        .iter()
+        .filter(|d| d.json_valid.is_some())
+        .count();
+    let malformed = run
+        .responses
+        .iter()
+        .filter(|d| d.json_valid == Some(false))
+        .count();
+    println!("\nJSON Validation");
+    println!("Parsed Responses\t{}", parsed);
+    println!("Valid JSON\t{}", parsed - malformed);
+    println!("Invalid JSON\t{}", malformed);
+    if !run.malformed_json.is_empty() {
+        println!("Request\tError\tBody");
+        for body in &run.malformed_json {
+            println!("{}\t{}\t{:?}", body.request, body.error, body.body);
+        }
+    }
+}
+
+/// Function to display the checksums of the hashed bodies: how many differed
+/// from --expected-checksum, or with --detect-duplicate-checksum the bodies
+/// that more than one response returned, most frequent first
+fn display_checksums(data: &[ResponseDetails], criteria: &SuccessCriteria) {
+    const MAX_SHOWN_DUPLICATES: usize = 10;
+
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for checksum in data.iter().filter_map(|d| d.checksum.as_deref()) {
+        *counts.entry(checksum).or_default() += 1;
+    }
+    let hashed: u64 = counts.values().sum();
+    println!("\nBody Checksums");
+    println!("Hashed Responses\t{}", hashed);
+    if let Some(expected) = &criteria.checksum {
+        let mismatched = data
+            .iter()
+            .filter(|d| checksum_mismatch(d, criteria))
+            .count();
+        println!("Expected SHA-256\t{}", expected);
+        println!("Mismatched Responses\t{}", mismatched);
+    }
+    println!("Distinct Bodies\t{}", counts.len());
+    let mut duplicates: Vec<(&str, u64)> =
+        counts.into_iter().filter(|(_, count)| *count > 1).collect();
+    if criteria.checksum.is_some() || duplicates.is_empty() {
+        return;
+    }
+    duplicates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    println!(
+        "Duplicated Responses\t{}",
+        duplicates.iter().map(|(_, count)| count).sum::<u64>()
+    );
+    println!("Responses\tSHA-256");
+    for (checksum, count) in duplicates.iter().take(MAX_SHOWN_DUPLICATES) {
+        println!("{}\t{}", count, checksum);
+    }
+    if duplicates.len() > MAX_SHOWN_DUPLICATES {
+        println!(
+            "... and {} more duplicated bodies",
+            duplicates.len() - MAX_SHOWN_DUPLICATES
+        );
+    }
+}
+
+/// Function to display how the response bodies fared against --assert-schema,
+/// with the locations that violated it most often
+fn display_schema_validation(data: &[ResponseDetails]) {
+    const MAX_SHOWN_PATHS: usize = 10;
+
+    let mut valid = 0;
+    let mut violating = 0;
+    let mut not_json = 0;
+    let mut paths: HashMap<&str, u64> = HashMap::new();
+    for check in data.iter().filter_map(|d| d.schema.as_ref()) {
+        match check {
+            schema::SchemaCheck::Valid => valid += 1,
+            schema::SchemaCheck::NotJson => not_json += 1,
+            schema::SchemaCheck::Violations(violations) => {
+                violating += 1;
+                for violation in violations {
+                    *paths.entry(violation).or_default() += 1;
+                }
+            }
+        }
+    }
+    println!("\nSchema Validation");
+    println!("Validated Responses\t{}", valid + violating);
+    println!("Violating Responses\t{}", violating);
+    println!("Skipped (not JSON)\t{}", not_json);
+    if paths.is_empty() {
+        return;
+    }
+    let mut paths: Vec<(&str, u64)> = paths.into_iter().collect();
+    paths.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    println!("Responses\tViolation");
+    for (path, count) in paths.iter().take(MAX_SHOWN_PATHS) {
+        println!("{}\t{}", count, path);
+    }
+    if paths.len() > MAX_SHOWN_PATHS {
+        println!("... and {} more violations", paths.len() - MAX_SHOWN_PATHS);
+    }
+}
+
+/// Struct to hold the outcome of one assertion of a run
+///
+/// Run-level assertions, such as --min-throughput, are evaluated once; the
+/// assertions on responses are evaluated once per response they apply to.
+#[derive(Debug, Clone, Serialize)]
+struct AssertionResult {
+    name: String,
+    evaluated: u64,
+    passed: u64,
+    failed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<String>, // Why the assertion failed, for the gate failures
+}
+
+/// Function to evaluate every assertion given on the command line against the
+/// results of a run
+fn check_assertions(run: &LoadTestRun, args: &Cli) -> Vec<AssertionResult> {
+    let mut assertions = vec![];
+    if let Some(min_throughput) = args.min_throughput {
+        let achieved = throughput(run.responses.len(), run.elapsed);
+        let passed = achieved >= min_throughput;
+        assertions.push(AssertionResult {
+            name: format!("throughput >= {:.2} req/s", min_throughput),
+            evaluated: 1,
+            passed: passed as u64,
+            failed: !passed as u64,
+            failure: (!passed).then(|| {
+                format!(
+                    "throughput {:.2} req/s is below the minimum of {:.2} req/s",
+                    achieved, min_throughput
+                )
+            }),
+        });
+    }
+    if let Some(min_reuse) = args.assert_min_reuse {
+        let reuse = connection_reuse(run);
+        let passed = reuse >= min_reuse;
+        assertions.push(AssertionResult {
+            name: format!("connection reuse >= {:.2}%", min_reuse),
+            evaluated: 1,
+            passed: passed as u64,
+            failed: !passed as u64,
+            failure: (!passed).then(|| {
+                format!(
+                    "connection reuse {:.2}% is below the minimum of {:.2}% ({} connections opened for {} responses); the server may be closing connections",
+                    reuse,
+                    min_reuse,
+                    run.connections_opened,
+                    run.responses.len()
+                )
+            }),
+        });
+    }
+    let criteria = SuccessCriteria::new(args);
+    let mut check_responses = |name: String, check: &dyn Fn(&ResponseDetails) -> bool| {
+        let passed = run.responses.iter().filter(|d| check(d)).count() as u64;
+        let failed = run.responses.len() as u64 - passed;
+        assertions.push(AssertionResult {
+            failure: (failed > 0).then(|| {
+                format!(
+                    "{} of {} responses failed the assertion {}",
+                    failed,
+                    run.responses.len(),
+                    name
+                )
+            }),
+            name,
+            evaluated: passed + failed,
+            passed,
+            failed,
+        });
+    };
+    if !criteria.statuses.is_empty() {
+        let statuses: Vec<String> = criteria.statuses.iter().map(u16::to_string).collect();
+        check_responses(format!("status in {}", statuses.join(", ")), &|d| {
+            criteria.accepts_status(d.status)
+        });
+    }
+    if let Some(max_latency) = criteria.max_latency {
+        check_responses(format!("latency <= {} ms", max_latency), &|d| {
+            d.time <= max_latency
+        });
+    }
+    if let Some(expected) = &criteria.checksum {
+        let hashed = run
+            .responses
+            .iter()
+            .filter(|d| d.checksum.is_some())
+            .count() as u64;
+        let failed = run
+            .responses
+            .iter()
+            .filter(|d| checksum_mismatch(d, &criteria))
+            .count() as u64;
+        assertions.push(AssertionResult {
+            name: format!("bodies match checksum {}", expected),
+            evaluated: hashed,
+            passed: hashed - failed,
+            failed,
+            failure: (failed > 0).then(|| {
+                format!(
+                    "{} of {} response bodies don't match the checksum {}",
+                    failed, hashed, expected
+                )
+            }),
+        });
+    }
+    if let Some(path) = &args.assert_schema {
+        let checks = run.responses.iter().filter_map(|d| d.schema.as_ref());
+        let (mut passed, mut failed) = (0, 0);
+        for check in checks {
+            match check {
+                schema::SchemaCheck::Valid => passed += 1,
+                schema::SchemaCheck::Violations(_) => failed += 1,
+                schema::SchemaCheck::NotJson => {}
+            }
+        }
+        assertions.push(AssertionResult {
+            name: format!("responses match schema {}", path),
+            evaluated: passed + failed,
+            passed,
+            failed,
+            failure: (failed > 0).then(|| {
+                format!(
+                    "{} of {} responses violate the schema {}",
+                    failed,
+                    passed + failed,
+                    path
+                )
+            }),
+        });
+    }
+    if let Some(path) = &args.golden {
+        let compared = run.responses.iter().filter(|d| d.golden.is_some()).count() as u64;
+        let failed = run
+            .responses
+            .iter()
+            .filter(|d| d.golden == Some(false))
+            .count() as u64;
+        assertions.push(AssertionResult {
+            name: format!("bodies match golden file {}", path),
+            evaluated: compared,
+            passed: compared - failed,
+            failed,
+            failure: (failed > 0).then(|| {
+                format!(
+                    "{} of {} response bodies differ from the golden file {}",
+                    failed, compared, path
+                )
+            }),
+        });
+    }
+    if args.validate_json {
+        let parsed = run
+            .responses
+            .iter()
+            .filter(|d| d.json_valid.is_some())
+            .count() as u64;
+        let failed = run
+            .responses
+            .iter()
+            .filter(|d| d.json_valid == Some(false))
+            .count() as u64;
+        assertions.push(AssertionResult {
+            name: "bodies are valid JSON".to_string(),
+            evaluated: parsed,
+            passed: parsed - failed,
+            failed,
+            failure: (failed > 0).then(|| {
+                format!(
+                    "{} of {} response bodies are not valid JSON",
+                    failed, parsed
+                )
+            }),
+        });
+    }
+    assertions
+}
+
+/// Function to check the results against the pass/fail gates given on the
+/// command line. Returns a description of every gate that failed.
+fn check_gates(run: &LoadTestRun, args: &Cli) -> Vec<String> {
+    check_assertions(run, args)
+        .into_iter()
+        .filter_map(|assertion| assertion.failure)
+        .collect()
+}
+
+/// Function to check whether any option that check_assertions turns into an
+/// assertion is given
+fn has_gates(args: &Cli) -> bool {
+    args.min_throughput.is_some()
+        || args.assert_min_reuse.is_some()
+        || !args.assert_status.is_empty()
+        || args.assert_max_latency.is_some()
+        || args.expected_checksum.is_some()
+        || args.assert_schema.is_some()
+        || args.golden.is_some()
+        || args.validate_json
+}
+
+/// Function to display a large PASS or FAIL after the report, whether every
+/// gate of every run held, to spot in CI logs at a glance
+fn display_banner(passed: bool, palette: &Palette) {
+    const PASS: [&str; 5] = [
+        "####    ###    ####   ####",
+        "#   #  #   #  #      #",
+        "####   #####   ###    ###",
+        "#      #   #      #      #",
+        "#      #   #  ####   ####",
+    ];
+    const FAIL: [&str; 5] = [
+        "#####   ###   #####  #",
+        "#      #   #    #    #",
+        "####   #####    #    #",
+        "#      #   #    #    #",
+        "#      #   #  #####  #####",
+    ];
+
+    let (banner, color) = if passed { (PASS, GREEN) } else { (FAIL, RED) };
+    println!();
+    for line in banner {
+        println!("{}", palette.paint(line, Some(color)));
+    }
+}
 
-    println!("\nAdditional Metrics");
-    println!("Min Successful Request Time: {} ms", min_success_time);
-    println!("Max Successful Request Time: {} ms", max_success_time);
-    println!("Avg Successful Request Time: {:.2} ms", avg_success_time);
+/// Function to display every assertion of the run with its pass and fail
+/// counts, whether or not the run passed
+fn display_assertions(assertions: &[AssertionResult], palette: &Palette) {
+    println!("\nAssertions");
+    println!("Assertion\tEvaluated\tPassed\tFailed\tResult");
+    for assertion in assertions {
+        let result = if assertion.failed > 0 { "FAIL" } else { "PASS" };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            assertion.name,
+            assertion.evaluated,
+            assertion.passed,
+            palette.failures(assertion.failed as usize),
+            result
+        );
+    }
+}
+
+/// Function to convert an offset from the start of the run into a wall-clock
+/// timestamp in seconds since UNIX_EPOCH
+fn wall_clock_secs(started_at: SystemTime, offset_ms: u64) -> u64 {
+    (started_at + Duration::from_millis(offset_ms))
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Function to save the latencies of a run as an HdrHistogram interval log.
+///
+/// The log holds a single interval covering the whole run, with latencies
+/// recorded in milliseconds, and can be read by HdrHistogram's log tools or
+/// merged with the logs of other load generators.
+fn save_hdr_histogram(run: &LoadTestRun, path: &str) -> Result<(), String> {
+    let mut histogram = Histogram::<u64>::new(3).expect("3 significant digits are supported");
+    for detail in &run.responses {
+        histogram
+            .record(detail.time)
+            .expect("histogram resizes automatically");
+    }
+
+    let mut file = File::create(path).map_err(write_error(path))?;
+    let mut serializer = V2DeflateSerializer::new();
+    let mut writer = IntervalLogWriterBuilder::new()
+        .add_comment(&format!("loadster {}", env!("CARGO_PKG_VERSION")))
+        .add_comment("Latency values are in milliseconds")
+        .with_start_time(run.started_at)
+        .with_base_time(run.started_at)
+        .begin_log_with(&mut file, &mut serializer)
+        .map_err(write_error(path))?;
+    writer
+        .write_histogram(&histogram, Duration::ZERO, run.elapsed, None)
+        .map_err(write_error(path))?;
+    Ok(())
+}
+
+/// Function to save the responses of a run as InfluxDB line protocol points of
+/// the `loadster` measurement, one line per response, appending to the file
+/// when `append` is set
+///
+/// Points are tagged with the URL template, method and status so their series
+/// stay few, and timestamped in nanoseconds since UNIX_EPOCH at the start of
+/// the request.
+fn save_influx(run: &LoadTestRun, path: &str, append: bool) -> Result<(), String> {
+    let file = if append {
+        OpenOptions::new().append(true).create(true).open(path)
+    } else {
+        File::create(path)
+    }
+    .map_err(write_error(path))?;
+    let mut file = std::io::BufWriter::new(file);
+    let started_at = run
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    for detail in &run.responses {
+        let timestamp = started_at + detail.started_at_offset_ns as u128;
+        writeln!(
+            file,
+            "loadster,url={},method={},status={} latency_ms={}i,ttfb_ms={}i,bytes={}i {}",
+            escape_influx_tag(&detail.url_template),
+            escape_influx_tag(&detail.method),
+            detail.status.as_u16(),
+            detail.time,
+            detail.ttfb,
+            detail.bytes,
+            timestamp
+        )
+        .map_err(write_error(path))?;
+    }
+    file.flush().map_err(write_error(path))
+}
+
+/// Function to write the latencies of a run, in milliseconds with three
+/// decimals, one per line: in ascending order, or in the order the requests
+/// were sent after their start in milliseconds since the start of the run
+/// and a space. Lines are streamed through a buffer instead of being built
+/// up in memory, so large runs export quickly.
+fn save_raw_latencies(
+    run: &LoadTestRun,
+    path: &str,
+    timestamps: bool,
+    append: bool,
+) -> Result<(), String> {
+    let file = if append {
+        OpenOptions::new().append(true).create(true).open(path)
+    } else {
+        File::create(path)
+    }
+    .map_err(write_error(path))?;
+    let mut file = std::io::BufWriter::new(file);
+    // Agents of older versions send no end, only the millisecond latency
+    let latency_ns = |detail: &ResponseDetails| match detail.ended_at_offset_ns {
+        0 => detail.time * 1_000_000,
+        ended => ended.saturating_sub(detail.started_at_offset_ns),
+    };
+    let ms = |ns: u64| format!("{}.{:03}", ns / 1_000_000, ns / 1_000 % 1_000);
+    if timestamps {
+        let mut details: Vec<&ResponseDetails> = run.responses.iter().collect();
+        details.sort_by_key(|detail| detail.started_at_offset_ns);
+        for detail in details {
+            writeln!(
+                file,
+                "{} {}",
+                ms(detail.started_at_offset_ns),
+                ms(latency_ns(detail))
+            )
+            .map_err(write_error(path))?;
+        }
+    } else {
+        let mut latencies: Vec<u64> = run.responses.iter().map(latency_ns).collect();
+        latencies.sort_unstable();
+        for latency in latencies {
+            writeln!(file, "{}", ms(latency)).map_err(write_error(path))?;
+        }
+    }
+    file.flush().map_err(write_error(path))
+}
+
+/// Function to escape the commas, equals signs and spaces of a line protocol
+/// tag value. Empty values aren't allowed, so they become `-`.
+fn escape_influx_tag(value: &str) -> String {
+    if value.is_empty() {
+        return "-".to_string();
+    }
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Function to collect the parameters of a run that are recorded at the top of
+/// the results file, so archived results describe themselves
+fn run_metadata(run: &LoadTestRun, url: &str, args: &Cli) -> Vec<(&'static str, Value)> {
+    let method: Method = args.method.clone().into();
+    let mut metadata = vec![
+        ("url", Value::from(url)),
+        ("method", Value::from(method.as_str())),
+        ("users", Value::from(args.users)),
+        ("requests", Value::from(run.requests)),
+        ("duration_ms", Value::from(run.elapsed.as_millis() as u64)),
+        (
+            "started_at",
+            Value::from(wall_clock_secs(run.started_at, 0)),
+        ),
+        ("tcp_nodelay", Value::from(args.tcp_nodelay.is_on())),
+        ("loadster_version", Value::from(env!("CARGO_PKG_VERSION"))),
+    ];
+    if let Some(percent) = args.inject_errors {
+        metadata.push(("inject_errors_percent", Value::from(percent)));
+    }
+    if let Some(rate) = args.max_rps_per_worker {
+        metadata.push(("max_rps_per_worker", Value::from(rate)));
+    }
+    if let Some(jitter) = args.jitter {
+        metadata.push(("jitter_ms", Value::from(jitter)));
+    }
+    if let Some(latency) = &args.network_latency {
+        metadata.push(("network_latency", Value::from(latency.as_str())));
+    }
+    // The anchor of the nanosecond offsets: the wall clock read when the
+    // monotonic clock of the run started
+    if args.ns_timestamps {
+        let started_at = run
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        metadata.push((
+            "started_at_unix_ns",
+            Value::from(started_at.as_nanos() as u64),
+        ));
+    }
+    metadata
 }
 
 /// Function to save the results to a file
-fn save_results(data: &[ResponseDetails], output: &str) {
-    let mut file = File::create(output).expect("Unable to create file");
-    for detail in data {
-        writeln!(file, "{:?}", detail).expect("Unable to write data");
+///
+/// The text and CSV formats start with `# key: value` comment lines holding
+/// the run metadata; the JSON format nests it under a `config` key.
+fn save_results(run: &LoadTestRun, url: &str, args: &Cli, output: &str) -> Result<(), String> {
+    let mut file = File::create(output).map_err(write_error(output))?;
+    let metadata = run_metadata(run, url, args);
+
+    if args.format == OutputFormat::Json {
+        let responses: Vec<Value> = run
+            .responses
+            .iter()
+            .map(|detail| {
+                let mut response = serde_json::json!({
+                    "status": detail.status.as_u16(),
+                    "time": detail.time,
+                    "ttfb": detail.ttfb,
+                    "timestamp": wall_clock_secs(run.started_at, detail.started_at_offset_ms),
+                    "started_at_offset_ms": detail.started_at_offset_ms,
+                    "bytes": detail.bytes,
+                    "bytes_read": detail.bytes_read,
+                });
+                if args.ns_timestamps {
+                    response["started_at_offset_ns"] =
+                        serde_json::json!(detail.started_at_offset_ns);
+                    response["ended_at_offset_ns"] = serde_json::json!(detail.ended_at_offset_ns);
+                }
+                if let Some(ip) = detail.remote_ip {
+                    response["remote_ip"] = serde_json::json!(ip);
+                }
+                if let Some(checksum) = &detail.checksum {
+                    response["checksum"] = serde_json::json!(checksum);
+                }
+                if detail.injected {
+                    response["injected"] = serde_json::json!(true);
+                }
+                response
+            })
+            .collect();
+        let config: serde_json::Map<String, Value> = metadata
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        let sizes = response_sizes(&run.responses);
+        let response_sizes = serde_json::json!({
+            "p50": percentile(&sizes, 0.50),
+            "p95": percentile(&sizes, 0.95),
+            "p99": percentile(&sizes, 0.99),
+            "max": percentile(&sizes, 1.0),
+        });
+        let mut document = serde_json::json!({
+            "config": config,
+            "response_sizes": response_sizes,
+            "goodput_bytes_per_second": goodput(run, &SuccessCriteria::new(args)).1,
+            "connection_reuse_percent": (!args.http3).then(|| connection_reuse(run)),
+            "response_codes": response_codes(&run.responses)
+                .into_iter()
+                .map(|(code, count)| serde_json::json!({"code": code.as_u16(), "count": count}))
+                .collect::<Vec<_>>(),
+            "status_classes": status_classes(run),
+            "latency_trend": latency_trend(&run.responses),
+            "responses": responses,
+        });
+        if args.capture_error_bodies {
+            document["error_bodies"] = serde_json::json!(run.error_bodies);
+        }
+        if args.histogram {
+            document["latency_histogram"] = serde_json::json!({
+                "scale": args.histogram_scale,
+                "buckets": latency_histogram(run, args.histogram_scale),
+            });
+        }
+        if args.adaptive_thinktime {
+            document["think_time_trajectory"] = serde_json::json!(run.think_times);
+        }
+        let assertions = check_assertions(run, args);
+        if !assertions.is_empty() {
+            document["assertions"] = serde_json::json!(assertions);
+        }
+        serde_json::to_writer_pretty(&mut file, &document).map_err(write_error(output))?;
+        writeln!(file).map_err(write_error(output))?;
+        return Ok(());
+    }
+
+    for (key, value) in &metadata {
+        match value {
+            Value::String(value) => writeln!(file, "# {}: {}", key, value),
+            value => writeln!(file, "# {}: {}", key, value),
+        }
+        .map_err(write_error(output))?;
+    }
+    if args.format == OutputFormat::Csv {
+        writeln!(
+            file,
+            "status,time,timestamp,started_at_offset_ms,bytes,bytes_read,ttfb"
+        )
+        .map_err(write_error(output))?;
+    }
+    for detail in &run.responses {
+        let timestamp = wall_clock_secs(run.started_at, detail.started_at_offset_ms);
+        if args.format == OutputFormat::Csv {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{}",
+                detail.status.as_u16(),
+                detail.time,
+                timestamp,
+                detail.started_at_offset_ms,
+                detail.bytes,
+                detail.bytes_read,
+                detail.ttfb
+            )
+        } else {
+            writeln!(
+                file,
+                "ResponseDetails {{ status: {}, time: {}, timestamp: {}, started_at_offset_ms: {}, bytes: {}, bytes_read: {}, ttfb: {} }}",
+                detail.status.as_u16(),
+                detail.time,
+                timestamp,
+                detail.started_at_offset_ms,
+                detail.bytes,
+                detail.bytes_read,
+                detail.ttfb
+            )
+        }
+        .map_err(write_error(output))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn response(status: u16, time: u64) -> ResponseDetails {
+        ResponseDetails {
+            status: StatusCode::from_u16(status).unwrap(),
+            time,
+            ttfb: time,
+            started_at_offset_ms: 0,
+            started_at_offset_ns: 0,
+            ended_at_offset_ns: time * 1_000_000,
+            bytes: 0,
+            bytes_read: 0,
+            body_read: false,
+            url: "http://localhost/".to_string(),
+            method: "GET".to_string(),
+            url_template: "http://localhost/".to_string(),
+            worker: 0,
+            step: None,
+            schema: None,
+            remote_ip: None,
+            version: "HTTP/1.1".to_string(),
+            checksum: None,
+            golden: None,
+            json_valid: None,
+            connection_close: false,
+            injected: false,
+        }
+    }
+
+    /// Function to make a run of the given responses over `elapsed`, as if
+    /// they had been recorded
+    fn recorded(responses: Vec<ResponseDetails>, elapsed: Duration) -> LoadTestRun {
+        LoadTestRun {
+            started_at: UNIX_EPOCH,
+            elapsed,
+            requests: responses.len() as u64,
+            responses,
+            aborted: None,
+            samples: RequestSamples::new(0, StdRng::seed_from_u64(0)),
+            compression: None,
+            connections_opened: 1,
+            prewarmed: None,
+            retries: 0,
+            retry_classes: BTreeMap::new(),
+            breaker_trips: 0,
+            request_errors: 0,
+            resource_exhausted: 0,
+            abandoned: 0,
+            timeouts: vec![],
+            max_duration_reached: false,
+            schedule_lag: vec![],
+            think_times: vec![],
+            scenario: None,
+            lock_wait_ns: None,
+            error_bodies: vec![],
+            golden_mismatch: None,
+            malformed_json: vec![],
+            requests_per_connection: vec![],
+        }
+    }
+
+    /// Function to parse flags as given on the command line
+    fn cli(flags: &[&str]) -> Cli {
+        Cli::try_parse_from(["overloader"].iter().chain(flags)).unwrap()
+    }
+
+    /// Function to run a load test with the given flags, as main would
+    fn run(flags: &[&str]) -> LoadTestRun {
+        let args = cli(&[&["-q"], flags].concat());
+        let inputs = RunInputs::load(&args).unwrap();
+        call_api(args.url.clone().unwrap(), &args, &inputs).unwrap()
+    }
+
+    #[test]
+    fn head_and_options_parse_and_map_to_their_methods() {
+        for (name, method) in [
+            ("head", Method::HEAD),
+            ("HEAD", Method::HEAD),
+            ("options", Method::OPTIONS),
+            ("Options", Method::OPTIONS),
+        ] {
+            assert_eq!(Method::from(name.parse::<HttpMethod>().unwrap()), method);
+        }
+        assert!("trace".parse::<HttpMethod>().is_err());
+    }
+
+    #[test]
+    fn head_requests_read_no_body_and_still_measure_latency() {
+        let url = mock::start(Duration::from_millis(20)).unwrap();
+        // --read-body always would read the body of any other method
+        let run = run(&[
+            "-u",
+            &url,
+            "-m",
+            "head",
+            "-c",
+            "1",
+            "-n",
+            "3",
+            "--read-body",
+            "always",
+        ]);
+        assert_eq!(run.responses.len(), 3);
+        for response in &run.responses {
+            assert_eq!(response.status, StatusCode::OK);
+            assert_eq!(response.method, "HEAD");
+            assert_eq!(response.bytes, 0);
+            assert!(!response.body_read);
+            assert!(response.time >= 20);
+        }
+    }
+
+    #[test]
+    fn options_requests_are_sent_as_options() {
+        let url = mock::start(Duration::ZERO).unwrap();
+        let run = run(&["-u", &url, "-m", "options", "-c", "1", "-n", "2"]);
+        assert_eq!(run.responses.len(), 2);
+        assert!(run
+            .responses
+            .iter()
+            .all(|response| response.method == "OPTIONS"));
+    }
+
+    #[test]
+    fn abort_on_status_stops_the_run_at_the_first_listed_status() {
+        let url = mock::start_with_status(Duration::from_millis(5), 503).unwrap();
+        let run = run(&[
+            "-u",
+            &url,
+            "-c",
+            "2",
+            "-n",
+            "1000",
+            "--abort-on-status",
+            "503",
+        ]);
+        let reason = run.aborted.expect("the run was not aborted");
+        assert_eq!(reason.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(run.responses.len() < 1000);
+    }
+
+    #[test]
+    fn abort_on_status_ignores_statuses_not_listed() {
+        let url = mock::start_with_status(Duration::ZERO, 503).unwrap();
+        let run = run(&["-u", &url, "-c", "1", "-n", "5", "--abort-on-status", "500"]);
+        assert!(run.aborted.is_none());
+        assert_eq!(run.responses.len(), 5);
+    }
+
+    #[test]
+    fn min_throughput_fails_a_run_below_the_floor() {
+        // 100 responses over 10 seconds of wall-clock time are 10 req/s,
+        // however long each of them took
+        let responses = (0..100).map(|_| response(200, 5_000)).collect();
+        let run = recorded(responses, Duration::from_secs(10));
+        let failures = check_gates(
+            &run,
+            &cli(&["-u", "http://localhost/", "--min-throughput", "12.5"]),
+        );
+        assert_eq!(
+            failures,
+            ["throughput 10.00 req/s is below the minimum of 12.50 req/s"]
+        );
+    }
+
+    #[test]
+    fn min_throughput_passes_a_run_at_the_floor() {
+        let responses = (0..100).map(|_| response(200, 5_000)).collect();
+        let run = recorded(responses, Duration::from_secs(10));
+        assert!(check_gates(
+            &run,
+            &cli(&["-u", "http://localhost/", "--min-throughput", "10"])
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn header_placeholders_are_filled_in_for_every_request() {
+        let (url, requests) = mock::start_recording().unwrap();
+        let data_file = std::env::temp_dir().join("loadster-test-header-placeholders.csv");
+        std::fs::write(&data_file, "user\nalice\nbob\n").unwrap();
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let run = run(&[
+            "-u",
+            &url,
+            "-c",
+            "1",
+            "-n",
+            "3",
+            "--data-file",
+            data_file.to_str().unwrap(),
+            "-H",
+            "X-Counter: {counter}",
+            "-H",
+            "X-Uuid: {uuid}",
+            "-H",
+            "X-Timestamp: {timestamp}",
+            "-H",
+            "X-User: user-{user}",
+        ]);
+        let _ = std::fs::remove_file(&data_file);
+        assert_eq!(run.responses.len(), 3);
+        let mut requests: Vec<mock::Request> = requests.try_iter().collect();
+        requests.sort_by_key(|request| request.header("x-counter").unwrap().to_string());
+        let mut uuids = HashSet::new();
+        for (i, request) in requests.iter().enumerate() {
+            assert_eq!(request.header("x-counter"), Some(i.to_string().as_str()));
+            let user = ["user-alice", "user-bob"][i % 2];
+            assert_eq!(request.header("x-user"), Some(user));
+            let uuid = request.header("x-uuid").unwrap();
+            let groups: Vec<usize> = uuid.split('-').map(str::len).collect();
+            assert_eq!(groups, [8, 4, 4, 4, 12], "{} is not a UUID", uuid);
+            uuids.insert(uuid.to_string());
+            let timestamp: u64 = request.header("x-timestamp").unwrap().parse().unwrap();
+            assert!(timestamp >= before && timestamp <= before + 60);
+        }
+        assert_eq!(uuids.len(), 3);
+    }
+
+    #[test]
+    fn compressed_bodies_arrive_whole_with_their_content_encoding() {
+        let body = "the quick brown fox jumps over the lazy dog ".repeat(50);
+        for compression in ["gzip", "br"] {
+            let (url, requests) = mock::start_recording().unwrap();
+            let run = run(&[
+                "-u",
+                &url,
+                "-m",
+                "post",
+                "-c",
+                "1",
+                "-n",
+                "2",
+                "-b",
+                &body,
+                "--compress-body",
+                compression,
+            ]);
+            assert_eq!(run.responses.len(), 2);
+            let requests: Vec<mock::Request> = requests.try_iter().collect();
+            assert_eq!(requests.len(), 2);
+            for request in &requests {
+                assert_eq!(request.header("content-encoding"), Some(compression));
+                assert!(request.body.len() < body.len());
+                let mut decompressed = String::new();
+                match compression {
+                    "gzip" => flate2::read::GzDecoder::new(&request.body[..])
+                        .read_to_string(&mut decompressed)
+                        .unwrap(),
+                    _ => brotli::Decompressor::new(&request.body[..], 4096)
+                        .read_to_string(&mut decompressed)
+                        .unwrap(),
+                };
+                assert_eq!(decompressed, body);
+            }
+            let stats = run.compression.unwrap();
+            assert_eq!(stats.original_bytes, 2 * body.len() as u64);
+            assert_eq!(stats.compressed_bytes, 2 * requests[0].body.len() as u64);
+        }
+    }
+
+    #[test]
+    fn compress_body_needs_a_body() {
+        let args = cli(&["-u", "http://localhost/", "--compress-body", "gzip"]);
+        assert!(RunInputs::load(&args).is_err());
+    }
+
+    /// Function to save a run of two responses in a format and read the file back
+    fn saved_results(format: &str) -> String {
+        let output = std::env::temp_dir().join(format!("loadster-test-results.{}", format));
+        let output = output.to_str().unwrap();
+        let args = cli(&[
+            "-u",
+            "http://localhost/items",
+            "-m",
+            "put",
+            "-c",
+            "4",
+            "-n",
+            "2",
+            "--format",
+            format,
+        ]);
+        let mut run = recorded(
+            vec![response(200, 3), response(503, 7)],
+            Duration::from_millis(1500),
+        );
+        run.started_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        run.responses[1].started_at_offset_ms = 1500;
+        save_results(&run, "http://localhost/items", &args, output).unwrap();
+        let contents = std::fs::read_to_string(output).unwrap();
+        let _ = std::fs::remove_file(output);
+        contents
+    }
+
+    #[test]
+    fn csv_results_start_with_the_run_metadata_as_comments() {
+        let contents = saved_results("csv");
+        let lines: Vec<&str> = contents.lines().collect();
+        let version = format!("# loadster_version: {}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            lines[..8],
+            [
+                "# url: http://localhost/items",
+                "# method: PUT",
+                "# users: 4",
+                "# requests: 2",
+                "# duration_ms: 1500",
+                "# started_at: 1700000000",
+                "# tcp_nodelay: true",
+                version.as_str(),
+            ]
+        );
+        assert_eq!(
+            lines[8],
+            "status,time,timestamp,started_at_offset_ms,bytes,bytes_read,ttfb"
+        );
+        // The timestamps count from the start of the run, not from when the
+        // rows were written
+        assert_eq!(lines[9], "200,3,1700000000,0,0,0,3");
+        assert_eq!(lines[10], "503,7,1700000001,1500,0,0,7");
+        assert_eq!(lines.len(), 11);
+    }
+
+    #[test]
+    fn text_results_stamp_each_response_from_the_start_of_the_run() {
+        let contents = saved_results("text");
+        let lines: Vec<&str> = contents
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "ResponseDetails { status: 200, time: 3, timestamp: 1700000000, started_at_offset_ms: 0, bytes: 0, bytes_read: 0, ttfb: 3 }",
+                "ResponseDetails { status: 503, time: 7, timestamp: 1700000001, started_at_offset_ms: 1500, bytes: 0, bytes_read: 0, ttfb: 7 }",
+            ]
+        );
+    }
+
+    #[test]
+    fn wall_clock_secs_adds_the_offset_and_rounds_down() {
+        let started_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(wall_clock_secs(started_at, 0), 1_700_000_000);
+        assert_eq!(wall_clock_secs(started_at, 999), 1_700_000_000);
+        assert_eq!(wall_clock_secs(started_at, 1000), 1_700_000_001);
+        assert_eq!(wall_clock_secs(started_at, 1500), 1_700_000_001);
+        assert_eq!(wall_clock_secs(started_at, 61_000), 1_700_000_061);
+    }
+
+    #[test]
+    fn json_results_nest_the_run_metadata_under_config() {
+        let document: Value = serde_json::from_str(&saved_results("json")).unwrap();
+        let config = &document["config"];
+        assert_eq!(config["url"], "http://localhost/items");
+        assert_eq!(config["method"], "PUT");
+        assert_eq!(config["users"], 4);
+        assert_eq!(config["requests"], 2);
+        assert_eq!(config["duration_ms"], 1500);
+        assert_eq!(config["started_at"], 1_700_000_000);
+        assert_eq!(config["loadster_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(document["responses"].as_array().unwrap().len(), 2);
+        assert_eq!(document["responses"][0]["timestamp"], 1_700_000_000);
+        assert_eq!(document["responses"][1]["timestamp"], 1_700_000_001);
+    }
+
+    #[test]
+    fn hdr_file_parses_back_into_a_histogram_of_every_response() {
+        use base64::Engine as _;
+        use hdrhistogram::serialization::interval_log::{IntervalLogIterator, LogEntry};
+        use hdrhistogram::serialization::Deserializer;
+
+        let path = std::env::temp_dir().join("loadster-test-latency.hlog");
+        let path = path.to_str().unwrap();
+        let times = [1, 2, 2, 5, 40, 250, 1200];
+        let run = recorded(
+            times.iter().map(|&time| response(200, time)).collect(),
+            Duration::from_secs(2),
+        );
+        save_hdr_histogram(&run, path).unwrap();
+        let log = std::fs::read(path).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let intervals: Vec<_> = IntervalLogIterator::new(&log)
+            .filter_map(|entry| match entry.unwrap() {
+                LogEntry::Interval(interval) => Some(interval),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(intervals[0].duration(), Duration::from_secs(2));
+        let encoded = base64::engine::general_purpose::STANDARD
+            .decode(intervals[0].encoded_histogram())
+            .unwrap();
+        let histogram: Histogram<u64> = Deserializer::new().deserialize(&mut &encoded[..]).unwrap();
+        assert_eq!(histogram.len(), times.len() as u64);
+        assert_eq!(histogram.min(), 1);
+        assert_eq!(histogram.max(), 1200);
+        assert_eq!(histogram.count_at(2), 2);
+    }
+
+    #[test]
+    fn bodies_without_content_length_are_counted_as_read() {
+        let body = "a body streamed in chunks";
+        let url = mock::start_chunked(body).unwrap();
+        for read_body in ["on-assert", "always"] {
+            let run = run(&["-u", &url, "-c", "1", "-n", "3", "--read-body", read_body]);
+            assert_eq!(run.responses.len(), 3);
+            for response in &run.responses {
+                assert_eq!(response.status, StatusCode::OK);
+                assert!(response.body_read);
+                assert_eq!(response.bytes, body.len() as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn bodies_without_content_length_are_left_unread_with_read_body_never() {
+        let url = mock::start_chunked("a body streamed in chunks").unwrap();
+        let run = run(&["-u", &url, "-c", "1", "-n", "3", "--read-body", "never"]);
+        assert_eq!(run.responses.len(), 3);
+        for response in &run.responses {
+            assert_eq!(response.status, StatusCode::OK);
+            assert!(!response.body_read);
+            assert_eq!(response.bytes, 0);
+        }
+    }
+
+    #[test]
+    fn bodies_over_max_body_size_are_kept_up_to_it_and_counted_in_full() {
+        let body = "a".repeat(100);
+        let url = mock::start_with_body(&body).unwrap();
+        let run = run(&[
+            "-u",
+            &url,
+            "-c",
+            "1",
+            "-n",
+            "2",
+            "--read-body",
+            "always",
+            "--max-body-size",
+            "10",
+        ]);
+        assert_eq!(run.responses.len(), 2);
+        for response in &run.responses {
+            assert_eq!(response.bytes_read, 10);
+            assert_eq!(response.bytes, 100);
+            assert!(response.truncated());
+        }
+    }
+
+    #[test]
+    fn max_body_size_of_zero_keeps_the_whole_body() {
+        let body = "a".repeat(100);
+        let url = mock::start_with_body(&body).unwrap();
+        let run = run(&[
+            "-u",
+            &url,
+            "-c",
+            "1",
+            "-n",
+            "2",
+            "--read-body",
+            "always",
+            "--max-body-size",
+            "0",
+        ]);
+        assert_eq!(run.responses.len(), 2);
+        for response in &run.responses {
+            assert_eq!(response.bytes_read, 100);
+            assert_eq!(response.bytes, 100);
+            assert!(!response.truncated());
+        }
+    }
+
+    #[test]
+    fn url_template_report_groups_concrete_urls_by_their_template() {
+        let url = mock::start(Duration::ZERO).unwrap();
+        let data_file = std::env::temp_dir().join("loadster-test-url-template.csv");
+        std::fs::write(&data_file, "id\n1\n2\n3\n").unwrap();
+        let template = format!("{}items/{{id}}", url);
+        let run = run(&[
+            "-u",
+            &template,
+            "-c",
+            "1",
+            "-n",
+            "6",
+            "--data-file",
+            data_file.to_str().unwrap(),
+        ]);
+        let _ = std::fs::remove_file(&data_file);
+        assert_eq!(run.responses.len(), 6);
+        assert!(run
+            .responses
+            .iter()
+            .all(|response| response.url_template == template));
+
+        let criteria = SuccessCriteria::default();
+        let by_url = url_rows(&run.responses, &criteria, false);
+        let mut urls: Vec<&str> = by_url.iter().map(|(url, _, _)| *url).collect();
+        urls.sort_unstable();
+        let expected: Vec<String> = (1..=3).map(|id| format!("{}items/{}", url, id)).collect();
+        assert_eq!(urls, expected);
+        assert!(by_url.iter().all(|(_, times, _)| times.len() == 2));
+
+        let by_template = url_rows(&run.responses, &criteria, true);
+        assert_eq!(by_template.len(), 1);
+        assert_eq!(by_template[0].0, template);
+        assert_eq!(by_template[0].1.len(), 6);
+        assert_eq!(by_template[0].2, 0);
+    }
+
+    #[test]
+    fn max_duration_stops_a_run_short_of_its_requests() {
+        let url = mock::start(Duration::from_millis(100)).unwrap();
+        let run = run(&["-u", &url, "-c", "2", "-n", "1000", "--max-duration", "1"]);
+        assert!(run.max_duration_reached);
+        assert!(run.responses.len() >= 2 && run.responses.len() < 100);
+        assert_eq!(run.requests, 1000);
+        // The run ends once the requests in flight when the cap passed are back
+        assert!(run.elapsed < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn max_duration_is_not_reached_by_a_run_that_finishes_first() {
+        let url = mock::start(Duration::ZERO).unwrap();
+        let run = run(&["-u", &url, "-c", "1", "-n", "3", "--max-duration", "60"]);
+        assert!(!run.max_duration_reached);
+        assert_eq!(run.responses.len(), 3);
+    }
+
+    /// Function to run --repeatable-load against the mock server, as main would
+    fn repeatable_run() -> LoadTestRun {
+        let args = cli(&[
+            "-q",
+            "--repeatable-load",
+            "-c",
+            "2",
+            "-n",
+            "20",
+            "--mock-latency",
+            "5",
+        ]);
+        let url = mock::start(Duration::from_millis(args.mock_latency)).unwrap();
+        let inputs = RunInputs::load(&args).unwrap();
+        call_api(url, &args, &inputs).unwrap()
+    }
+
+    #[test]
+    fn repeatable_load_sends_the_same_requests_on_every_run() {
+        let runs = [repeatable_run(), repeatable_run()];
+        let requests: Vec<Vec<u32>> = runs
+            .iter()
+            .map(|run| {
+                let mut workers: Vec<u32> = run.responses.iter().map(|d| d.worker).collect();
+                workers.sort_unstable();
+                workers
+            })
+            .collect();
+        assert_eq!(requests[0].len(), 20);
+        assert_eq!(requests[0], requests[1]);
+        assert_eq!(runs[0].schedule_lag.len(), 20);
+        assert_eq!(runs[1].schedule_lag.len(), 20);
+    }
+
+    #[test]
+    fn repeatable_load_schedules_requests_at_fixed_times() {
+        let args = cli(&["--repeatable-load", "-c", "4", "--mock-latency", "10"]);
+        let schedule = Schedule::new(&args);
+        // Each of the 4 workers waits twice the mock latency between requests
+        assert_eq!(schedule.due(0), Duration::ZERO);
+        assert_eq!(schedule.due(1), Duration::from_millis(5));
+        assert_eq!(schedule.due(8), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn unwritable_output_files_are_reported_not_panicked_on() {
+        let output = "/nonexistent-loadster-dir/results.txt";
+        let run = recorded(vec![response(200, 1)], Duration::from_secs(1));
+        let args = cli(&["-u", "http://localhost/"]);
+        let e = save_results(&run, "http://localhost/", &args, output).unwrap_err();
+        assert!(e.starts_with("cannot write to /nonexistent-loadster-dir/results.txt: "));
+        let e = save_hdr_histogram(&run, output).unwrap_err();
+        assert!(e.starts_with("cannot write to /nonexistent-loadster-dir/results.txt: "));
+    }
+
+    #[test]
+    fn missing_input_files_are_reported() {
+        let args = cli(&[
+            "-u",
+            "http://localhost/",
+            "--data-file",
+            "/nonexistent-loadster-dir/data.csv",
+        ]);
+        let e = RunInputs::load(&args).err().unwrap();
+        assert!(e.starts_with("cannot read data file /nonexistent-loadster-dir/data.csv: "));
+    }
+
+    #[test]
+    fn requests_without_a_response_count_as_failed() {
+        // A port that was just free, so nothing answers on it
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/", port);
+        let run = run(&["-u", &url, "-c", "1", "-n", "3"]);
+        assert!(run.responses.is_empty());
+        assert_eq!(run.request_errors, 3);
+        assert_eq!(unanswered(&run), 3);
+    }
+
+    #[test]
+    fn mean_of_nothing_is_zero() {
+        assert_eq!(mean(0, 0), 0.0);
+        assert_eq!(mean(10, 4), 2.5);
+    }
+
+    #[test]
+    fn scenario_steps_pause_for_their_own_think_time_outside_their_latency() {
+        let url = mock::start(Duration::ZERO).unwrap();
+        let scenario = std::env::temp_dir().join("loadster-test-scenario-pacing.toml");
+        std::fs::write(
+            &scenario,
+            "[[steps]]\nurl = \"/read\"\nthink_time = 300\n\n[[steps]]\nurl = \"/click\"\n",
+        )
+        .unwrap();
+        let run = run(&[
+            "-u",
+            &url,
+            "--scenario",
+            scenario.to_str().unwrap(),
+            "-c",
+            "1",
+            "-n",
+            "2",
+        ]);
+        let _ = std::fs::remove_file(&scenario);
+        let mut responses = run.responses.clone();
+        responses.sort_by_key(|d| d.started_at_offset_ns);
+        let steps: Vec<Option<usize>> = responses.iter().map(|d| d.step).collect();
+        assert_eq!(steps, [Some(0), Some(1), Some(0), Some(1)]);
+        let gap = |from: &ResponseDetails, to: &ResponseDetails| {
+            Duration::from_nanos(to.started_at_offset_ns - from.ended_at_offset_ns)
+        };
+        // The pause follows the step that has it, and only that step
+        assert!(gap(&responses[0], &responses[1]) >= Duration::from_millis(300));
+        assert!(gap(&responses[1], &responses[2]) < Duration::from_millis(300));
+        assert!(responses.iter().all(|d| d.time < 300));
+    }
+
+    /// The keys and values of the tags or fields of a line protocol point
+    type Pairs = Vec<(String, String)>;
+
+    /// Function to split a line of InfluxDB line protocol at its unescaped
+    /// separators, into the measurement, tags, fields and timestamp
+    fn parse_line_protocol(line: &str) -> (String, Pairs, Pairs, u128) {
+        let mut sections = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    sections.last_mut().unwrap().push(c);
+                    sections.last_mut().unwrap().extend(chars.next());
+                }
+                ' ' => sections.push(String::new()),
+                c => sections.last_mut().unwrap().push(c),
+            }
+        }
+        let [series, fields, timestamp] = &sections[..] else {
+            panic!("{:?} has {} sections, not 3", line, sections.len());
+        };
+        let pairs = |section: &str| -> Pairs {
+            let mut pairs = vec![];
+            let mut current = String::new();
+            let mut chars = section.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        current.extend(chars.next());
+                    }
+                    ',' => pairs.push(std::mem::take(&mut current)),
+                    c => current.push(c),
+                }
+            }
+            pairs.push(current);
+            pairs
+                .into_iter()
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=').expect("key=value");
+                    (key.to_string(), value.to_string())
+                })
+                .collect()
+        };
+        let (measurement, tags) = series.split_once(',').expect("tags");
+        (
+            measurement.to_string(),
+            pairs(tags),
+            pairs(fields),
+            timestamp.parse().expect("integer timestamp"),
+        )
+    }
+
+    #[test]
+    fn influx_file_holds_a_line_protocol_point_per_response() {
+        let path = std::env::temp_dir().join("loadster-test-influx.lp");
+        let path = path.to_str().unwrap();
+        let mut slow = response(503, 250);
+        slow.url_template = "http://localhost/search?q=a b,c=d".to_string();
+        slow.started_at_offset_ns = 1_500_000;
+        let mut run = recorded(vec![response(200, 12), slow], Duration::from_secs(1));
+        run.started_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        save_influx(&run, path, false).unwrap();
+        save_influx(&run, path, true).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "loadster,url=http://localhost/search?q\\=a\\ b\\,c\\=d,method=GET,status=503 latency_ms=250i,ttfb_ms=250i,bytes=0i 1700000000001500000"
+        );
+        let (measurement, tags, fields, timestamp) = parse_line_protocol(lines[1]);
+        assert_eq!(measurement, "loadster");
+        let tag = |name: &str| tags.iter().find(|(key, _)| key == name).unwrap().1.clone();
+        assert_eq!(tag("url"), "http://localhost/search?q=a b,c=d");
+        assert_eq!(tag("method"), "GET");
+        assert_eq!(tag("status"), "503");
+        let field_names: Vec<&str> = fields.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(field_names, ["latency_ms", "ttfb_ms", "bytes"]);
+        assert!(fields.iter().all(|(_, value)| value.ends_with('i')));
+        assert_eq!(timestamp, 1_700_000_000_001_500_000);
+        for line in &lines {
+            parse_line_protocol(line);
+        }
+    }
+
+    #[test]
+    fn empty_influx_tags_are_written_as_a_dash() {
+        assert_eq!(escape_influx_tag(""), "-");
+        assert_eq!(escape_influx_tag("/a,b=c d"), "/a\\,b\\=c\\ d");
+    }
+
+    #[test]
+    fn ignore_body_asks_for_one_byte_and_reads_none() {
+        let (url, requests) = mock::start_recording().unwrap();
+        let run = run(&["-u", &url, "-c", "1", "-n", "2", "--ignore-body"]);
+        assert_eq!(run.responses.len(), 2);
+        for response in &run.responses {
+            assert!(!response.body_read);
+            assert_eq!(response.bytes_read, 0);
+            // Sized by the Content-Length of the mock, which ignores ranges
+            assert_eq!(response.bytes, 2);
+        }
+        let requests: Vec<mock::Request> = requests.try_iter().collect();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.header("range"), Some("bytes=0-0"));
+        }
+    }
+
+    #[test]
+    fn ignore_body_keeps_a_range_given_with_headers() {
+        let (url, requests) = mock::start_recording().unwrap();
+        run(&[
+            "-u",
+            &url,
+            "-c",
+            "1",
+            "-n",
+            "1",
+            "--ignore-body",
+            "-H",
+            "Range: bytes=10-20",
+        ]);
+        let request = requests.try_iter().next().unwrap();
+        assert_eq!(request.header("range"), Some("bytes=10-20"));
+    }
+
+    #[test]
+    fn success_takes_any_2xx_without_assertions() {
+        let criteria = SuccessCriteria::default();
+        assert!(is_successful(&response(200, 10_000), &criteria));
+        assert!(is_successful(&response(204, 1), &criteria));
+        assert!(!is_successful(&response(301, 1), &criteria));
+        assert!(!is_successful(&response(500, 1), &criteria));
+    }
+
+    #[test]
+    fn success_needs_every_assertion_to_hold() {
+        let criteria = SuccessCriteria::new(&cli(&[
+            "-u",
+            "http://localhost/",
+            "--assert-status",
+            "200",
+            "--assert-status",
+            "404",
+            "--assert-max-latency",
+            "100",
+            "--expected-checksum",
+            "ABC123",
+        ]));
+        let mut good = response(404, 100);
+        good.checksum = Some("abc123".to_string());
+        assert!(is_successful(&good, &criteria));
+
+        let mut wrong_status = good.clone();
+        wrong_status.status = StatusCode::CREATED;
+        let mut too_slow = good.clone();
+        too_slow.time = 101;
+        let mut wrong_checksum = good.clone();
+        wrong_checksum.checksum = Some("def456".to_string());
+        let mut violating = good.clone();
+        violating.schema = Some(schema::SchemaCheck::Violations(vec!["/id".to_string()]));
+        let mut not_golden = good.clone();
+        not_golden.golden = Some(false);
+        let mut malformed = good.clone();
+        malformed.json_valid = Some(false);
+        for failed in [
+            wrong_status,
+            too_slow,
+            wrong_checksum,
+            violating,
+            not_golden,
+            malformed,
+        ] {
+            assert!(!is_successful(&failed, &criteria));
+        }
+    }
+
+    #[test]
+    fn success_ignores_the_body_checks_that_did_not_run() {
+        let criteria = SuccessCriteria::new(&cli(&[
+            "-u",
+            "http://localhost/",
+            "--expected-checksum",
+            "abc123",
+        ]));
+        let mut unchecked = response(200, 1);
+        unchecked.schema = Some(schema::SchemaCheck::NotJson);
+        assert!(is_successful(&unchecked, &criteria));
+    }
+
+    #[test]
+    fn a_slow_response_fails_both_the_report_and_the_latency_gate() {
+        let args = cli(&["-u", "http://localhost/", "--assert-max-latency", "50"]);
+        let run = recorded(
+            vec![response(200, 10), response(200, 80), response(500, 10)],
+            Duration::from_secs(1),
+        );
+        let criteria = SuccessCriteria::new(&args);
+        let successful = run
+            .responses
+            .iter()
+            .filter(|d| is_successful(d, &criteria))
+            .count();
+        assert_eq!(successful, 1);
+        assert_eq!(
+            check_gates(&run, &args),
+            ["1 of 3 responses failed the assertion latency <= 50 ms"]
+        );
+    }
+
+    /// Function to run a mix of the URLs /a, /b and /c of an OpenAPI document,
+    /// returning the paths in the order their requests started
+    fn mix_paths(name: &str, flags: &[&str]) -> Vec<String> {
+        let url = mock::start(Duration::ZERO).unwrap();
+        let spec = std::env::temp_dir().join(format!("loadster-test-{}.json", name));
+        std::fs::write(
+            &spec,
+            r#"{"openapi": "3.0.0", "paths": {"/a": {"get": {}}, "/b": {"get": {}}, "/c": {"get": {}}}}"#,
+        )
+        .unwrap();
+        let mut run = run(&[&["-u", &url, "--openapi", spec.to_str().unwrap()], flags].concat());
+        let _ = std::fs::remove_file(&spec);
+        run.responses.sort_by_key(|d| d.started_at_offset_ns);
+        run.responses
+            .iter()
+            .map(|d| d.url.trim_start_matches(url.as_str()).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_visits_the_urls_in_turn() {
+        let paths = mix_paths(
+            "round-robin",
+            &["-c", "1", "-n", "7", "--url-order", "round-robin"],
+        );
+        assert_eq!(paths, ["a", "b", "c", "a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn sequential_visits_every_url_once_whatever_the_requests() {
+        for users in ["1", "3"] {
+            let mut paths = mix_paths(
+                &format!("sequential-{}", users),
+                &["-c", users, "-n", "100", "--url-order", "sequential"],
+            );
+            paths.sort_unstable();
+            assert_eq!(paths, ["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn random_spreads_the_requests_over_every_url() {
+        let paths = mix_paths("random", &["-c", "2", "-n", "300", "--seed", "1"]);
+        assert_eq!(paths.len(), 300);
+        for path in ["a", "b", "c"] {
+            let count = paths.iter().filter(|p| *p == path).count();
+            assert!(
+                (60..=140).contains(&count),
+                "{} requests to /{}",
+                count,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn random_picks_urls_in_proportion_to_their_weight() {
+        let spec = |url: &str, weight: f64| targets::RequestSpec {
+            method: Method::GET,
+            url: url.to_string(),
+            body: None,
+            headers: vec![],
+            weight,
+        };
+        let specs = [spec("light", 1.0), spec("heavy", 3.0)];
+        let mut rng = StdRng::seed_from_u64(7);
+        let heavy = (0..4000)
+            .filter(|_| targets::pick(&specs, &mut rng).url == "heavy")
+            .count();
+        assert!((2800..=3200).contains(&heavy), "{} of 4000 heavy", heavy);
+    }
+
+    #[test]
+    fn trimmed_mean_drops_the_outliers_at_both_ends() {
+        // One timeout of 30 s among nine fast responses and a very fast one
+        let times = [1, 10, 10, 11, 11, 12, 12, 13, 14, 30_000];
+        assert_eq!(mean(times.iter().sum(), times.len()), 3009.4);
+        assert_eq!(trimmed_mean(&times, 10.0), 11.625);
+        assert_eq!(trimmed_mean(&times, 0.0), 3009.4);
+    }
+
+    #[test]
+    fn trimmed_mean_rounds_the_trimmed_count_down() {
+        // 10% of 15 times trims one from each end, not two
+        let times: Vec<u64> = (1..=15).collect();
+        assert_eq!(trimmed_mean(&times, 10.0), 8.0);
+        assert_eq!(trimmed_mean(&[1, 2, 100], 10.0), 103.0 / 3.0);
+    }
+
+    #[test]
+    fn trimmed_mean_of_no_times_is_zero() {
+        assert_eq!(trimmed_mean(&[], 10.0), 0.0);
+    }
+
+    #[test]
+    fn trimmed_mean_keeps_the_middle_at_the_largest_percent() {
+        assert_eq!(trimmed_mean(&[5, 6, 700], 49.0), 6.0);
+        assert_eq!(trimmed_mean(&[5, 7], 49.0), 6.0);
+    }
+
+    #[test]
+    fn huge_concurrency_is_refused_with_a_clear_error() {
+        let e = check_concurrency(&cli(&["-u", "http://localhost/", "-c", "100000"])).unwrap_err();
+        assert!(
+            e.starts_with("100000 concurrent users would start as many threads"),
+            "{}",
+            e
+        );
+        assert!(e.contains("--allow-unsafe-concurrency"));
+    }
+
+    #[test]
+    fn concurrency_up_to_the_safe_limit_is_allowed() {
+        let users = MAX_SAFE_USERS.to_string();
+        assert!(check_concurrency(&cli(&["-u", "http://localhost/", "-c", &users])).is_ok());
+    }
+
+    #[test]
+    fn allow_unsafe_concurrency_lifts_the_limit() {
+        let args = cli(&[
+            "-u",
+            "http://localhost/",
+            "-c",
+            "100000",
+            "--allow-unsafe-concurrency",
+        ]);
+        assert!(check_concurrency(&args).is_ok());
+    }
+
+    #[test]
+    fn the_limit_counts_the_largest_sweep_level_and_both_ab_targets() {
+        let sweep = cli(&["-u", "http://localhost/", "--sweep", "10,20000"]);
+        assert!(check_concurrency(&sweep).is_err());
+        let users = (MAX_SAFE_USERS / 2 + 1).to_string();
+        let ab = cli(&[
+            "--url-a",
+            "http://localhost/a",
+            "--url-b",
+            "http://localhost/b",
+            "-c",
+            &users,
+        ]);
+        assert!(check_concurrency(&ab).is_err());
+    }
+
+    #[test]
+    fn replayed_har_requests_reach_the_server_with_their_own_headers() {
+        let (url, requests) = mock::start_recording().unwrap();
+        let har = std::env::temp_dir().join("loadster-test-replay-headers.har");
+        std::fs::write(
+            &har,
+            r#"{"log": {"entries": [
+                {"startedDateTime": "2024-05-01T10:00:00Z", "request": {
+                    "method": "POST", "url": "https://shop.example.com/cart",
+                    "headers": [
+                        {"name": "Content-Type", "value": "application/json"},
+                        {"name": "Cookie", "value": "session=abc"},
+                        {"name": "X-Team", "value": "captured"}
+                    ],
+                    "postData": {"text": "{\"qty\": 2}"}}},
+                {"startedDateTime": "2024-05-01T10:00:01Z", "request": {
+                    "method": "GET", "url": "https://shop.example.com/account?tab=orders",
+                    "headers": [{"name": "Authorization", "value": "Bearer t0ken"}]}}
+            ]}}"#,
+        )
+        .unwrap();
+        let run = run(&[
+            "-u",
+            &url,
+            "--replay-log",
+            har.to_str().unwrap(),
+            "--log-format",
+            "har",
+            "-c",
+            "1",
+            "-H",
+            "X-Team: default",
+            "-H",
+            "X-Run: 42",
+        ]);
+        let _ = std::fs::remove_file(&har);
+        assert_eq!(run.responses.len(), 2);
+        let mut requests: Vec<mock::Request> = requests.try_iter().collect();
+        requests.sort_by_key(|request| request.target.clone());
+        let [account, cart] = &requests[..] else {
+            panic!("{} requests", requests.len());
+        };
+        assert_eq!(cart.method, "POST");
+        assert_eq!(cart.target, "/cart");
+        assert_eq!(cart.header("content-type"), Some("application/json"));
+        assert_eq!(cart.header("cookie"), Some("session=abc"));
+        assert_eq!(cart.body, b"{\"qty\": 2}");
+        assert_eq!(account.method, "GET");
+        assert_eq!(account.target, "/account?tab=orders");
+        assert_eq!(account.header("authorization"), Some("Bearer t0ken"));
+        assert_eq!(account.header("cookie"), None);
+        // --headers apply to every request unless the capture has its own
+        assert_eq!(cart.header("x-team"), Some("captured"));
+        assert_eq!(
+            cart.headers
+                .iter()
+                .filter(|(name, _)| name == "x-team")
+                .count(),
+            1
+        );
+        assert_eq!(account.header("x-team"), Some("default"));
+        assert!(requests
+            .iter()
+            .all(|request| request.header("x-run") == Some("42")));
+    }
+
+    #[test]
+    fn bootstrap_intervals_hold_the_percentiles_of_the_times() {
+        let times: Vec<u64> = (1..=200).collect();
+        let fractions = [0.50, 0.95, 0.99];
+        let mut rng = StdRng::seed_from_u64(3);
+        let intervals = bootstrap_percentiles(&times, &fractions, 500, &mut rng);
+        for (&fraction, &(lower, upper)) in fractions.iter().zip(&intervals) {
+            let estimate = percentile(&times, fraction);
+            assert!(
+                lower <= estimate && estimate <= upper,
+                "{} not in {}-{}",
+                estimate,
+                lower,
+                upper
+            );
+        }
+        // P50 of 200 evenly spread times varies by a few ranks from resample
+        // to resample, not by half the range
+        let (lower, upper) = intervals[0];
+        assert!(upper - lower < 40, "{}-{}", lower, upper);
+        assert!(intervals[2].1 <= 200);
+    }
+
+    #[test]
+    fn bootstrap_intervals_of_equal_times_have_no_width() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            bootstrap_percentiles(&[7; 50], &[0.50, 0.99], 100, &mut rng),
+            [(7, 7), (7, 7)]
+        );
+    }
+
+    #[test]
+    fn bootstrap_intervals_of_one_time_pick_it() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            bootstrap_percentiles(&[42], &[0.50], 100, &mut rng),
+            [(42, 42)]
+        );
+    }
+
+    #[test]
+    fn bootstrap_intervals_of_no_times_are_zero() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            bootstrap_percentiles(&[], &[0.50, 0.95], 100, &mut rng),
+            [(0, 0), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn bootstrap_intervals_are_the_same_for_the_same_seed() {
+        let mut sorted: Vec<u64> = (0..100).map(|i| i * i % 97).collect();
+        sorted.sort_unstable();
+        let intervals = |seed| {
+            bootstrap_percentiles(&sorted, &[0.5, 0.9], 200, &mut StdRng::seed_from_u64(seed))
+        };
+        assert_eq!(intervals(9), intervals(9));
+    }
+
+    #[test]
+    fn response_codes_are_counted_in_ascending_order() {
+        let responses: Vec<ResponseDetails> = [503, 200, 404, 200, 201, 500, 200]
+            .into_iter()
+            .map(|status| response(status, 1))
+            .collect();
+        let codes: Vec<(u16, usize)> = response_codes(&responses)
+            .into_iter()
+            .map(|(code, count)| (code.as_u16(), count))
+            .collect();
+        assert_eq!(codes, [(200, 3), (201, 1), (404, 1), (500, 1), (503, 1)]);
+    }
+
+    #[test]
+    fn median_averages_the_two_middle_times_of_an_even_number() {
+        assert_eq!(median(&[1, 2, 3, 4]), 2.5);
+        assert_eq!(median(&[10, 20, 30, 40, 50, 60]), 35.0);
+    }
+
+    #[test]
+    fn median_takes_the_middle_time_of_an_odd_number() {
+        assert_eq!(median(&[1, 2, 3]), 2.0);
+        assert_eq!(median(&[7]), 7.0);
+    }
+
+    #[test]
+    fn median_of_no_times_is_zero() {
+        assert_eq!(median(&[]), 0.0);
+    }
+
+    #[test]
+    fn percentile_ranks_the_tail_without_rounding_up_to_the_maximum() {
+        let times: Vec<u64> = (1..=20).collect();
+        assert_eq!(percentile(&times, 0.95), 19);
+        assert_eq!(percentile(&times, 1.0), 20);
+        assert_eq!(percentile(&times, 0.0), 1);
+    }
+
+    #[test]
+    fn percentile_of_no_times_is_zero() {
+        assert_eq!(percentile(&[], 0.50), 0);
+    }
+
+    #[test]
+    fn validate_json_alone_is_a_gate() {
+        assert!(!has_gates(&cli(&["-u", "http://localhost/"])));
+        assert!(has_gates(&cli(&[
+            "-u",
+            "http://localhost/",
+            "--validate-json"
+        ])));
+    }
+
+    #[test]
+    fn fail_fast_requires_a_scenario() {
+        assert_eq!(
+            check_option_combinations(&cli(&["-u", "http://localhost/", "--fail-fast"])),
+            Err("--fail-fast requires --scenario".to_string())
+        );
+    }
+
+    #[test]
+    fn preserve_timing_requires_a_replay_log() {
+        assert_eq!(
+            check_option_combinations(&cli(&["-u", "http://localhost/", "--preserve-timing"])),
+            Err("--preserve-timing requires --replay-log".to_string())
+        );
+    }
+
+    #[test]
+    fn retry_on_requires_retries() {
+        assert_eq!(
+            check_option_combinations(&cli(&["-u", "http://localhost/", "--retry-on", "503"])),
+            Err("--retry-on requires --retries".to_string())
+        );
+        assert_eq!(
+            check_option_combinations(&cli(&[
+                "-u",
+                "http://localhost/",
+                "--retry-on",
+                "503",
+                "--retries",
+                "2"
+            ])),
+            Ok(())
+        );
     }
 }
@@ -0,0 +1,266 @@
+//! The built-in mock server `--repeatable-load` runs against, which the
+//! `serve` subcommand also runs on its own.
+//!
+//! For `--repeatable-load` it listens on an ephemeral loopback port. It answers every request with
+//! `200 OK` and a two-byte body after a fixed delay, keeping connections
+//! alive (with no body for HEAD requests), so the time a request takes beyond that delay is spent in loadster
+//! and the loopback stack rather than in a real server.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+#[cfg(test)]
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::distributed::with_default_host;
+
+/// The head of the response to every request
+const HEAD: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: 2\r\n\r\n";
+
+/// The body of the response to every request
+const BODY: &[u8] = b"ok";
+
+/// Command line arguments of the `serve` subcommand
+#[derive(Args, Clone)]
+pub(crate) struct ServeArgs {
+    /// The address to listen on, e.g. :8080 or 127.0.0.1:8080 (default: 127.0.0.1:8080)
+    #[clap(
+        long,
+        default_value = "127.0.0.1:8080",
+        help = "The address to listen on, e.g. :8080 or 127.0.0.1:8080 (default: 127.0.0.1:8080)"
+    )]
+    listen: String,
+
+    /// How long the server takes to answer each request in milliseconds (default: 0)
+    #[clap(
+        long,
+        default_value_t = 0,
+        help = "How long the server takes to answer each request in milliseconds (default: 0)"
+    )]
+    latency: u64,
+}
+
+/// Struct to hold how the mock server answers
+#[derive(Clone)]
+struct Answer {
+    latency: Duration,
+    head: Vec<u8>,                     // Status line and headers of every response
+    body: Vec<u8>,                     // Body of every response but those to HEAD requests
+    requests: Option<Sender<Request>>, // Where to hand every request received, for tests
+}
+
+/// Struct to hold a request the mock server received
+#[derive(Debug)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) target: String, // The path and query of the request line
+    pub(crate) headers: Vec<(String, String)>, // Names in lowercase
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// Function to get the value of the first header of a name, in lowercase
+    #[cfg(test)]
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Function to start the mock server in the background, returning its URL
+pub(crate) fn start(latency: Duration) -> Result<String, String> {
+    start_answering(Answer {
+        latency,
+        head: HEAD.to_vec(),
+        body: BODY.to_vec(),
+        requests: None,
+    })
+}
+
+/// Function to start a mock server that answers with another status, for
+/// tests of how a run reacts to it
+#[cfg(test)]
+pub(crate) fn start_with_status(latency: Duration, status: u16) -> Result<String, String> {
+    let reason = reqwest::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("");
+    let head = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: text/plain\r\ncontent-length: 2\r\n\r\n",
+        status, reason
+    );
+    start_answering(Answer {
+        latency,
+        head: head.into_bytes(),
+        body: BODY.to_vec(),
+        requests: None,
+    })
+}
+
+/// Function to start a mock server that streams `body` in two chunks with
+/// no Content-Length, as servers that stream do
+#[cfg(test)]
+pub(crate) fn start_chunked(body: &str) -> Result<String, String> {
+    let (first, second) = body.split_at(body.len() / 2);
+    let chunks = format!(
+        "{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+        first.len(),
+        first,
+        second.len(),
+        second
+    );
+    start_answering(Answer {
+        latency: Duration::ZERO,
+        head: b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ntransfer-encoding: chunked\r\n\r\n"
+            .to_vec(),
+        body: chunks.into_bytes(),
+        requests: None,
+    })
+}
+
+/// Function to start a mock server that answers with `body` and its
+/// Content-Length, for tests of bodies other than the two bytes
+#[cfg(test)]
+pub(crate) fn start_with_body(body: &str) -> Result<String, String> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\n\r\n",
+        body.len()
+    );
+    start_answering(Answer {
+        latency: Duration::ZERO,
+        head: head.into_bytes(),
+        body: body.as_bytes().to_vec(),
+        requests: None,
+    })
+}
+
+/// Function to start a mock server that also hands every request it receives
+/// to the returned receiver, for tests of what a run sends
+#[cfg(test)]
+pub(crate) fn start_recording() -> Result<(String, Receiver<Request>), String> {
+    let (sender, receiver) = mpsc::channel();
+    let url = start_answering(Answer {
+        latency: Duration::ZERO,
+        head: HEAD.to_vec(),
+        body: BODY.to_vec(),
+        requests: Some(sender),
+    })?;
+    Ok((url, receiver))
+}
+
+/// Function to start a mock server on an ephemeral loopback port
+fn start_answering(answer: Answer) -> Result<String, String> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .map_err(|e| format!("cannot start the mock server: {}", e))?;
+    let address = listener
+        .local_addr()
+        .map_err(|e| format!("cannot start the mock server: {}", e))?;
+    let mock = thread::Builder::new().name("loadster-mock".to_string());
+    mock.spawn(move || accept(listener, answer))
+        .map_err(|e| format!("cannot start the mock server: {}", e))?;
+    Ok(format!("http://{}/", address))
+}
+
+/// Function to carry out the `serve` subcommand: run the mock server until
+/// the process is stopped
+pub(crate) fn run(args: &ServeArgs) -> Result<(), String> {
+    let address = with_default_host(&args.listen);
+    let listener =
+        TcpListener::bind(&address).map_err(|e| format!("cannot listen on {}: {}", address, e))?;
+    println!(
+        "Mock server listening on http://{}/ ({} ms latency)",
+        address, args.latency
+    );
+    accept(
+        listener,
+        Answer {
+            latency: Duration::from_millis(args.latency),
+            head: HEAD.to_vec(),
+            body: BODY.to_vec(),
+            requests: None,
+        },
+    );
+    Ok(())
+}
+
+/// Function to answer every connection accepted by `listener` on a thread of
+/// its own
+fn accept(listener: TcpListener, answer: Answer) {
+    for stream in listener.incoming().flatten() {
+        let connection = thread::Builder::new().name("loadster-mock".to_string());
+        let answer = answer.clone();
+        let _ = connection.spawn(move || serve(stream, answer));
+    }
+}
+
+/// Function to answer the requests of one connection until the client closes
+/// it
+fn serve(stream: TcpStream, answer: Answer) {
+    let _ = stream.set_nodelay(true);
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader) {
+        thread::sleep(answer.latency);
+        let mut response = answer.head.clone();
+        // The response to a HEAD request has the headers of the body, but no body
+        if request.method != "HEAD" {
+            response.extend_from_slice(&answer.body);
+        }
+        if let Some(requests) = &answer.requests {
+            let _ = requests.send(request);
+        }
+        if writer.write_all(&response).is_err() {
+            return;
+        }
+    }
+}
+
+/// Function to read the head and body of the next request, None once the
+/// connection is closed or the request is malformed
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut request_line = line.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+    let mut headers = vec![];
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+            if name == "content-length" {
+                content_length = value.parse().ok()?;
+            }
+            headers.push((name, value));
+        }
+    }
+    // Chunked request bodies aren't supported; loadster sends bodies whole
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        target,
+        headers,
+        body,
+    })
+}
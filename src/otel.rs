@@ -0,0 +1,246 @@
+//! OpenTelemetry spans for every request (`--otel-endpoint`, with the `otel`
+//! feature).
+//!
+//! Every request gets a client span of its own trace and carries it in a W3C
+//! `traceparent` header, so the spans of the server link up under it. Spans
+//! are queued to an exporter thread, which sends them in batches to the
+//! collector as OTLP over HTTP, in its JSON encoding, so the workers never
+//! wait for the collector.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+/// The most spans sent to the collector at once
+const BATCH_SIZE: usize = 512;
+
+/// How long a span waits at most for its batch to fill up
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Struct to hold the trace and span IDs of a request
+pub(crate) struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+}
+
+impl SpanContext {
+    /// Draw new IDs; both must be nonzero to be valid
+    pub(crate) fn new() -> Self {
+        SpanContext {
+            trace_id: rand::random::<u128>().max(1),
+            span_id: rand::random::<u64>().max(1),
+        }
+    }
+
+    /// The `traceparent` header of the request, sampled
+    pub(crate) fn traceparent(&self) -> String {
+        format!(
+            "traceparent: 00-{:032x}-{:016x}-01",
+            self.trace_id, self.span_id
+        )
+    }
+}
+
+/// Struct to hold a finished span, waiting to be exported
+struct Span {
+    context: SpanContext,
+    method: String,
+    url: String,
+    status: Option<u16>, // None for requests that got no response
+    error: Option<String>,
+    end: SystemTime,
+    latency: Duration,
+}
+
+/// A message to the exporter thread
+enum Message {
+    Span(Span),
+    Flush(mpsc::Sender<()>),
+}
+
+/// Struct to hold the queue of the exporter thread and what it exported
+pub(crate) struct Tracer {
+    endpoint: String,
+    sender: Option<mpsc::Sender<Message>>, // Taken when the tracer is dropped
+    exporter: Option<JoinHandle<()>>,
+    exported: Arc<AtomicU64>,
+    failed: Arc<AtomicU64>, // Spans of batches the collector didn't accept
+}
+
+impl Tracer {
+    /// Start the exporter thread for a collector. An endpoint such as
+    /// `http://localhost:4318` gets the OTLP traces path appended.
+    pub(crate) fn start(endpoint: &str) -> Result<Self, String> {
+        let endpoint = if endpoint.trim_end_matches('/').ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+        };
+        reqwest::Url::parse(&endpoint)
+            .map_err(|e| format!("invalid --otel-endpoint {}: {}", endpoint, e))?;
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("cannot build the OTLP client: {}", e))?;
+        let (sender, receiver) = mpsc::channel();
+        let exported = Arc::new(AtomicU64::new(0));
+        let failed = Arc::new(AtomicU64::new(0));
+        let exporter = {
+            let endpoint = endpoint.clone();
+            let (exported, failed) = (exported.clone(), failed.clone());
+            thread::Builder::new()
+                .name("loadster-otel".to_string())
+                .spawn(move || {
+                    let export = |batch: &mut Vec<Span>| {
+                        if batch.is_empty() {
+                            return;
+                        }
+                        let accepted = client
+                            .post(&endpoint)
+                            .json(&document(batch))
+                            .send()
+                            .is_ok_and(|res| res.status().is_success());
+                        let counter = if accepted { &exported } else { &failed };
+                        counter.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        batch.clear();
+                    };
+                    let mut batch = vec![];
+                    loop {
+                        match receiver.recv_timeout(BATCH_DELAY) {
+                            Ok(Message::Span(span)) => {
+                                batch.push(span);
+                                if batch.len() >= BATCH_SIZE {
+                                    export(&mut batch);
+                                }
+                            }
+                            Ok(Message::Flush(done)) => {
+                                export(&mut batch);
+                                let _ = done.send(());
+                            }
+                            Err(RecvTimeoutError::Timeout) => export(&mut batch),
+                            Err(RecvTimeoutError::Disconnected) => {
+                                export(&mut batch);
+                                return;
+                            }
+                        }
+                    }
+                })
+                .map_err(|e| format!("cannot start the OTLP exporter: {}", e))?
+        };
+        Ok(Tracer {
+            endpoint,
+            sender: Some(sender),
+            exporter: Some(exporter),
+            exported,
+            failed,
+        })
+    }
+
+    /// Queue the span of a request that ended now, after `latency`
+    pub(crate) fn record(
+        &self,
+        context: SpanContext,
+        method: &str,
+        url: &str,
+        status: Result<u16, String>,
+        latency: Duration,
+    ) {
+        let (status, error) = match status {
+            Ok(status) => (Some(status), None),
+            Err(e) => (None, Some(e)),
+        };
+        let span = Span {
+            context,
+            method: method.to_string(),
+            url: url.to_string(),
+            status,
+            error,
+            end: SystemTime::now(),
+            latency,
+        };
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Message::Span(span));
+        }
+    }
+
+    /// Export the spans still queued and display how many were exported
+    pub(crate) fn finish(&self) {
+        let (done, wait) = mpsc::channel();
+        let queued = self
+            .sender
+            .as_ref()
+            .is_some_and(|sender| sender.send(Message::Flush(done)).is_ok());
+        if queued {
+            let _ = wait.recv();
+        }
+        println!("\nOpenTelemetry");
+        println!("Collector\t{}", self.endpoint);
+        println!("Exported Spans\t{}", self.exported.load(Ordering::Relaxed));
+        println!("Failed Spans\t{}", self.failed.load(Ordering::Relaxed));
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        // A closed queue ends the exporter once it sent the last batch
+        drop(self.sender.take());
+        if let Some(exporter) = self.exporter.take() {
+            let _ = exporter.join();
+        }
+    }
+}
+
+/// Function to build the OTLP JSON document of a batch of spans
+fn document(batch: &[Span]) -> Value {
+    let nanos = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string()
+    };
+    let spans: Vec<Value> = batch
+        .iter()
+        .map(|span| {
+            let mut attributes = vec![
+                json!({"key": "http.request.method", "value": {"stringValue": span.method}}),
+                json!({"key": "url.full", "value": {"stringValue": span.url}}),
+                json!({"key": "loadster.latency_ms", "value": {"doubleValue": span.latency.as_secs_f64() * 1000.0}}),
+            ];
+            if let Some(status) = span.status {
+                attributes.push(json!({"key": "http.response.status_code", "value": {"intValue": status.to_string()}}));
+            }
+            if let Some(error) = &span.error {
+                attributes.push(json!({"key": "error.type", "value": {"stringValue": error}}));
+            }
+            // Status codes 1 and 2 are OK and ERROR; 4xx and 5xx responses
+            // are errors of a client span
+            let failed = span.status.is_none_or(|status| status >= 400);
+            json!({
+                "traceId": format!("{:032x}", span.context.trace_id),
+                "spanId": format!("{:016x}", span.context.span_id),
+                "name": span.method,
+                "kind": 3,
+                "startTimeUnixNano": nanos(span.end - span.latency),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": attributes,
+                "status": {"code": if failed { 2 } else { 1 }},
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": "loadster"}}],
+            },
+            "scopeSpans": [{
+                "scope": {"name": "loadster", "version": "1.0.0"},
+                "spans": spans,
+            }],
+        }],
+    })
+}
@@ -0,0 +1,440 @@
+//! HTTP/1.1 pipelining benchmarks (`--pipeline`).
+//!
+//! reqwest never pipelines: it waits for the response to a request before it
+//! sends the next one on the same connection. This mode speaks HTTP/1.1 over
+//! plain TCP sockets itself instead. Every connection writes `depth` requests
+//! back to back, then reads their responses, which HTTP/1.1 sends in request
+//! order, so the n-th response answers the n-th request. Before that, the same
+//! number of requests is sent one at a time over as many connections, as the
+//! baseline the gain of pipelining is measured against.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+use crate::{percentile, throughput};
+
+/// Struct to hold what to send and how
+pub(crate) struct Pipeline<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) method: &'a str,
+    pub(crate) headers: &'a [String],
+    pub(crate) body: Option<&'a str>,
+    pub(crate) connections: u32,
+    pub(crate) requests: u64, // Requests of each phase
+    pub(crate) depth: u32,    // Requests in flight at once on a connection
+    pub(crate) timeout: Duration,
+    pub(crate) precision: usize,
+}
+
+/// Struct to hold what a connection measured during a phase
+#[derive(Default)]
+struct ConnectionResult {
+    latencies: Vec<u64>, // From writing the batch to reading the response, in milliseconds
+    failed: u64,         // Requests answered with a non-2xx status or not answered at all
+    unanswered: u64,     // Requests whose connection closed or failed before their response
+    active: Duration,    // Time the connection was sending requests
+}
+
+/// Struct to hold a phase of the benchmark, sequential or pipelined
+struct PhaseResult {
+    elapsed: Duration,
+    connections: Vec<ConnectionResult>,
+}
+
+impl PhaseResult {
+    fn answered(&self) -> usize {
+        self.connections.iter().map(|c| c.latencies.len()).sum()
+    }
+}
+
+/// Function to run the sequential and the pipelined phase and print the
+/// report
+pub(crate) fn run(pipeline: &Pipeline) -> Result<(), String> {
+    let (url, address) = resolve(pipeline.url, "--pipeline")?;
+    let request = build_request(pipeline, &url);
+
+    println!(
+        "Pipelining {} requests over {} connections, {} at a time, after as many one at a time",
+        pipeline.requests, pipeline.connections, pipeline.depth
+    );
+    let connect = || TcpStream::connect_timeout(&address, pipeline.timeout);
+    let sequential = run_phase(pipeline, &request, 1, &connect);
+    let pipelined = run_phase(pipeline, &request, pipeline.depth, &connect);
+    display_report(pipeline, &sequential, &pipelined);
+    Ok(())
+}
+
+/// Function to parse an http:// URL for a mode that speaks HTTP/1.1 itself,
+/// named by `option` in errors, and resolve the address of its host
+pub(crate) fn resolve(url: &str, option: &str) -> Result<(Url, SocketAddr), String> {
+    let parsed = Url::parse(url).map_err(|e| format!("invalid URL {}: {}", url, e))?;
+    if parsed.scheme() != "http" {
+        return Err(format!(
+            "{} only supports http:// URLs, got {}",
+            option, url
+        ));
+    }
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| format!("{} needs a URL with a host", option))?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    let address = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", host))?;
+    Ok((parsed, address))
+}
+
+/// Function to render the request every connection sends
+fn build_request(pipeline: &Pipeline, url: &Url) -> Vec<u8> {
+    let mut request = request_head(pipeline.method, url, pipeline.headers);
+    if let Some(body) = pipeline.body {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(pipeline.body.unwrap_or_default());
+    request.into_bytes()
+}
+
+/// Function to render the request line and headers of a request, each ending
+/// in CRLF, without the blank line that ends the head
+pub(crate) fn request_head(method: &str, url: &Url, headers: &[String]) -> String {
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target = format!("{}?{}", target, query);
+    }
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: loadster 1.0.0\r\n",
+        method, target, host
+    );
+    for header in headers {
+        request.push_str(header.trim());
+        request.push_str("\r\n");
+    }
+    request
+}
+
+/// Function to send the requests of a phase over every connection at once,
+/// `depth` at a time on each
+fn run_phase(
+    pipeline: &Pipeline,
+    request: &[u8],
+    depth: u32,
+    connect: &(impl Fn() -> io::Result<TcpStream> + Sync),
+) -> PhaseResult {
+    let next = AtomicU64::new(0);
+    let start = Instant::now();
+    let connections = thread::scope(|scope| {
+        let handles: Vec<_> = (0..pipeline.connections)
+            .map(|i| {
+                thread::Builder::new()
+                    .name(format!("loadster-pipeline-{}", i))
+                    .spawn_scoped(scope, || {
+                        run_connection(pipeline, request, depth, &next, connect)
+                    })
+                    .expect("the system can start another thread")
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    PhaseResult {
+        elapsed: start.elapsed(),
+        connections,
+    }
+}
+
+/// Function to claim up to `depth` of the remaining requests of a phase
+fn claim(next: &AtomicU64, total: u64, depth: u32) -> u64 {
+    let claimed = next.fetch_add(depth as u64, Ordering::SeqCst);
+    total.saturating_sub(claimed).min(depth as u64)
+}
+
+/// Function to send batches of requests over one connection until the phase
+/// has sent all of its requests, reconnecting when the server closes it
+fn run_connection(
+    pipeline: &Pipeline,
+    request: &[u8],
+    depth: u32,
+    next: &AtomicU64,
+    connect: &impl Fn() -> io::Result<TcpStream>,
+) -> ConnectionResult {
+    let head = pipeline.method.eq_ignore_ascii_case("HEAD");
+    let mut result = ConnectionResult::default();
+    let mut connection: Option<(TcpStream, BufReader<TcpStream>)> = None;
+    let start = Instant::now();
+    loop {
+        let batch = claim(next, pipeline.requests, depth);
+        if batch == 0 {
+            break;
+        }
+        if connection.is_none() {
+            connection = open(connect, pipeline.timeout).ok();
+        }
+        let Some((writer, reader)) = &mut connection else {
+            result.failed += batch;
+            result.unanswered += batch;
+            continue;
+        };
+        // The whole batch goes out in one write, before any response is read
+        let sent = Instant::now();
+        if writer.write_all(&request.repeat(batch as usize)).is_err() {
+            result.failed += batch;
+            result.unanswered += batch;
+            connection = None;
+            continue;
+        }
+        for answered in 0..batch {
+            match read_response(reader, head) {
+                Ok((status, keep_alive)) => {
+                    result.latencies.push(sent.elapsed().as_millis() as u64);
+                    if !(200..300).contains(&status) {
+                        result.failed += 1;
+                    }
+                    if !keep_alive {
+                        let lost = batch - answered - 1;
+                        result.failed += lost;
+                        result.unanswered += lost;
+                        connection = None;
+                        break;
+                    }
+                }
+                Err(_) => {
+                    let lost = batch - answered;
+                    result.failed += lost;
+                    result.unanswered += lost;
+                    connection = None;
+                    break;
+                }
+            }
+        }
+    }
+    result.active = start.elapsed();
+    result
+}
+
+/// Function to open a connection, returning its write and read halves
+pub(crate) fn open(
+    connect: &impl Fn() -> io::Result<TcpStream>,
+    timeout: Duration,
+) -> io::Result<(TcpStream, BufReader<TcpStream>)> {
+    let stream = connect()?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let reader = BufReader::new(stream.try_clone()?);
+    Ok((stream, reader))
+}
+
+/// Struct to hold what the head of a response says about it
+pub(crate) struct ResponseHead {
+    pub(crate) status: u16,
+    content_length: Option<u64>,
+    chunked: bool,
+    keep_alive: bool,
+}
+
+/// Function to read the next response, skipping interim 1xx responses, and
+/// return its status and whether the connection stays open after it
+pub(crate) fn read_response(
+    reader: &mut BufReader<TcpStream>,
+    head: bool,
+) -> io::Result<(u16, bool)> {
+    loop {
+        let response = read_head(reader)?;
+        if !(100..200).contains(&response.status) {
+            let keep_alive = read_body(reader, &response, head)?;
+            return Ok((response.status, keep_alive));
+        }
+    }
+}
+
+/// Function to read the status line and headers of the next response, interim
+/// or final
+pub(crate) fn read_head(reader: &mut BufReader<TcpStream>) -> io::Result<ResponseHead> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))?;
+
+    let mut response = ResponseHead {
+        status,
+        content_length: None,
+        chunked: false,
+        keep_alive: !line.starts_with("HTTP/1.0"),
+    };
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(response);
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            response.content_length = value.parse::<u64>().ok();
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            response.chunked = value.to_ascii_lowercase().contains("chunked");
+        } else if name.eq_ignore_ascii_case("connection") {
+            response.keep_alive = !value.eq_ignore_ascii_case("close");
+        }
+    }
+}
+
+/// Function to read and discard the body of a final response whose head was
+/// read, and return whether the connection stays open after it
+pub(crate) fn read_body(
+    reader: &mut BufReader<TcpStream>,
+    response: &ResponseHead,
+    head: bool,
+) -> io::Result<bool> {
+    if head || response.status == 204 || response.status == 304 {
+        return Ok(response.keep_alive);
+    }
+    if response.chunked {
+        read_chunked(reader)?;
+    } else if let Some(length) = response.content_length {
+        skip(reader, length)?;
+    } else {
+        // The body runs until the server closes the connection
+        io::copy(reader, &mut io::sink())?;
+        return Ok(false);
+    }
+    Ok(response.keep_alive)
+}
+
+/// Function to read and discard a chunked body, trailers included
+fn read_chunked(reader: &mut BufReader<TcpStream>) -> io::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        skip(reader, size + 2)?;
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Function to read and discard `length` bytes
+fn skip(reader: &mut BufReader<TcpStream>, length: u64) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(length), &mut io::sink())?;
+    if copied < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Function to display both phases side by side, with the gain of pipelining
+/// overall and per connection
+fn display_report(pipeline: &Pipeline, sequential: &PhaseResult, pipelined: &PhaseResult) {
+    let precision = pipeline.precision;
+    println!(
+        "\nPipelining Report (HTTP/1.1, depth {}, {} connections)",
+        pipeline.depth, pipeline.connections
+    );
+    println!("Target URL\t{}", pipeline.url);
+    println!(
+        "Phase\tRequests\tFailed\tUnanswered\tDuration\tThroughput\tAvg Latency\tP50\tP95\tP99"
+    );
+    for (name, phase) in [("Sequential", sequential), ("Pipelined", pipelined)] {
+        let mut times: Vec<u64> = phase
+            .connections
+            .iter()
+            .flat_map(|c| c.latencies.iter().copied())
+            .collect();
+        times.sort_unstable();
+        println!(
+            "{}\t{}\t{}\t{}\t{:.*} s\t{:.*} req/s\t{:.*} ms\t{} ms\t{} ms\t{} ms",
+            name,
+            pipeline.requests,
+            phase.connections.iter().map(|c| c.failed).sum::<u64>(),
+            phase.connections.iter().map(|c| c.unanswered).sum::<u64>(),
+            precision,
+            phase.elapsed.as_secs_f64(),
+            precision,
+            throughput(phase.answered(), phase.elapsed),
+            precision,
+            times.iter().sum::<u64>() as f64 / times.len().max(1) as f64,
+            percentile(&times, 0.50),
+            percentile(&times, 0.95),
+            percentile(&times, 0.99)
+        );
+    }
+    let gain = |sequential: f64, pipelined: f64| {
+        if sequential > 0.0 {
+            pipelined / sequential
+        } else {
+            0.0
+        }
+    };
+    let overall = gain(
+        throughput(sequential.answered(), sequential.elapsed),
+        throughput(pipelined.answered(), pipelined.elapsed),
+    );
+    println!("Throughput Gain\t{:.*}x", precision, overall);
+
+    // A connection that gains `depth` times its sequential rate is fully
+    // efficient: the server answers a batch as fast as a single request
+    println!("\nPer-Connection Pipeline Efficiency");
+    println!("Connection\tSequential\tPipelined\tGain\tEfficiency");
+    for (i, (sequential, pipelined)) in sequential
+        .connections
+        .iter()
+        .zip(&pipelined.connections)
+        .enumerate()
+    {
+        let sequential = throughput(sequential.latencies.len(), sequential.active);
+        let pipelined = throughput(pipelined.latencies.len(), pipelined.active);
+        let gain = gain(sequential, pipelined);
+        println!(
+            "{}\t{:.*} req/s\t{:.*} req/s\t{:.*}x\t{:.*}%",
+            i,
+            precision,
+            sequential,
+            precision,
+            pipelined,
+            precision,
+            gain,
+            precision,
+            gain / pipeline.depth as f64 * 100.0
+        );
+    }
+    let unanswered: u64 = pipelined.connections.iter().map(|c| c.unanswered).sum();
+    if unanswered > 0 {
+        println!(
+            "{} pipelined requests were not answered before their connection closed; the server may not support pipelining",
+            unanswered
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Resource use of the loadster process itself (`--self-metrics`).
+//!
+//! The figures come from `/proc/self/status`, so they are only known on
+//! Linux; elsewhere they are reported as unavailable.
+
+/// Struct to hold a snapshot of the resource use of this process
+pub(crate) struct ProcessMetrics {
+    pub(crate) rss_kib: Option<u64>,      // Resident memory, in KiB
+    pub(crate) peak_rss_kib: Option<u64>, // Highest resident memory since the process started, in KiB
+    pub(crate) threads: Option<u64>,
+}
+
+impl ProcessMetrics {
+    /// Read the current resource use of this process
+    pub(crate) fn read() -> Self {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.split_whitespace().next()?.parse().ok())
+        };
+        ProcessMetrics {
+            rss_kib: field("VmRSS"),
+            peak_rss_kib: field("VmHWM"),
+            threads: field("Threads"),
+        }
+    }
+}
+
+/// Function to format a size in KiB as MiB, or n/a when unknown
+pub(crate) fn format_mib(kib: Option<u64>, precision: usize) -> String {
+    match kib {
+        Some(kib) => format!("{:.*} MiB", precision, kib as f64 / 1024.0),
+        None => "n/a".to_string(),
+    }
+}
+
+/// Function to format a count, or n/a when unknown
+pub(crate) fn format_count(count: Option<u64>) -> String {
+    count.map_or_else(|| "n/a".to_string(), |count| count.to_string())
+}
@@ -0,0 +1,141 @@
+//! Named test profiles (`--save-profile`, `--profile` and the `profile`
+//! subcommand).
+//!
+//! Every profile is a table of the profiles file, holding the options of a
+//! run that differ from their defaults under the same keys as a config file.
+//! The file is `$LOADSTER_PROFILES` when set, or `loadster/profiles.toml` in
+//! `$XDG_CONFIG_HOME` (by default `~/.config`).
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use serde_json::{Map, Value};
+
+/// The options of every profile, by name
+type Profiles = BTreeMap<String, Map<String, Value>>;
+
+/// Command line arguments of the `profile` subcommand
+#[derive(Args, Clone)]
+pub(crate) struct ProfileArgs {
+    #[command(subcommand)]
+    command: ProfileCommand,
+}
+
+/// Ways of managing the saved profiles
+#[derive(Subcommand, Clone)]
+enum ProfileCommand {
+    /// List the saved profiles and their options
+    List,
+    /// Delete a saved profile
+    Delete {
+        /// The name of the profile to delete
+        #[clap(help = "The name of the profile to delete")]
+        name: String,
+    },
+}
+
+/// Function to find the profiles file
+fn profiles_path() -> Result<PathBuf, String> {
+    if let Ok(path) = std::env::var("LOADSTER_PROFILES") {
+        return Ok(PathBuf::from(path));
+    }
+    let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var("HOME")
+                .map_err(|_| "cannot find the profiles file, set HOME or LOADSTER_PROFILES")?;
+            PathBuf::from(home).join(".config")
+        }
+    };
+    Ok(config_dir.join("loadster").join("profiles.toml"))
+}
+
+/// Function to read every saved profile; a missing profiles file has none
+fn load_profiles() -> Result<(PathBuf, Profiles), String> {
+    let path = profiles_path()?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((path, Profiles::new())),
+        Err(e) => {
+            return Err(format!(
+                "cannot read profiles file {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    let profiles = toml::from_str(&contents)
+        .map_err(|e| format!("invalid profiles file {}: {}", path.display(), e))?;
+    Ok((path, profiles))
+}
+
+/// Function to write every profile back to the profiles file
+fn store_profiles(path: &PathBuf, profiles: &Profiles) -> Result<(), String> {
+    let write_error = |e: &dyn std::fmt::Display| {
+        format!("cannot write to profiles file {}: {}", path.display(), e)
+    };
+    let contents = toml::to_string(profiles).map_err(|e| write_error(&e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| write_error(&e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| write_error(&e))
+}
+
+/// Function to read the options of a saved profile
+pub(crate) fn load_profile(name: &str) -> Result<Map<String, Value>, String> {
+    let (path, mut profiles) = load_profiles()?;
+    profiles.remove(name).ok_or_else(|| {
+        format!(
+            "no profile named {} in {} (see `overloader profile list`)",
+            name,
+            path.display()
+        )
+    })
+}
+
+/// Function to save options under a profile name, replacing a profile of the
+/// same name. Returns the path of the profiles file.
+pub(crate) fn save_profile(name: &str, options: Map<String, Value>) -> Result<PathBuf, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid profile name {:?}, use letters, digits, - and _",
+            name
+        ));
+    }
+    let (path, mut profiles) = load_profiles()?;
+    profiles.insert(name.to_string(), options);
+    store_profiles(&path, &profiles)?;
+    Ok(path)
+}
+
+/// Function to carry out the `profile` subcommand
+pub(crate) fn run(args: &ProfileArgs) -> Result<(), String> {
+    let (path, mut profiles) = load_profiles()?;
+    match &args.command {
+        ProfileCommand::List => {
+            if profiles.is_empty() {
+                println!("No saved profiles in {}", path.display());
+            }
+            for (name, options) in &profiles {
+                let options: Vec<String> = options
+                    .iter()
+                    .map(|(key, value)| format!("{} = {}", key, value))
+                    .collect();
+                println!("{}\t{}", name, options.join(", "));
+            }
+        }
+        ProfileCommand::Delete { name } => {
+            if profiles.remove(name).is_none() {
+                return Err(format!("no profile named {} in {}", name, path.display()));
+            }
+            store_profiles(&path, &profiles)?;
+            println!("Deleted profile {}", name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,444 @@
+//! Replaying the requests of an access log (`--replay-log`).
+//!
+//! Every line of the log, or every entry of a HAR file, becomes a
+//! `RequestSpec` for the logged method and path, sent to the `--url` base,
+//! with the headers and body that were logged along with it. The time of
+//! every request relative to the first one is kept, so `--preserve-timing`
+//! can send them with the same inter-arrival times as in production.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::targets::RequestSpec;
+
+/// Supported formats of access logs
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    Combined, // The NCSA combined log format of Apache and nginx
+    Json,     // One JSON object per line with method, path and timestamp
+    Har,      // A HAR file, as saved by the developer tools of browsers
+}
+
+/// Headers of a capture that aren't replayed: those the client sets for the
+/// connection and body it sends, and the pseudo-headers of HTTP/2
+const CONNECTION_HEADERS: [&str; 7] = [
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+];
+
+/// Struct to hold a request as it was logged
+struct LoggedRequest {
+    time: f64, // Seconds since UNIX_EPOCH
+    method: Method,
+    target: String, // The path and query string
+    headers: Vec<String>,
+    body: Option<String>,
+}
+
+/// Struct to hold one logged request
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayEntry {
+    pub(crate) offset: Duration, // Time of the request since the first request of the log
+    pub(crate) request: RequestSpec,
+}
+
+/// Struct to hold the requests of a log and how many lines could not be
+/// parsed
+pub(crate) struct ReplayLog {
+    pub(crate) entries: Vec<ReplayEntry>,
+    pub(crate) skipped: usize,
+}
+
+/// Function to parse an access log into requests to `base_url`, in the order
+/// they were logged. Lines, or HAR entries, that can't be parsed are skipped
+/// and counted.
+pub(crate) fn load_replay_log(
+    path: &str,
+    format: LogFormat,
+    base_url: &str,
+) -> Result<ReplayLog, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read replay log {}: {}", path, e))?;
+    let base_url = base_url.trim_end_matches('/');
+
+    let logged: Vec<Option<LoggedRequest>> = match format {
+        LogFormat::Har => {
+            let har: Value = serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid HAR file {}: {}", path, e))?;
+            har.pointer("/log/entries")
+                .and_then(Value::as_array)
+                .ok_or_else(|| format!("HAR file {} has no log.entries list", path))?
+                .iter()
+                .map(parse_har_entry)
+                .collect()
+        }
+        LogFormat::Combined | LogFormat::Json => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match format {
+                LogFormat::Combined => parse_combined(line),
+                _ => parse_json(line),
+            })
+            .collect(),
+    };
+    let mut parsed = vec![];
+    let mut skipped = 0;
+    for entry in logged {
+        match entry {
+            Some(entry) => parsed.push(entry),
+            None => skipped += 1,
+        }
+    }
+    if parsed.is_empty() {
+        return Err(format!(
+            "replay log {} has no requests ({} malformed {})",
+            path,
+            skipped,
+            if format == LogFormat::Har {
+                "entries"
+            } else {
+                "lines"
+            }
+        ));
+    }
+
+    // Logs are written when requests finish, so they are not quite in order
+    // of arrival
+    parsed.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let first = parsed[0].time;
+    let entries = parsed
+        .into_iter()
+        .map(|logged| ReplayEntry {
+            offset: Duration::from_secs_f64(logged.time - first),
+            request: RequestSpec {
+                method: logged.method,
+                url: format!("{}{}", base_url, logged.target),
+                body: logged.body,
+                headers: logged.headers,
+                weight: 1.0,
+            },
+        })
+        .collect();
+    Ok(ReplayLog { entries, skipped })
+}
+
+/// Function to parse a combined log line, e.g.
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326 "-" "curl"`
+fn parse_combined(line: &str) -> Option<LoggedRequest> {
+    let (_, rest) = line.split_once('[')?;
+    let (time, rest) = rest.split_once(']')?;
+    let (_, rest) = rest.split_once('"')?;
+    let (request, _) = rest.split_once('"')?;
+    let mut parts = request.split_whitespace();
+    let method = Method::from_bytes(parts.next()?.as_bytes()).ok()?;
+    let target = parts.next()?;
+    if !target.starts_with('/') {
+        return None;
+    }
+    Some(LoggedRequest {
+        time: parse_clf_time(time)?,
+        method,
+        target: target.to_string(),
+        headers: vec![],
+        body: None,
+    })
+}
+
+/// Function to parse a JSON log line with `method`, `path` (or `uri` or
+/// `url`) and `timestamp` (or `time`) fields, and optional `headers` (an
+/// object of names to values) and `body`. Timestamps are RFC 3339 strings or
+/// seconds since UNIX_EPOCH.
+fn parse_json(line: &str) -> Option<LoggedRequest> {
+    let entry: Value = serde_json::from_str(line).ok()?;
+    let field = |names: &[&str]| names.iter().find_map(|name| entry.get(*name));
+    let method = field(&["method", "verb"])?.as_str()?;
+    let target = request_target(field(&["path", "uri", "url"])?.as_str()?)?;
+    let time = match field(&["timestamp", "time", "ts", "@timestamp"])? {
+        Value::Number(seconds) => seconds.as_f64()?,
+        Value::String(time) => parse_rfc3339(time)?,
+        _ => return None,
+    };
+    let headers = match entry.get("headers") {
+        Some(Value::Object(headers)) => headers
+            .iter()
+            .map(|(name, value)| Some((name.as_str(), value.as_str()?)))
+            .collect::<Option<Vec<_>>>()?,
+        Some(_) => return None,
+        None => vec![],
+    };
+    Some(LoggedRequest {
+        time,
+        method: Method::from_bytes(method.to_uppercase().as_bytes()).ok()?,
+        target,
+        headers: replayed_headers(headers),
+        body: entry
+            .get("body")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+/// Function to parse an entry of a HAR file: the `method`, `url`, `headers`
+/// and `postData` text of its `request`, sent at its `startedDateTime`
+fn parse_har_entry(entry: &Value) -> Option<LoggedRequest> {
+    let request = entry.get("request")?;
+    let method = request.get("method")?.as_str()?;
+    let headers = request
+        .get("headers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|header| {
+            Some((
+                header.get("name")?.as_str()?,
+                header.get("value")?.as_str()?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(LoggedRequest {
+        time: parse_rfc3339(entry.get("startedDateTime")?.as_str()?)?,
+        method: Method::from_bytes(method.to_uppercase().as_bytes()).ok()?,
+        target: request_target(request.get("url")?.as_str()?)?,
+        headers: replayed_headers(headers),
+        body: request
+            .pointer("/postData/text")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+/// Function to take the path and query string of a logged URL, which is
+/// replayed against the base URL even when it is a full URL
+fn request_target(url: &str) -> Option<String> {
+    let target = match url.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/').unwrap_or(rest.len())..],
+        None => url,
+    };
+    let target = if target.is_empty() { "/" } else { target };
+    target.starts_with('/').then(|| target.to_string())
+}
+
+/// Function to turn logged headers into `Name: value` lines, leaving out
+/// those of CONNECTION_HEADERS and the HTTP/2 pseudo-headers
+fn replayed_headers(headers: Vec<(&str, &str)>) -> Vec<String> {
+    headers
+        .into_iter()
+        .filter(|(name, _)| {
+            !name.starts_with(':')
+                && !CONNECTION_HEADERS
+                    .iter()
+                    .any(|skipped| name.eq_ignore_ascii_case(skipped))
+        })
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect()
+}
+
+/// Function to parse a common log format time, `10/Oct/2000:13:55:36 -0700`,
+/// into seconds since UNIX_EPOCH
+fn parse_clf_time(time: &str) -> Option<f64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (date_time, zone) = time.split_once(' ')?;
+    let mut parts = date_time.splitn(4, [':', '/']);
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = parts.next()?.parse().ok()?;
+    let clock = parse_clock(parts.next()?)?;
+    let zone = parse_zone(zone)?;
+    Some(days_from_civil(year, month, day) as f64 * 86400.0 + clock - zone)
+}
+
+/// Function to parse an RFC 3339 time, `2000-10-10T13:55:36.123Z` or with an
+/// offset such as `+02:00`, into seconds since UNIX_EPOCH
+fn parse_rfc3339(time: &str) -> Option<f64> {
+    let (date, rest) = time.split_once(['T', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let (clock, zone) = match rest.find(['Z', 'z', '+', '-']) {
+        Some(index) => rest.split_at(index),
+        None => (rest, "Z"),
+    };
+    let zone = if zone.eq_ignore_ascii_case("z") {
+        0.0
+    } else {
+        parse_zone(&zone.replace(':', ""))?
+    };
+    Some(days_from_civil(year, month, day) as f64 * 86400.0 + parse_clock(clock)? - zone)
+}
+
+/// Function to parse `HH:MM:SS` with optional fractional seconds into seconds
+/// since midnight
+fn parse_clock(clock: &str) -> Option<f64> {
+    let mut parts = clock.splitn(3, ':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Function to parse a `+HHMM` or `-HHMM` time zone offset into seconds
+fn parse_zone(zone: &str) -> Option<f64> {
+    let sign = match zone.as_bytes().first()? {
+        b'+' => 1.0,
+        b'-' => -1.0,
+        _ => return None,
+    };
+    let digits = zone.get(1..5)?;
+    let hours: f64 = digits[..2].parse().ok()?;
+    let minutes: f64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600.0 + minutes * 60.0))
+}
+
+/// Function to count the days from UNIX_EPOCH to a civil date (Howard
+/// Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A HAR file of two requests saved out of order, and one entry without
+    /// a request
+    const HAR: &str = r#"{"log": {"entries": [
+        {"startedDateTime": "2024-05-01T10:00:01.500Z", "request": {
+            "method": "POST", "url": "https://shop.example.com/cart?item=7",
+            "headers": [
+                {"name": ":authority", "value": "shop.example.com"},
+                {"name": "Host", "value": "shop.example.com"},
+                {"name": "Content-Type", "value": "application/json"},
+                {"name": "Cookie", "value": "session=abc"},
+                {"name": "Content-Length", "value": "12"}
+            ],
+            "postData": {"mimeType": "application/json", "text": "{\"qty\": 2}"}}},
+        {"startedDateTime": "2024-05-01T12:00:00+02:00", "request": {
+            "method": "get", "url": "https://shop.example.com/",
+            "headers": [{"name": "Authorization", "value": "Bearer t0ken"}]}},
+        {"startedDateTime": "2024-05-01T10:00:02Z"}
+    ]}}"#;
+
+    /// Function to load a replay log written to a file of its own
+    fn load(name: &str, contents: &str, format: LogFormat) -> Result<ReplayLog, String> {
+        let path = std::env::temp_dir().join(format!("loadster-test-{}", name));
+        std::fs::write(&path, contents).unwrap();
+        let log = load_replay_log(path.to_str().unwrap(), format, "http://localhost:8080/");
+        let _ = std::fs::remove_file(&path);
+        log
+    }
+
+    #[test]
+    fn har_entries_become_requests_in_the_order_they_started() {
+        let log = load("replay.har", HAR, LogFormat::Har).unwrap();
+        assert_eq!(log.skipped, 1);
+        let [first, second] = &log.entries[..] else {
+            panic!("{} entries", log.entries.len());
+        };
+        assert_eq!(first.offset, Duration::ZERO);
+        assert_eq!(first.request.method, Method::GET);
+        assert_eq!(first.request.url, "http://localhost:8080/");
+        assert_eq!(first.request.headers, ["Authorization: Bearer t0ken"]);
+        assert_eq!(second.offset, Duration::from_millis(1500));
+        assert_eq!(second.request.method, Method::POST);
+        assert_eq!(second.request.url, "http://localhost:8080/cart?item=7");
+        assert_eq!(second.request.body.as_deref(), Some("{\"qty\": 2}"));
+    }
+
+    #[test]
+    fn har_headers_leave_out_connection_and_pseudo_headers() {
+        let log = load("replay-headers.har", HAR, LogFormat::Har).unwrap();
+        assert_eq!(
+            log.entries[1].request.headers,
+            ["Content-Type: application/json", "Cookie: session=abc"]
+        );
+    }
+
+    #[test]
+    fn a_har_file_without_entries_is_rejected() {
+        let e = load("replay-empty.har", r#"{"log": {}}"#, LogFormat::Har)
+            .err()
+            .unwrap();
+        assert!(e.ends_with("has no log.entries list"), "{}", e);
+    }
+
+    #[test]
+    fn combined_lines_are_parsed_and_malformed_ones_counted() {
+        let log = load(
+            "replay-combined.log",
+            concat!(
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /a.gif HTTP/1.0\" 200 2326 \"-\" \"curl\"\n",
+                "not a log line\n",
+                "127.0.0.1 - - [10/Oct/2000:13:55:37 -0700] \"DELETE /items/1 HTTP/1.1\" 204 0 \"-\" \"curl\"\n",
+            ),
+            LogFormat::Combined,
+        )
+        .unwrap();
+        assert_eq!(log.skipped, 1);
+        assert_eq!(log.entries.len(), 2);
+        assert_eq!(log.entries[0].request.url, "http://localhost:8080/a.gif");
+        assert_eq!(log.entries[1].request.method, Method::DELETE);
+        assert_eq!(log.entries[1].offset, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn json_lines_carry_their_headers_and_body() {
+        let log = load(
+            "replay.jsonl",
+            "{\"method\": \"put\", \"uri\": \"/items/1\", \"ts\": 100.25, \"headers\": {\"X-Api-Key\": \"k\", \"Connection\": \"close\"}, \"body\": \"{}\"}\n",
+            LogFormat::Json,
+        )
+        .unwrap();
+        let request = &log.entries[0].request;
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(request.headers, ["X-Api-Key: k"]);
+        assert_eq!(request.body.as_deref(), Some("{}"));
+    }
+
+    #[test]
+    fn log_times_are_read_in_every_time_zone() {
+        // 2000-10-10T20:55:36Z
+        assert_eq!(
+            parse_clf_time("10/Oct/2000:13:55:36 -0700"),
+            Some(971211336.0)
+        );
+        assert_eq!(parse_rfc3339("2000-10-10T20:55:36Z"), Some(971211336.0));
+        assert_eq!(
+            parse_rfc3339("2000-10-10T22:55:36+02:00"),
+            Some(971211336.0)
+        );
+        assert_eq!(parse_rfc3339("2000-10-10T20:55:36.5Z"), Some(971211336.5));
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse_clf_time("10/Foo/2000:13:55:36 -0700"), None);
+    }
+
+    #[test]
+    fn logged_urls_keep_only_their_path_and_query() {
+        assert_eq!(
+            request_target("https://example.com/a?b=c").as_deref(),
+            Some("/a?b=c")
+        );
+        assert_eq!(request_target("https://example.com").as_deref(), Some("/"));
+        assert_eq!(request_target("/x").as_deref(), Some("/x"));
+        assert_eq!(request_target("x"), None);
+    }
+}
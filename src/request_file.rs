@@ -0,0 +1,109 @@
+//! A whole request described by one JSON file (`--request`).
+//!
+//! The file holds an object with the `url`, `method`, `headers` and `body` of
+//! the request, each optional, and whatever it gives replaces the matching
+//! flags. Headers are an object of names to values, as in a `fetch()` call
+//! copied from the browser's developer tools, or a list of `Name: value`
+//! strings or of `{"name": ..., "value": ...}` objects, as in a HAR entry. A
+//! body that isn't a string is sent as its JSON text, and a `null` body sends
+//! none.
+
+use clap::ValueEnum;
+use reqwest::header::HeaderName;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{Cli, HttpMethod};
+
+/// Struct to hold the request of a request file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequestFile {
+    url: Option<String>,
+    method: Option<String>,
+    headers: Option<Value>,
+    body: Option<Value>,
+}
+
+/// Function to read a request file and apply it to the arguments: the URL,
+/// method and body it gives replace those of the flags, and its headers
+/// replace all of `--headers`
+pub(crate) fn apply(args: &mut Cli, path: &str) -> Result<(), String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read request file {}: {}", path, e))?;
+    let invalid = |message: String| format!("invalid request file {}: {}", path, message);
+    let request: RequestFile =
+        serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    if let Some(url) = request.url {
+        reqwest::Url::parse(&url).map_err(|e| invalid(format!("url {}: {}", url, e)))?;
+        args.url = Some(url);
+    }
+    if let Some(method) = request.method {
+        args.method = HttpMethod::from_str(&method, true).map_err(|_| {
+            invalid(format!(
+                "unsupported method {} (supported: GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS)",
+                method
+            ))
+        })?;
+    }
+    if let Some(headers) = request.headers {
+        args.headers = header_lines(&headers).map_err(invalid)?;
+    }
+    args.body = match request.body {
+        None => args.body.take(),
+        Some(Value::Null) => None,
+        Some(Value::String(body)) => Some(body),
+        Some(body) => Some(body.to_string()),
+    };
+    Ok(())
+}
+
+/// Function to turn the headers of a request file into `Name: value` lines,
+/// as given with --headers
+fn header_lines(headers: &Value) -> Result<Vec<String>, String> {
+    let scalar = |value: &Value| match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    };
+    let lines = match headers {
+        Value::Object(map) => map
+            .iter()
+            .map(|(name, value)| {
+                scalar(value)
+                    .map(|value| format!("{}: {}", name, value))
+                    .ok_or_else(|| format!("the value of header {} is not a string", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Value::Array(entries) => entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| match entry {
+                Value::String(line) => Ok(line.clone()),
+                Value::Object(pair) => match (pair.get("name"), pair.get("value").and_then(scalar))
+                {
+                    (Some(Value::String(name)), Some(value)) => Ok(format!("{}: {}", name, value)),
+                    _ => Err(format!("header {} has no string name and value", i + 1)),
+                },
+                _ => Err(format!(
+                    "header {} is neither a \"Name: value\" string nor a name and value object",
+                    i + 1
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(
+                "headers must be an object of names to values or a list of headers".to_string(),
+            )
+        }
+    };
+    for line in &lines {
+        let valid = line
+            .split_once(':')
+            .is_some_and(|(name, _)| HeaderName::from_bytes(name.trim().as_bytes()).is_ok());
+        if !valid {
+            return Err(format!("header {:?} is not of the form Name: value", line));
+        }
+    }
+    Ok(lines)
+}
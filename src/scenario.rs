@@ -0,0 +1,212 @@
+//! Multi-step scenarios (`--scenario`).
+//!
+//! A scenario file lists the steps of a user flow, such as logging in and
+//! then calling authenticated endpoints. Every iteration of the run sends the
+//! steps in order from one worker, with the same template values, so a value
+//! drawn for the first step (e.g. `{uuid}`) is the same in the later ones.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+/// Struct to hold a scenario file as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    steps: Vec<StepFile>,
+}
+
+/// Struct to hold a step of a scenario file as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepFile {
+    name: Option<String>,
+    method: Option<String>,
+    url: String,
+    #[serde(default)]
+    headers: Vec<String>,
+    body: Option<String>,
+    think_time: Option<ThinkTime>,
+}
+
+/// The pause after a step, before the next step of the iteration, in
+/// milliseconds
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub(crate) enum ThinkTime {
+    Fixed(u64),                     // `think_time = 500`
+    Uniform { min: u64, max: u64 }, // `think_time = { min = 500, max = 2000 }`
+    Exponential { mean: u64 }, // `think_time = { mean = 1000 }`, as the gaps between independent arrivals
+}
+
+impl ThinkTime {
+    /// Draw the length of one pause
+    pub(crate) fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let ms = match *self {
+            ThinkTime::Fixed(ms) => ms as f64,
+            ThinkTime::Uniform { min, max } => rng.random_range(min..=max) as f64,
+            ThinkTime::Exponential { mean } => -(mean as f64) * (1.0 - rng.random::<f64>()).ln(),
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+}
+
+/// Struct to hold one step of a scenario
+#[derive(Debug, Clone)]
+pub(crate) struct Step {
+    pub(crate) name: String,
+    pub(crate) method: Method,
+    pub(crate) url: String,
+    pub(crate) headers: Vec<String>, // Sent after the headers given with --headers
+    pub(crate) body: Option<String>,
+    pub(crate) think_time: Option<ThinkTime>, // The pause before the next step
+}
+
+/// Struct to hold what happened to the steps of a scenario over a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ScenarioStats {
+    pub(crate) steps: Vec<String>,  // Names of the steps, in order
+    pub(crate) abandoned: Vec<u64>, // Number of times each step was skipped by --fail-fast
+}
+
+/// Function to load the steps of a TOML scenario file. URLs starting with `/`
+/// are relative to `base_url`.
+pub(crate) fn load_scenario(path: &str, base_url: Option<&str>) -> Result<Vec<Step>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read scenario file {}: {}", path, e))?;
+    let file: ScenarioFile =
+        toml::from_str(&contents).map_err(|e| format!("invalid scenario file {}: {}", path, e))?;
+    if file.steps.is_empty() {
+        return Err(format!("scenario file {} has no steps", path));
+    }
+
+    let mut steps = vec![];
+    for (i, step) in file.steps.into_iter().enumerate() {
+        let method = step.method.as_deref().unwrap_or("GET").to_uppercase();
+        let method = Method::from_str(&method).map_err(|_| {
+            format!(
+                "invalid method {} in step {} of scenario file {}",
+                method,
+                i + 1,
+                path
+            )
+        })?;
+        if let Some(ThinkTime::Uniform { min, max }) = step.think_time {
+            if min > max {
+                return Err(format!(
+                    "the think_time of step {} of scenario file {} has a min above its max",
+                    i + 1,
+                    path
+                ));
+            }
+        }
+        let url = if step.url.starts_with('/') {
+            let base_url = base_url.ok_or_else(|| {
+                format!(
+                    "step {} of scenario file {} has a relative URL, give the base URL with --url",
+                    i + 1,
+                    path
+                )
+            })?;
+            format!("{}{}", base_url.trim_end_matches('/'), step.url)
+        } else {
+            step.url
+        };
+        steps.push(Step {
+            name: step.name.unwrap_or_else(|| format!("{} {}", method, url)),
+            method,
+            url,
+            headers: step.headers,
+            body: step.body,
+            think_time: step.think_time,
+        });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// Function to load a scenario written to a file of its own
+    fn load(name: &str, contents: &str) -> Result<Vec<Step>, String> {
+        let path = std::env::temp_dir().join(format!("loadster-test-{}.toml", name));
+        std::fs::write(&path, contents).unwrap();
+        let steps = load_scenario(path.to_str().unwrap(), Some("http://localhost/"));
+        let _ = std::fs::remove_file(&path);
+        steps
+    }
+
+    #[test]
+    fn every_step_has_its_own_think_time() {
+        let steps = load(
+            "think-times",
+            r#"
+            [[steps]]
+            url = "/read"
+            think_time = 500
+
+            [[steps]]
+            url = "/click"
+            think_time = { min = 100, max = 200 }
+
+            [[steps]]
+            url = "/browse"
+            think_time = { mean = 1000 }
+
+            [[steps]]
+            url = "/leave"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(steps[0].think_time, Some(ThinkTime::Fixed(500))));
+        assert!(matches!(
+            steps[1].think_time,
+            Some(ThinkTime::Uniform { min: 100, max: 200 })
+        ));
+        assert!(matches!(
+            steps[2].think_time,
+            Some(ThinkTime::Exponential { mean: 1000 })
+        ));
+        assert!(steps[3].think_time.is_none());
+        assert_eq!(steps[0].url, "http://localhost/read");
+    }
+
+    #[test]
+    fn a_think_time_with_its_min_above_its_max_is_rejected() {
+        let e = load(
+            "inverted-think-time",
+            "[[steps]]\nurl = \"/\"\nthink_time = { min = 200, max = 100 }\n",
+        )
+        .unwrap_err();
+        assert!(e.contains("the think_time of step 1"), "{}", e);
+    }
+
+    #[test]
+    fn think_times_are_drawn_from_their_distribution() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            ThinkTime::Fixed(250).sample(&mut rng),
+            Duration::from_millis(250)
+        );
+        for _ in 0..100 {
+            let pause = ThinkTime::Uniform { min: 100, max: 200 }.sample(&mut rng);
+            assert!(pause >= Duration::from_millis(100) && pause <= Duration::from_millis(200));
+        }
+        let mean = (0..10_000)
+            .map(|_| {
+                ThinkTime::Exponential { mean: 100 }
+                    .sample(&mut rng)
+                    .as_secs_f64()
+            })
+            .sum::<f64>()
+            / 10_000.0;
+        assert!((mean - 0.1).abs() < 0.01, "mean of {} s", mean);
+    }
+}
@@ -0,0 +1,83 @@
+//! Validating response bodies against a JSON Schema (`--assert-schema`).
+//!
+//! Every successful response is parsed as JSON and checked against the
+//! schema. Violations are reported by the location in the body and the
+//! keyword that failed, with array indexes replaced by `*`, so the same
+//! mistake in every element of a list counts as one location.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Struct to hold a compiled JSON Schema
+pub(crate) struct ResponseSchema {
+    validator: jsonschema::Validator,
+}
+
+/// The outcome of checking one response body against the schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum SchemaCheck {
+    Valid,
+    Violations(Vec<String>), // Locations and keywords of the violations, e.g. `/items/*/id (type)`
+    NotJson,                 // The body is not JSON and not labelled as JSON either
+}
+
+impl ResponseSchema {
+    /// Load and compile the JSON Schema at `path`
+    pub(crate) fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read JSON schema {}: {}", path, e))?;
+        let schema: Value = serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid JSON schema {}: {}", path, e))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| format!("invalid JSON schema {}: {}", path, e))?;
+        Ok(ResponseSchema { validator })
+    }
+
+    /// Check a response body. A body that doesn't parse is a violation when
+    /// its content type says it is JSON, and skipped otherwise.
+    pub(crate) fn check(&self, body: &[u8], json_content_type: bool) -> SchemaCheck {
+        let instance: Value = match serde_json::from_slice(body) {
+            Ok(instance) => instance,
+            Err(_) if json_content_type => {
+                return SchemaCheck::Violations(vec!["/ (invalid JSON)".to_string()])
+            }
+            Err(_) => return SchemaCheck::NotJson,
+        };
+        let mut violations: Vec<String> = self
+            .validator
+            .iter_errors(&instance)
+            .map(|error| {
+                format!(
+                    "{} ({})",
+                    normalize_path(&error.instance_path().to_string()),
+                    error.kind().keyword()
+                )
+            })
+            .collect();
+        if violations.is_empty() {
+            return SchemaCheck::Valid;
+        }
+        violations.sort();
+        violations.dedup();
+        SchemaCheck::Violations(violations)
+    }
+}
+
+/// Function to replace the array indexes of a JSON pointer with `*`, and name
+/// the root `/`
+fn normalize_path(pointer: &str) -> String {
+    if pointer.is_empty() {
+        return "/".to_string();
+    }
+    pointer
+        .split('/')
+        .map(|segment| {
+            if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+                "*"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
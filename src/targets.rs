@@ -0,0 +1,279 @@
+//! Weighted request mixes discovered from an OpenAPI document (`--openapi`)
+//! or a sitemap (`--sitemap`).
+//!
+//! Every operation of a spec, or every page of a sitemap, becomes a
+//! `RequestSpec`. Each request of the run picks one of them at random, in
+//! proportion to their weights.
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use reqwest::Method;
+use serde_json::Value;
+
+/// The HTTP methods an OpenAPI path item can describe operations for
+const OPERATION_METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// The sitemaps of a sitemap index that are followed, at most
+const MAX_NESTED_SITEMAPS: usize = 50;
+
+/// Struct to hold one request of a mix
+#[derive(Debug, Clone)]
+pub(crate) struct RequestSpec {
+    pub(crate) method: Method,
+    pub(crate) url: String,
+    pub(crate) body: Option<String>,
+    pub(crate) headers: Vec<String>, // `Name: value` lines sent with this request only
+    pub(crate) weight: f64,
+}
+
+/// Function to pick a request of the mix, in proportion to the weights
+pub(crate) fn pick<'a>(specs: &'a [RequestSpec], rng: &mut StdRng) -> &'a RequestSpec {
+    let total: f64 = specs.iter().map(|spec| spec.weight).sum();
+    let mut point = rng.random_range(0.0..total);
+    for spec in specs {
+        if point < spec.weight {
+            return spec;
+        }
+        point -= spec.weight;
+    }
+    &specs[specs.len() - 1]
+}
+
+/// Function to derive the requests of an OpenAPI 3 or Swagger 2 document, in
+/// JSON or YAML. Path parameters are filled in from their examples, and the
+/// JSON example of a request body is sent as the body.
+///
+/// Operations are weighted by their `x-loadster-weight` extension, 1 when not
+/// given. `base_url` replaces the servers of the document.
+pub(crate) fn load_openapi(path: &str, base_url: Option<&str>) -> Result<Vec<RequestSpec>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read OpenAPI document {}: {}", path, e))?;
+    let spec: Value = if path.ends_with(".json") {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        serde_yaml_ng::from_str(&contents).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("invalid OpenAPI document {}: {}", path, e))?;
+
+    let base_url = match base_url {
+        Some(base_url) => base_url.to_string(),
+        None => server_url(&spec).ok_or_else(|| {
+            format!(
+                "OpenAPI document {} names no absolute server URL, give one with --url",
+                path
+            )
+        })?,
+    };
+    let base_url = base_url.trim_end_matches('/');
+
+    let mut specs = vec![];
+    let paths = spec.get("paths").and_then(Value::as_object);
+    for (route, item) in paths.into_iter().flatten() {
+        for method in OPERATION_METHODS {
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            // Parameters of the path item apply to all of its operations
+            let parameters: Vec<&Value> = item
+                .get("parameters")
+                .and_then(Value::as_array)
+                .into_iter()
+                .chain(operation.get("parameters").and_then(Value::as_array))
+                .flatten()
+                .map(|parameter| resolve(&spec, parameter))
+                .collect();
+            let mut url = format!("{}{}", base_url, route);
+            let mut query = vec![];
+            for parameter in parameters {
+                let name = parameter.get("name").and_then(Value::as_str).unwrap_or("");
+                match parameter.get("in").and_then(Value::as_str) {
+                    Some("path") => {
+                        url =
+                            url.replace(&format!("{{{}}}", name), &example_value(&spec, parameter))
+                    }
+                    Some("query")
+                        if parameter.get("required").and_then(Value::as_bool) == Some(true) =>
+                    {
+                        query.push(format!("{}={}", name, example_value(&spec, parameter)))
+                    }
+                    _ => {}
+                }
+            }
+            if !query.is_empty() {
+                url = format!("{}?{}", url, query.join("&"));
+            }
+            specs.push(RequestSpec {
+                method: Method::from_bytes(method.to_uppercase().as_bytes())
+                    .expect("operation methods are valid"),
+                url,
+                body: request_body_example(&spec, operation),
+                headers: vec![],
+                weight: operation
+                    .get("x-loadster-weight")
+                    .and_then(Value::as_f64)
+                    .unwrap_or(1.0),
+            });
+        }
+    }
+    specs.retain(|spec| spec.weight > 0.0);
+    if specs.is_empty() {
+        return Err(format!("OpenAPI document {} has no operations", path));
+    }
+    Ok(specs)
+}
+
+/// Function to find the absolute base URL of a document: the first server of
+/// OpenAPI 3, or the scheme, host and base path of Swagger 2
+fn server_url(spec: &Value) -> Option<String> {
+    if let Some(url) = spec.pointer("/servers/0/url").and_then(Value::as_str) {
+        return url.contains("://").then(|| url.to_string());
+    }
+    let host = spec.get("host").and_then(Value::as_str)?;
+    let scheme = spec
+        .pointer("/schemes/0")
+        .and_then(Value::as_str)
+        .unwrap_or("https");
+    let base_path = spec.get("basePath").and_then(Value::as_str).unwrap_or("");
+    Some(format!("{}://{}{}", scheme, host, base_path))
+}
+
+/// Function to follow a local `$ref` such as `#/components/parameters/Id`
+fn resolve<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    match value.get("$ref").and_then(Value::as_str) {
+        Some(reference) => reference
+            .strip_prefix('#')
+            .and_then(|pointer| spec.pointer(pointer))
+            .unwrap_or(value),
+        None => value,
+    }
+}
+
+/// Function to pick an example value for a parameter: its example, the first
+/// of its examples, or else the example, default or first enum value of its
+/// schema, falling back to a value of its type
+fn example_value(spec: &Value, parameter: &Value) -> String {
+    let schema = parameter.get("schema").map(|schema| resolve(spec, schema));
+    let example = parameter
+        .get("example")
+        .or_else(|| {
+            parameter
+                .get("examples")
+                .and_then(Value::as_object)
+                .and_then(|examples| examples.values().next())
+                .map(|example| resolve(spec, example))
+                .and_then(|example| example.get("value"))
+        })
+        .or_else(|| schema.and_then(|schema| schema.get("example")))
+        .or_else(|| schema.and_then(|schema| schema.get("default")))
+        .or_else(|| schema.and_then(|schema| schema.pointer("/enum/0")))
+        // Swagger 2 puts the type on the parameter itself
+        .or_else(|| parameter.get("default"));
+    match example {
+        Some(Value::String(example)) => example.clone(),
+        Some(example) => example.to_string(),
+        None => {
+            let kind = schema
+                .and_then(|schema| schema.get("type"))
+                .or_else(|| parameter.get("type"))
+                .and_then(Value::as_str);
+            match kind {
+                Some("integer") | Some("number") => "1".to_string(),
+                Some("boolean") => "true".to_string(),
+                _ => "example".to_string(),
+            }
+        }
+    }
+}
+
+/// Function to find the JSON example of an operation's request body
+fn request_body_example(spec: &Value, operation: &Value) -> Option<String> {
+    if let Some(request_body) = operation.get("requestBody") {
+        let media = resolve(spec, request_body).pointer("/content/application~1json")?;
+        let example = media
+            .get("example")
+            .or_else(|| {
+                media
+                    .get("examples")
+                    .and_then(Value::as_object)
+                    .and_then(|examples| examples.values().next())
+                    .map(|example| resolve(spec, example))
+                    .and_then(|example| example.get("value"))
+            })
+            .or_else(|| {
+                media
+                    .get("schema")
+                    .map(|schema| resolve(spec, schema))
+                    .and_then(|schema| schema.get("example"))
+            })?;
+        return Some(example.to_string());
+    }
+    // Swagger 2 describes the body as a parameter
+    operation
+        .get("parameters")
+        .and_then(Value::as_array)?
+        .iter()
+        .map(|parameter| resolve(spec, parameter))
+        .find(|parameter| parameter.get("in").and_then(Value::as_str) == Some("body"))
+        .and_then(|parameter| {
+            parameter
+                .get("schema")
+                .map(|schema| resolve(spec, schema))
+                .and_then(|schema| schema.get("example"))
+        })
+        .map(Value::to_string)
+}
+
+/// Function to fetch a sitemap and turn each of its pages into a GET request
+/// of weight 1. The sitemaps of a sitemap index are fetched in turn.
+pub(crate) fn load_sitemap(url: &str) -> Result<Vec<RequestSpec>, String> {
+    let client = reqwest::blocking::Client::new();
+    let fetch = |url: &str| -> Result<String, String> {
+        client
+            .get(url)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .and_then(|res| res.text())
+            .map_err(|e| format!("cannot fetch sitemap {}: {}", url, e))
+    };
+
+    let contents = fetch(url)?;
+    let mut pages = vec![];
+    if contents.contains("<sitemapindex") {
+        for sitemap in locations(&contents).into_iter().take(MAX_NESTED_SITEMAPS) {
+            pages.extend(locations(&fetch(&sitemap)?));
+        }
+    } else {
+        pages = locations(&contents);
+    }
+    if pages.is_empty() {
+        return Err(format!("sitemap {} lists no pages", url));
+    }
+    Ok(pages
+        .into_iter()
+        .map(|page| RequestSpec {
+            method: Method::GET,
+            url: page,
+            body: None,
+            headers: vec![],
+            weight: 1.0,
+        })
+        .collect())
+}
+
+/// Function to extract the `<loc>` entries of a sitemap
+fn locations(xml: &str) -> Vec<String> {
+    xml.split("<loc>")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("</loc>"))
+        .map(|(location, _)| unescape_xml(location.trim()))
+        .collect()
+}
+
+/// Function to decode the entities XML escapes in URLs
+fn unescape_xml(text: &str) -> String {
+    text.replace("&amp;", "&")
+        .replace("&apos;", "'")
+        .replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+}
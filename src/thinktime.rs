@@ -0,0 +1,81 @@
+//! Adaptive think time (`--adaptive-thinktime --target-p95`), an experimental
+//! feedback controller that keeps the P95 latency near a target.
+//!
+//! Every worker pauses for the current think time after each request. Once a
+//! second the controller takes the P95 latency of the responses of the second
+//! before: above the target it lengthens the think time by half, by at least
+//! `STEP`, so clients back off quickly; at or under the target it shortens it
+//! by a quarter, so load creeps back up. The think time it settles around is
+//! the pace at which the clients are sustainable. Seconds without responses
+//! leave it as it is.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{percentile, ResponseDetails};
+
+/// How often the controller adjusts the think time
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// The least the think time grows by when the P95 is above the target
+const STEP: Duration = Duration::from_millis(10);
+
+/// The longest think time
+const MAX_THINK_TIME: Duration = Duration::from_secs(10);
+
+/// Struct to hold one adjustment of the think time
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ThinkTimeStep {
+    pub(crate) second: u64,        // Seconds since the run started
+    pub(crate) responses: usize,   // Responses of the second before
+    pub(crate) p95: u64,           // Their P95 latency in milliseconds
+    pub(crate) think_time_ms: u64, // The think time from then on
+}
+
+/// Function to adjust `think_time` (in microseconds) toward `target_p95` (in
+/// milliseconds) from the responses recorded in `data`, until `finish` is
+/// dropped at the end of the run. Returns every adjustment.
+pub(crate) fn control(
+    think_time: &AtomicU64,
+    data: &Mutex<Vec<ResponseDetails>>,
+    target_p95: u64,
+    finish: mpsc::Receiver<()>,
+) -> Vec<ThinkTimeStep> {
+    let mut steps = vec![];
+    let mut seen = 0;
+    let mut second = 0;
+    while let Err(RecvTimeoutError::Timeout) = finish.recv_timeout(INTERVAL) {
+        second += 1;
+        let mut times: Vec<u64> = {
+            let data = data.lock().unwrap();
+            let new = data[seen..].iter().map(|d| d.time).collect();
+            seen = data.len();
+            new
+        };
+        if times.is_empty() {
+            continue;
+        }
+        times.sort_unstable();
+        let p95 = percentile(&times, 0.95);
+        let current = Duration::from_micros(think_time.load(Ordering::Relaxed));
+        let next = if p95 > target_p95 {
+            current.mul_f64(1.5).max(current + STEP).min(MAX_THINK_TIME)
+        } else if current < Duration::from_millis(1) {
+            Duration::ZERO
+        } else {
+            current.mul_f64(0.75)
+        };
+        think_time.store(next.as_micros() as u64, Ordering::Relaxed);
+        steps.push(ThinkTimeStep {
+            second,
+            responses: times.len(),
+            p95,
+            think_time_ms: next.as_millis() as u64,
+        });
+    }
+    steps
+}
@@ -0,0 +1,239 @@
+//! The live dashboard of a running test (`--tui`).
+//!
+//! It takes over the terminal while the run lasts and redraws a few times a
+//! second from the responses recorded so far: the progress of the run, the
+//! request rate, the error rate, a sparkline of the P95 latency of every
+//! redraw and the count of every response code. Pressing `q` or Esc stops
+//! sending requests; either way the terminal is restored when the run ends,
+//! before the final report is printed.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hdrhistogram::Histogram;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+
+use crate::{is_successful, percentile, ResponseDetails, SuccessCriteria};
+
+/// How often the dashboard redraws
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Struct to hold what the dashboard watches
+pub(crate) struct Dashboard<'a> {
+    pub(crate) url: &'a str,
+    pub(crate) data: &'a Mutex<Vec<ResponseDetails>>,
+    pub(crate) criteria: &'a SuccessCriteria,
+    pub(crate) request_errors: &'a AtomicU64,
+    pub(crate) stop: &'a AtomicBool,
+    pub(crate) total_requests: Option<u64>, // None for runs that end with --max-duration only
+    pub(crate) max_duration: Option<Duration>,
+}
+
+/// Struct to hold the figures shown, updated with the responses recorded since
+/// the last redraw
+struct Stats {
+    start: Instant,
+    seen: usize,
+    failed: u64,
+    codes: BTreeMap<u16, u64>,
+    latencies: Histogram<u64>, // Every latency so far, in milliseconds
+    p95s: Vec<u64>,            // P95 latency of the responses of each redraw
+    rate: f64,                 // Responses per second since the last redraw
+}
+
+impl Stats {
+    fn update(&mut self, dashboard: &Dashboard, elapsed: Duration) {
+        let data = dashboard.data.lock().unwrap();
+        let new = &data[self.seen..];
+        let mut times: Vec<u64> = new.iter().map(|d| d.time).collect();
+        for detail in new {
+            let _ = self.latencies.record(detail.time.max(1));
+            *self.codes.entry(detail.status.as_u16()).or_default() += 1;
+            if !is_successful(detail, dashboard.criteria) {
+                self.failed += 1;
+            }
+        }
+        self.seen = data.len();
+        drop(data);
+        times.sort_unstable();
+        self.p95s.push(percentile(&times, 0.95));
+        self.rate = times.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    }
+}
+
+/// Function to show the dashboard until `finish` is dropped at the end of the
+/// run. `q` or Esc stops the run.
+pub(crate) fn run(dashboard: &Dashboard, finish: mpsc::Receiver<()>) {
+    let mut stats = Stats {
+        start: Instant::now(),
+        seen: 0,
+        failed: 0,
+        codes: BTreeMap::new(),
+        latencies: Histogram::new(3).expect("3 significant figures are valid"),
+        p95s: vec![],
+        rate: 0.0,
+    };
+    let mut terminal = ratatui::init();
+    let mut last = Instant::now();
+    while let Err(TryRecvError::Empty) = finish.try_recv() {
+        if last.elapsed() >= REFRESH {
+            stats.update(dashboard, last.elapsed());
+            last = Instant::now();
+        }
+        if terminal
+            .draw(|frame| draw(frame, dashboard, &stats))
+            .is_err()
+        {
+            break;
+        }
+        // Waiting for input paces the loop; a resize just redraws
+        if let Ok(true) = event::poll(REFRESH) {
+            if let Ok(Event::Key(key)) = event::read() {
+                let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc);
+                if key.kind == KeyEventKind::Press && quit {
+                    dashboard.stop.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+    ratatui::restore();
+}
+
+/// Function to draw the dashboard over the whole terminal
+fn draw(frame: &mut Frame, dashboard: &Dashboard, stats: &Stats) {
+    let [header, progress, errors, figures, sparkline, codes] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(4),
+        Constraint::Length(7),
+        Constraint::Min(4),
+    ])
+    .areas(frame.area());
+
+    let stopping = if dashboard.stop.load(Ordering::SeqCst) {
+        "stopping, waiting for the requests in flight"
+    } else {
+        "q to stop"
+    };
+    frame.render_widget(
+        Paragraph::new(format!("loadster: {} ({})", dashboard.url, stopping)),
+        header,
+    );
+
+    let elapsed = stats.start.elapsed();
+    let request_errors = dashboard.request_errors.load(Ordering::Relaxed);
+    let sent = stats.seen as u64 + request_errors;
+    let (ratio, label) = match (dashboard.total_requests, dashboard.max_duration) {
+        (Some(total), _) => (
+            sent as f64 / total.max(1) as f64,
+            format!("{} of {} requests", sent, total),
+        ),
+        (None, Some(max_duration)) => (
+            elapsed.as_secs_f64() / max_duration.as_secs_f64().max(f64::EPSILON),
+            format!("{} of {} s", elapsed.as_secs(), max_duration.as_secs()),
+        ),
+        (None, None) => (0.0, format!("{} requests", sent)),
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title("Progress"))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(ratio.clamp(0.0, 1.0))
+            .label(label),
+        progress,
+    );
+
+    let failed = stats.failed + request_errors;
+    let error_rate = failed as f64 / sent.max(1) as f64;
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title("Error Rate"))
+            .gauge_style(Style::default().fg(Color::Red))
+            .ratio(error_rate.clamp(0.0, 1.0))
+            .label(format!(
+                "{:.2}% ({} failed, {} without a response)",
+                error_rate * 100.0,
+                failed,
+                request_errors
+            )),
+        errors,
+    );
+
+    let latencies = &stats.latencies;
+    let quantile = |q: f64| {
+        if latencies.is_empty() {
+            0
+        } else {
+            latencies.value_at_quantile(q)
+        }
+    };
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!(
+                "Throughput: {:.2} req/s now, {:.2} req/s overall",
+                stats.rate,
+                stats.seen as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+            )),
+            Line::from(format!(
+                "Latency: P50 {} ms, P95 {} ms, P99 {} ms, max {} ms",
+                quantile(0.50),
+                quantile(0.95),
+                quantile(0.99),
+                latencies.max()
+            )),
+        ])
+        .block(Block::bordered().title("Figures")),
+        figures,
+    );
+
+    // The sparkline shows as many of the latest redraws as fit
+    let width = sparkline.width.saturating_sub(2) as usize;
+    let recent = &stats.p95s[stats.p95s.len().saturating_sub(width)..];
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(format!(
+                "P95 Latency ({} ms now)",
+                recent.last().copied().unwrap_or(0)
+            )))
+            .style(Style::default().fg(Color::Yellow))
+            .data(recent.iter().copied()),
+        sparkline,
+    );
+
+    let rows = stats.codes.iter().map(|(code, count)| {
+        let color = match code / 100 {
+            2 => Color::Green,
+            4 => Color::Yellow,
+            5 => Color::Red,
+            _ => Color::Reset,
+        };
+        Row::new(vec![
+            code.to_string(),
+            count.to_string(),
+            format!("{:.2}%", *count as f64 * 100.0 / stats.seen.max(1) as f64),
+        ])
+        .style(Style::default().fg(color))
+    });
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(6),
+                Constraint::Length(12),
+                Constraint::Length(10),
+            ],
+        )
+        .header(Row::new(vec!["Code", "Count", "Share"]))
+        .block(Block::bordered().title("Response Codes")),
+        codes,
+    );
+}
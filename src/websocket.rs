@@ -0,0 +1,247 @@
+//! WebSocket load testing (`overloader ws`).
+//!
+//! Every user opens one connection and sends `--messages` messages,
+//! `--interval` apart, waiting for a reply to each one. The time from sending
+//! a message until the next text or binary message arrives is its round-trip
+//! latency, so the endpoint has to answer every message, as an echo endpoint
+//! does.
+
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use crate::{percentile, throughput};
+
+/// Command line arguments of the `ws` subcommand
+#[derive(Args, Clone)]
+pub(crate) struct WsArgs {
+    /// The target WebSocket URL (ws:// or wss://)
+    #[clap(short = 'u', long, help = "The target WebSocket URL (ws:// or wss://)")]
+    url: String,
+
+    /// The number of concurrent connections (default: 10)
+    #[clap(
+        short = 'c',
+        long,
+        default_value = "10",
+        help = "The number of concurrent connections (default: 10)"
+    )]
+    users: u32,
+
+    /// The text message to send (default: ping)
+    #[clap(
+        short = 'm',
+        long,
+        default_value = "ping",
+        help = "The text message to send (default: ping)"
+    )]
+    message: String,
+
+    /// The number of messages each connection sends (default: 10)
+    #[clap(
+        short = 'n',
+        long,
+        default_value = "10",
+        help = "The number of messages each connection sends (default: 10)"
+    )]
+    messages: u32,
+
+    /// The time between two messages of a connection in milliseconds (default: 1000)
+    #[clap(
+        long,
+        default_value = "1000",
+        help = "The time between two messages of a connection in milliseconds (default: 1000)"
+    )]
+    interval: u64,
+
+    /// How long to wait for the reply to a message in seconds (default: 30)
+    #[clap(
+        short = 't',
+        long,
+        default_value = "30",
+        help = "How long to wait for the reply to a message in seconds (default: 30)"
+    )]
+    timeout: u64,
+
+    /// Enable verbose output
+    #[clap(short = 'v', long, help = "Enable verbose output")]
+    verbose: bool,
+}
+
+/// Struct to hold what a single connection measured
+struct ConnectionResult {
+    connect_time: Option<u64>, // Time to open the connection and finish the handshake, in milliseconds
+    round_trips: Vec<u64>,     // Round-trip latency of every answered message, in milliseconds
+    error: Option<String>,     // Why the connection failed or stopped early
+}
+
+/// Function to run the WebSocket load test and print its report
+pub(crate) fn run(args: &WsArgs, precision: usize) {
+    let results = Arc::new(Mutex::new(vec![]));
+    let run_start = Instant::now();
+    let mut handles = vec![];
+    for i in 0..args.users {
+        let args = args.clone();
+        let results = Arc::clone(&results);
+        let worker = thread::Builder::new().name(format!("loadster-ws-{}", i));
+        let handle = worker.spawn(move || {
+            let result = run_connection(i, &args);
+            if let Some(e) = &result.error {
+                eprintln!("Connection {} failed: {}", i, e);
+            }
+            results.lock().unwrap().push((i, result));
+        });
+        handles.push(handle.expect("the system can start another thread"));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = run_start.elapsed();
+
+    let mut results = Arc::try_unwrap(results)
+        .ok()
+        .expect("all connections have finished")
+        .into_inner()
+        .unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    display_report(&args.url, &results, elapsed, precision);
+}
+
+/// Function to open one connection and exchange its messages
+fn run_connection(i: u32, args: &WsArgs) -> ConnectionResult {
+    let timeout = Duration::from_secs(args.timeout);
+    let start = Instant::now();
+    let mut socket = match tungstenite::connect(args.url.as_str()) {
+        Ok((socket, _)) => socket,
+        Err(e) => {
+            return ConnectionResult {
+                connect_time: None,
+                round_trips: vec![],
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    let connect_time = start.elapsed().as_millis() as u64;
+    if args.verbose {
+        println!("i: {} ,Connected in {} ms", i, connect_time);
+    }
+    set_read_timeout(&socket, timeout);
+
+    let mut round_trips = vec![];
+    let mut error = None;
+    for n in 0..args.messages {
+        if n > 0 {
+            thread::sleep(Duration::from_millis(args.interval));
+        }
+        let sent = Instant::now();
+        if let Err(e) = socket.send(Message::text(args.message.clone())) {
+            error = Some(e.to_string());
+            break;
+        }
+        match read_reply(&mut socket) {
+            Ok(()) => {
+                let time = sent.elapsed().as_millis() as u64;
+                if args.verbose {
+                    println!("i: {} ,Message {} answered in {} ms", i, n, time);
+                }
+                round_trips.push(time);
+            }
+            Err(e) => {
+                error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    let _ = socket.close(None);
+    ConnectionResult {
+        connect_time: Some(connect_time),
+        round_trips,
+        error,
+    }
+}
+
+/// Function to wait for the next data message, skipping control frames
+fn read_reply(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> tungstenite::Result<()> {
+    loop {
+        match socket.read()? {
+            Message::Text(_) | Message::Binary(_) => return Ok(()),
+            Message::Close(_) => return Err(tungstenite::Error::ConnectionClosed),
+            _ => continue,
+        }
+    }
+}
+
+/// Function to bound how long a read on the connection may block
+fn set_read_timeout(socket: &WebSocket<MaybeTlsStream<TcpStream>>, timeout: Duration) {
+    let stream = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream,
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref(),
+        _ => return,
+    };
+    let _ = stream.set_read_timeout(Some(timeout));
+}
+
+/// Function to display the report of a WebSocket run
+fn display_report(
+    url: &str,
+    results: &[(u32, ConnectionResult)],
+    elapsed: Duration,
+    precision: usize,
+) {
+    let failed = results.iter().filter(|(_, r)| r.error.is_some()).count();
+    let mut connect_times: Vec<u64> = results.iter().filter_map(|(_, r)| r.connect_time).collect();
+    connect_times.sort_unstable();
+    let mut round_trips: Vec<u64> = results
+        .iter()
+        .flat_map(|(_, r)| r.round_trips.iter().copied())
+        .collect();
+    round_trips.sort_unstable();
+    let avg = round_trips.iter().sum::<u64>() as f64 / round_trips.len().max(1) as f64;
+
+    println!("\nWebSocket Load Test Report");
+    println!("Summary");
+    println!("Metric\tValue");
+    println!("Target URL\t{}", url);
+    println!("Connections\t{}", results.len());
+    println!("Failed Connections\t{}", failed);
+    println!("Messages Answered\t{}", round_trips.len());
+    println!("Duration\t{:.*} seconds", precision, elapsed.as_secs_f64());
+    println!(
+        "Message Throughput\t{:.*} msg/s",
+        precision,
+        throughput(round_trips.len(), elapsed)
+    );
+    println!("Avg Round Trip\t{:.*} ms", precision, avg);
+
+    println!("\nLatency Distribution");
+    println!("Percentile\tConnect (ms)\tRound Trip (ms)");
+    for (name, fraction) in [("P50", 0.50), ("P95", 0.95), ("P99", 0.99), ("Max", 1.0)] {
+        println!(
+            "{}\t{}\t{}",
+            name,
+            percentile(&connect_times, fraction),
+            percentile(&round_trips, fraction)
+        );
+    }
+
+    println!("\nPer-Connection Round Trips");
+    println!("Connection\tMessages\tP50\tP95\tP99\tError");
+    for (i, result) in results {
+        let mut times = result.round_trips.clone();
+        times.sort_unstable();
+        println!(
+            "{}\t{}\t{} ms\t{} ms\t{} ms\t{}",
+            i,
+            times.len(),
+            percentile(&times, 0.50),
+            percentile(&times, 0.95),
+            percentile(&times, 0.99),
+            result.error.as_deref().unwrap_or("-")
+        );
+    }
+}